@@ -1,21 +1,172 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
-use arc_swap::ArcSwap;
-use futures::{Sink, Stream, StreamExt};
-use slv_proto::IndexMethod;
+use arc_swap::{ArcSwap, ArcSwapOption};
+use futures::channel::{mpsc, oneshot};
+use futures::lock::Mutex;
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use slv_proto::server::SourceState;
+use slv_proto::{Anchor, Direction, Entry, IndexMethod, IndexRef, MessageId};
 use tokio::sync::broadcast;
 
 pub struct State<Tx: Sink<slv_proto::client::Message> + Unpin> {
-    tx:       Tx,
-    key_list: ArcSwap<Vec<IndexMethod>>,
+    tx:                   Mutex<Tx>,
+    key_list:             ArcSwap<Vec<IndexMethod>>,
+    status:               ArcSwapOption<slv_proto::server::StatusFeed>,
+    next_request_id:      AtomicU64,
+    pending_entries: parking_lot::Mutex<HashMap<u64, oneshot::Sender<slv_proto::server::Entries>>>,
+    pending_key_stats:
+        parking_lot::Mutex<HashMap<u64, oneshot::Sender<slv_proto::server::KeyStats>>>,
+    next_subscription_id: AtomicU64,
+    subscriptions: parking_lot::Mutex<HashMap<u64, mpsc::UnboundedSender<SubscriptionEvent>>>,
 }
 
 impl<Tx: Sink<slv_proto::client::Message> + Unpin> State<Tx> {
-    pub fn new(tx: Tx) -> Self { Self { tx, key_list: ArcSwap::default() } }
+    pub fn new(tx: Tx) -> Self {
+        Self {
+            tx:                   Mutex::new(tx),
+            key_list:             ArcSwap::default(),
+            status:               ArcSwapOption::empty(),
+            next_request_id:      AtomicU64::new(0),
+            pending_entries:      parking_lot::Mutex::default(),
+            pending_key_stats:    parking_lot::Mutex::default(),
+            next_subscription_id: AtomicU64::new(0),
+            subscriptions:        parking_lot::Mutex::default(),
+        }
+    }
+
+    async fn send(&self, message: slv_proto::client::Message) -> Result<(), Tx::Error> {
+        let mut tx = self.tx.lock().await;
+        tx.send(message).await
+    }
+
+    /// Requests the server to create an index for `method`.
+    pub async fn create_index(&self, method: IndexMethod) -> Result<(), Tx::Error> {
+        self.send(slv_proto::client::Message::CreateIndex(method)).await
+    }
+
+    /// Requests the server to drop the index for `method`.
+    pub async fn drop_index(&self, method: IndexMethod) -> Result<(), Tx::Error> {
+        self.send(slv_proto::client::Message::DropIndex(method)).await
+    }
+
+    /// Fetches a page of entries from the server and waits for the response.
+    pub async fn fetch_entries(
+        &self,
+        index: IndexRef,
+        anchor: Anchor,
+        direction: Direction,
+        limit: usize,
+    ) -> Result<slv_proto::server::Entries, RequestError<Tx::Error>> {
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let (response_tx, response_rx) = oneshot::channel();
+        self.pending_entries.lock().insert(request_id, response_tx);
+
+        let request =
+            slv_proto::client::FetchEntries { request_id, index, anchor, direction, limit };
+        if let Err(err) = self.send(slv_proto::client::Message::FetchEntries(request)).await {
+            self.pending_entries.lock().remove(&request_id);
+            return Err(RequestError::Send(err));
+        }
+
+        response_rx.await.map_err(|_| RequestError::Closed)
+    }
+
+    /// Fetches the statistics of the keys in the buffer and waits for the response.
+    pub async fn key_stats(
+        &self,
+        top_values: usize,
+    ) -> Result<slv_proto::server::KeyStats, RequestError<Tx::Error>> {
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let (response_tx, response_rx) = oneshot::channel();
+        self.pending_key_stats.lock().insert(request_id, response_tx);
+
+        let request = slv_proto::client::KeyStats { request_id, top_values };
+        if let Err(err) = self.send(slv_proto::client::Message::KeyStats(request)).await {
+            self.pending_key_stats.lock().remove(&request_id);
+            return Err(RequestError::Send(err));
+        }
+
+        response_rx.await.map_err(|_| RequestError::Closed)
+    }
+
+    /// Subscribes to new and evicted entries in `index`.
+    ///
+    /// Returns the subscription ID and the stream of events.
+    pub async fn subscribe(
+        &self,
+        index: IndexRef,
+        include_entries: bool,
+    ) -> Result<(u64, mpsc::UnboundedReceiver<SubscriptionEvent>), Tx::Error> {
+        let subscription_id = self.next_subscription_id.fetch_add(1, Ordering::Relaxed);
+        let (event_tx, event_rx) = mpsc::unbounded();
+        self.subscriptions.lock().insert(subscription_id, event_tx);
+
+        let request = slv_proto::client::Subscribe { subscription_id, index, include_entries };
+        if let Err(err) = self.send(slv_proto::client::Message::Subscribe(request)).await {
+            self.subscriptions.lock().remove(&subscription_id);
+            return Err(err);
+        }
+
+        Ok((subscription_id, event_rx))
+    }
+
+    /// Cancels a subscription created by `subscribe`.
+    pub async fn unsubscribe(&self, subscription_id: u64) -> Result<(), Tx::Error> {
+        self.subscriptions.lock().remove(&subscription_id);
+        let request = slv_proto::client::Unsubscribe { subscription_id };
+        self.send(slv_proto::client::Message::Unsubscribe(request)).await
+    }
+
+    /// The indices that exist on the server.
+    pub fn key_list(&self) -> Arc<Vec<IndexMethod>> { self.key_list.load_full() }
+
+    /// The number of inputs of the buffer, or 0 before the first status is received.
+    pub fn source_count(&self) -> usize {
+        self.status.load().as_ref().map_or(0, |status| status.sources.len())
+    }
 
     pub fn status_line(&self) -> String {
         let key_list = self.key_list.load();
-        format!("{} keys", key_list.len())
+        let mut line = format!("{} keys", key_list.len());
+
+        if let Some(status) = &*self.status.load() {
+            line.push_str(&format!(
+                " | {} entries, {}",
+                status.buffer_entries,
+                format_bytes(status.buffer_memory)
+            ));
+            if let Some(limit) = status.buffer_memory_limit {
+                line.push_str(&format!(" / {}", format_bytes(limit)));
+            }
+            if status.spill_entries > 0 {
+                line.push_str(&format!(
+                    " (+{} on disk, {})",
+                    status.spill_entries,
+                    format_bytes(status.spill_size)
+                ));
+            }
+
+            if status.sources.len() > 1 {
+                line.push_str(&format!(" | {} sources", status.sources.len()));
+            }
+            // directories may contain many archives that were read to their end
+            let ended: Vec<_> =
+                status.sources.iter().filter(|source| source.state == SourceState::Ended).collect();
+            match &ended[..] {
+                [] => {}
+                [source] => line.push_str(&format!(" | {} ended", source.label)),
+                ended => line.push_str(&format!(" | {} ended", ended.len())),
+            }
+            for source in &status.sources {
+                if let SourceState::Failed(err) = &source.state {
+                    line.push_str(&format!(" | {}: {err}", source.label));
+                }
+            }
+        }
+
+        line
     }
 }
 
@@ -52,6 +203,81 @@ async fn handle_message<Tx: Sink<slv_proto::client::Message> + Unpin>(
             let list = Arc::new(list);
             state.key_list.store(Arc::clone(&list));
         }
-        slv_proto::server::Message::StatusFeed(_) => todo!(),
+        slv_proto::server::Message::StatusFeed(status) => {
+            state.status.store(Some(Arc::new(status)));
+        }
+        slv_proto::server::Message::Entries(entries) => {
+            let response_tx = state.pending_entries.lock().remove(&entries.request_id);
+            match response_tx {
+                Some(response_tx) => _ = response_tx.send(entries), // requester may have given up
+                None => log::warn!("Received response for unknown request {}", entries.request_id),
+            }
+        }
+        slv_proto::server::Message::KeyStats(stats) => {
+            let response_tx = state.pending_key_stats.lock().remove(&stats.request_id);
+            match response_tx {
+                Some(response_tx) => _ = response_tx.send(stats), // requester may have given up
+                None => log::warn!("Received response for unknown request {}", stats.request_id),
+            }
+        }
+        slv_proto::server::Message::Appended(appended) => {
+            let event = SubscriptionEvent::Appended(appended.entries);
+            send_subscription_event(state, appended.subscription_id, event);
+        }
+        slv_proto::server::Message::Evicted(evicted) => {
+            let event = SubscriptionEvent::Evicted(evicted.until);
+            send_subscription_event(state, evicted.subscription_id, event);
+        }
+    }
+}
+
+fn send_subscription_event<Tx: Sink<slv_proto::client::Message> + Unpin>(
+    state: &State<Tx>,
+    subscription_id: u64,
+    event: SubscriptionEvent,
+) {
+    let mut subscriptions = state.subscriptions.lock();
+    if let Some(event_tx) = subscriptions.get(&subscription_id) {
+        if event_tx.unbounded_send(event).is_err() {
+            // the receiver was dropped without unsubscribing
+            subscriptions.remove(&subscription_id);
+        }
+    }
+}
+
+/// Formats a size in bytes with a binary unit, such as `12.3 MiB`.
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} B");
     }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for next in &UNITS[1..] {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    format!("{size:.1} {unit}")
+}
+
+/// An event in a subscribed index.
+pub enum SubscriptionEvent {
+    /// New entries in ascending order of ID.
+    ///
+    /// Entries are only included if requested in the subscription.
+    Appended(Vec<(MessageId, Option<Entry>)>),
+    /// All entries with an ID less than or equal to this ID have been evicted.
+    Evicted(MessageId),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RequestError<E> {
+    #[error("Cannot send request: {0}")]
+    Send(E),
+    #[error("Connection closed before a response was received")]
+    Closed,
 }
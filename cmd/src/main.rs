@@ -1,4 +1,5 @@
 use std::future::Future;
+use std::path::PathBuf;
 use std::pin::Pin;
 use std::process::ExitCode;
 use std::sync::Arc;
@@ -19,19 +20,33 @@ async fn main() -> ExitCode {
 }
 
 async fn run() -> Result<(), Error> {
-    let options = Options::parse();
+    let mut options = Options::parse();
 
     let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
 
     let mut inits: Vec<Pin<Box<dyn Future<Output = ()> + Send>>> = Vec::new();
 
     let implicit_noninteractive = options.interactive && atty::isnt(atty::Stream::Stdout);
-    let read_from_stdin = options.input.source.input.as_os_str() == "-";
+    let interactive = options.interactive && !implicit_noninteractive;
+    if !interactive && options.tui.filter.is_some() {
+        return Err(Error::FilterNonInteractive);
+    }
+    let mut read_from_stdin = options.input.source.input.iter().any(|path| path.as_os_str() == "-");
 
-    let (index, input) = slv_input::init(options.input, shutdown_rx.resubscribe()).await?;
-    inits.push(Box::pin(input));
+    let index = match options.command.take() {
+        Some(Command::Save(save)) => return save_snapshot(save).await,
+        Some(Command::Open(open)) => {
+            read_from_stdin = false;
+            slv_input::open(options.input.index, &open.file)?
+        }
+        None => {
+            let (index, input) = slv_input::init(options.input, shutdown_rx.resubscribe()).await?;
+            inits.push(Box::pin(input));
+            index
+        }
+    };
 
-    if options.interactive && !implicit_noninteractive {
+    if interactive {
         if let Ok(path) = env::var("RUST_LOG_FILE") {
             let pipe = fs::File::create(path).map_err(Error::LogFileOpen)?;
             simplelog::WriteLogger::init(
@@ -43,8 +58,13 @@ async fn run() -> Result<(), Error> {
         }
 
         inits.push(Box::pin(
-            slv_tui::init(Arc::clone(&index), shutdown_tx.clone(), shutdown_rx.resubscribe())
-                .await?,
+            slv_tui::init(
+                options.tui,
+                Arc::clone(&index),
+                shutdown_tx.clone(),
+                shutdown_rx.resubscribe(),
+            )
+            .await?,
         ));
     } else {
         env_logger::init();
@@ -82,9 +102,34 @@ async fn run() -> Result<(), Error> {
     Ok(())
 }
 
+/// Reads the input until its end and saves it as a snapshot.
+async fn save_snapshot(mut options: SaveOptions) -> Result<(), Error> {
+    env_logger::init();
+
+    options.input.source.stop_at_eof = true;
+    let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+    let (index, input) = slv_input::init(options.input, shutdown_rx).await?;
+
+    tokio::spawn(async move {
+        if let Err(err) = signal::ctrl_c().await {
+            log::error!("Cannot register ctrl-c handler: {err}");
+        }
+        // save the messages read so far
+        _ = shutdown_tx.send(());
+    });
+    input.await;
+
+    let len = index.save(&options.file)?;
+    eprintln!("Saved {len} messages to {}", options.file.display());
+    Ok(())
+}
+
 #[derive(Parser)]
 #[clap(name = "slv", version, author, about)]
 pub struct Options {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     #[clap(flatten)]
     pub input: slv_input::Options,
 
@@ -94,6 +139,9 @@ pub struct Options {
     #[clap(flatten)]
     pub server:        slv_server::Options,
 
+    #[clap(flatten)]
+    pub tui: slv_tui::Options,
+
     /// Do not start interactive UI.
     ///
     /// Interactive mode is automatically disabled if the standard output is not a terminal.
@@ -104,14 +152,45 @@ pub struct Options {
     pub interactive: bool,
 }
 
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Read log files until their end and save them as a snapshot to view with `slv open`.
+    Save(SaveOptions),
+    /// View a snapshot saved by `slv save` or from the interactive UI.
+    Open(OpenOptions),
+}
+
+#[derive(clap::Args)]
+struct SaveOptions {
+    /// Path to write the snapshot to.
+    #[clap(value_parser)]
+    file:  PathBuf,
+    #[clap(flatten)]
+    input: slv_input::Options,
+}
+
+#[derive(clap::Args)]
+struct OpenOptions {
+    /// Path to a snapshot file.
+    #[clap(value_parser)]
+    file: PathBuf,
+}
+
 #[derive(Debug, thiserror::Error)]
 enum Error {
     #[error("Cannot open target log file for slv")]
     LogFileOpen(io::Error),
     #[error("Error starting input source: {0}")]
     Input(#[from] slv_input::InitError),
+    #[error("Snapshot error: {0}")]
+    Snapshot(#[from] slv_input::index::SnapshotError),
     #[error("Error starting server: {0}")]
     Server(#[from] slv_server::InitError),
     #[error("{0}")]
     Tui(#[from] slv_tui::InitError),
+    #[error(
+        "--filter only applies to the interactive UI, which is disabled by --non-interactive or \
+         when stdout is not a terminal"
+    )]
+    FilterNonInteractive,
 }
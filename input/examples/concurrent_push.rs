@@ -0,0 +1,151 @@
+//! Measures the throughput and latency of `Store::push`
+//! while other threads read the store like sessions do:
+//! following the newest messages, scrolling through messages spilled to disk,
+//! searching the full-text index, requesting key statistics and creating filter indices.
+//!
+//! On a machine with fewer cores than threads, readers take CPU time from the pushing thread,
+//! so the push rate is best compared with its fair share, i.e. the rate without readers
+//! divided by the number of threads.
+//!
+//! Run with `cargo run --release -p slv-input --example concurrent_push -- [MESSAGES]`.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use arcstr::ArcStr;
+use clap::Parser;
+use slv_input::index::{Options, Store};
+use slv_proto::query::Query;
+use slv_proto::{Anchor, Content, Direction, Entry, IndexRef, JsonEntry, Number, TextQuery, Value};
+
+const READER_COUNTS: &[usize] = &[0, 1, 4, 8];
+const PAGE_SIZE: usize = 100;
+/// Messages beyond this are spilled to disk.
+const BUFFER_SIZE: &str = "100000";
+
+/// The ways that reader threads read the store, assigned to readers in turn.
+#[derive(Clone, Copy)]
+enum Reader {
+    /// Reads the newest page of all messages and the buffer usage.
+    Follow,
+    /// Reads the oldest page of all messages, which are spilled to disk.
+    Scroll,
+    /// Searches the full-text index and requests key statistics.
+    Search,
+    /// Creates a filter index, reads its newest page and drops it.
+    Filter,
+}
+
+const READERS: [Reader; 4] = [Reader::Follow, Reader::Scroll, Reader::Search, Reader::Filter];
+
+fn main() {
+    let messages: usize =
+        std::env::args().nth(1).map_or(500_000, |arg| arg.parse().expect("invalid message count"));
+    let entries: Vec<_> = (0..messages).map(entry).collect();
+
+    println!("readers   push/s  reads/s  p99.9 push  max push");
+    for &readers in READER_COUNTS {
+        let result = run(&entries, readers);
+        println!(
+            "{readers:7}  {:7.0}  {:7.0}  {:10?}  {:8?}",
+            result.push_rate, result.read_rate, result.p999_push, result.max_push
+        );
+    }
+}
+
+struct RunResult {
+    /// Messages pushed per second.
+    push_rate: f64,
+    /// Read operations per second by all readers.
+    read_rate: f64,
+    p999_push: Duration,
+    max_push:  Duration,
+}
+
+/// Pushes all entries into a new store while `readers` threads read it.
+fn run(entries: &[Entry], readers: usize) -> RunResult {
+    let options = ["bench", "--buffer-size", BUFFER_SIZE, "--spill", "--full-text"];
+    let store = Arc::new(Store::new(Options::parse_from(options)).expect("temp dir is writable"));
+
+    let done = Arc::new(AtomicBool::new(false));
+    let reads = Arc::new(AtomicU64::new(0));
+    let handles: Vec<_> = (0..readers)
+        .map(|reader| {
+            let store = Arc::clone(&store);
+            let done = Arc::clone(&done);
+            let reads = Arc::clone(&reads);
+            let reader = READERS[reader % READERS.len()];
+            thread::spawn(move || {
+                while !done.load(Ordering::Relaxed) {
+                    read(&store, reader);
+                    reads.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+        })
+        .collect();
+
+    let mut latencies = Vec::with_capacity(entries.len());
+    let start = Instant::now();
+    for entry in entries {
+        let entry = entry.clone();
+        let push_start = Instant::now();
+        store.push(entry);
+        latencies.push(push_start.elapsed());
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+
+    done.store(true, Ordering::Relaxed);
+    for handle in handles {
+        handle.join().expect("reader panicked");
+    }
+
+    latencies.sort_unstable();
+    RunResult {
+        push_rate: entries.len() as f64 / elapsed,
+        read_rate: reads.load(Ordering::Relaxed) as f64 / elapsed,
+        p999_push: latencies[latencies.len() * 999 / 1000],
+        max_push:  latencies.last().copied().unwrap_or_default(),
+    }
+}
+
+fn read(store: &Store, reader: Reader) {
+    match reader {
+        Reader::Follow => {
+            store.page(&IndexRef::All, Anchor::Newest, Direction::Backward, PAGE_SIZE);
+            store.status();
+        }
+        Reader::Scroll => {
+            store.page(&IndexRef::All, Anchor::Oldest, Direction::Forward, PAGE_SIZE);
+        }
+        Reader::Search => {
+            let query = TextQuery { text: arcstr::literal!("error"), phrase: false };
+            store.page(&IndexRef::Text(query), Anchor::Newest, Direction::Backward, PAGE_SIZE);
+            store.key_stats(5);
+        }
+        Reader::Filter => {
+            let index = "component=db".parse::<Query>().expect("valid query").index_ref();
+            let IndexRef::Method(method) = &index else { unreachable!("the query is not empty") };
+            store.create_index(method.clone());
+            store.page(&index, Anchor::Newest, Direction::Backward, PAGE_SIZE);
+            store.drop_index(method);
+        }
+    }
+}
+
+/// A logrus-style message.
+fn entry(i: usize) -> Entry {
+    const LEVELS: &[&str] = &["info", "info", "info", "warning", "error", "debug"];
+    const COMPONENTS: &[&str] = &["api", "db", "cache", "auth"];
+
+    let string = |string: &str| Value::String(ArcStr::from(string));
+    let content = Content::Json(JsonEntry(vec![
+        (arcstr::literal!("component"), string(COMPONENTS[i % COMPONENTS.len()])),
+        (arcstr::literal!("latency_ms"), Value::Number(Number::UInt((i * 7 % 900) as u64))),
+        (arcstr::literal!("level"), string(LEVELS[i % LEVELS.len()])),
+        (arcstr::literal!("msg"), string("request completed")),
+        (arcstr::literal!("request_id"), string(&format!("{:016x}", i * 2654435761))),
+    ]));
+    Entry { source: arcstr::literal!("bench"), content }
+}
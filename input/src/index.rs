@@ -1,194 +1,778 @@
-use std::cmp;
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::atomic::{self, AtomicU64, AtomicUsize};
 use std::sync::Arc;
+use std::{cmp, io};
 
-use parking_lot::RwLock;
-use slv_proto::{Entry, FieldCondition, IndexMethod, JsonEntry, MessageId};
+use futures::channel::mpsc;
+use parking_lot::{Mutex, RwLock};
+use slv_proto::{server, Anchor, Content, Direction, Entry, IndexMethod, IndexRef, MessageId};
+use tokio::sync::broadcast;
 
+use self::append::AppendQueue;
+use self::buffer::{MessageBuffer, Row};
+use self::deferred::Deferred;
+use self::fulltext::FullText;
+pub use self::snapshot::SnapshotError;
+use self::spill::SharedSpill;
+use self::stats::Stats;
+use crate::interner::Interner;
+use crate::matcher::Matcher;
+use crate::source::Statuses;
+
+mod append;
+mod buffer;
+mod deferred;
+mod fulltext;
+mod snapshot;
+mod spill;
+mod stats;
+
+type IndexMap = HashMap<IndexMethod, IndexEntry>;
+
+/// Number of messages pushed while a new index is backfilled
+/// below which they are scanned while `push` waits, instead of being scanned again before.
+const MAX_LOCKED_SCAN: usize = 1024;
+
+/// An index along with the compiled matcher of its method,
+/// so that regular expressions are not recompiled for every pushed message.
+struct IndexEntry {
+    matcher: Matcher,
+    index:   Arc<Index>,
+    /// Whether the index was created with `create_index`.
+    ///
+    /// Indices created by subscribing to them are dropped along with their last subscriber.
+    pinned:  bool,
+}
+
+/// The messages of an input along with their indices.
+///
+/// Messages are pushed by one thread at a time.
+/// Reading the buffer and the indices does not lock them,
+/// and the state that readers do lock is updated later if they hold the lock,
+/// so that sessions reading messages do not block the input.
 pub struct Store {
-    buffer:    RwLock<MessageBuffer>,
-    raw_index: RwLock<VecDeque<MessageId>>,
-    indices:   RwLock<HashMap<IndexMethod, Arc<RwLock<Index>>>>,
+    buffer:                 MessageBuffer,
+    /// Serializes `push` and holds the state of `buffer` only used while pushing.
+    ///
+    /// Only locked by writers, never by readers.
+    writer:                 Mutex<buffer::Writer>,
+    /// Messages evicted from `buffer`, if enabled with `--spill`.
+    spill:                  Option<SharedSpill>,
+    raw_index:              Index,
+    indices:                RwLock<IndexMap>,
+    /// Statistics of the keys of the messages in `buffer`.
+    stats:                  Deferred<Stats, stats::Update>,
+    /// The full-text index, if enabled with `--full-text`.
+    full_text:              Option<FullText>,
+    index_list_tx:          broadcast::Sender<()>,
+    /// Subscribers of `IndexRef::All`.
+    all_subscribers:        RwLock<Vec<Subscriber>>,
+    /// Number of subscribers in `all_subscribers`,
+    /// checked before locking it in the `push` hot path.
+    all_subscriber_count:   AtomicUsize,
+    /// Number of subscribers that requested entries instead of IDs only,
+    /// checked before cloning each pushed message.
+    entry_subscriber_count: AtomicUsize,
+    next_subscriber_key:    AtomicU64,
+    /// Shared with the input source, which interns the strings of parsed messages.
+    interner:               Arc<Interner>,
+    /// Shared with the input source, which reports the state of each input.
+    sources:                Arc<Statuses>,
 }
 
 impl Store {
-    pub fn new(options: Options) -> Self {
-        Self {
-            buffer:    RwLock::new(MessageBuffer::new(options.buffer_size)),
-            raw_index: Default::default(),
-            indices:   Default::default(),
-        }
+    pub fn new(options: Options) -> io::Result<Self> {
+        let spill = if options.spill || options.spill_dir.is_some() {
+            Some(SharedSpill::new(options.spill_dir, options.spill_size as u64)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            buffer: MessageBuffer::new(options.buffer_size, options.buffer_memory),
+            writer: Mutex::default(),
+            spill,
+            raw_index: Index::new(),
+            indices: Default::default(),
+            stats: Deferred::new(Stats::default()),
+            full_text: options
+                .full_text
+                .then(|| FullText::new(options.full_text_fields, options.full_text_memory)),
+            index_list_tx: broadcast::channel(16).0,
+            all_subscribers: Default::default(),
+            all_subscriber_count: AtomicUsize::new(0),
+            entry_subscriber_count: AtomicUsize::new(0),
+            next_subscriber_key: AtomicU64::new(0),
+            interner: Arc::default(),
+            sources: Arc::default(),
+        })
     }
 
     pub fn push(&self, message: Entry) {
-        let target = self.index_target(&message);
+        let words = self.full_text.as_ref().map(|full_text| full_text.words(&message));
+        let mut writer = self.writer.lock();
+
+        let (target, mut push_result, spill_start) = {
+            // `indices` stays read-locked until the message is in the buffer,
+            // so that `create_index` either sees the message in its backfill scan
+            // or is included in `target`, but never neither.
+            let indices = self.indices.read();
+            let target = index_target(&indices, &message);
 
-        let push_result = {
-            let mut buffer = self.buffer.write();
-            buffer.push(message)
+            // evicted messages are spilled before readers stop seeing them in the buffer,
+            // so that readers always find them either in the buffer or in the spill
+            let mut spill = self.spill.as_ref().map(SharedSpill::writer);
+            let push_result = self.buffer.push(&mut writer, message, |id, message| {
+                if let Some(spill) = &mut spill {
+                    spill.append(id, message);
+                }
+            });
+            (target, push_result, spill.and_then(spill::SpillWriter::finish))
         };
 
-        self.add_to_index(push_result.added, target);
-        if let Some((removed_id, removed_message)) = push_result.removed {
+        let buffer = self.buffer.view();
+        // added after the message is in the buffer, so that the strings shared with
+        // the statistics are still included in the estimated memory of the message
+        let added = match buffer.get(push_result.added) {
+            Some(Row::Entry(Entry { content: Content::Json(json), .. })) => Some(json),
+            _ => None,
+        };
+        match self.stats.try_write() {
+            Some(mut stats) => {
+                if let Some(json) = added {
+                    stats.add(push_result.added, json);
+                }
+                for (removed_id, removed_message) in &push_result.removed {
+                    stats.remove(*removed_id, removed_message);
+                }
+            }
+            None => {
+                if let Some(json) = added {
+                    self.stats.update(stats::Update::Add(push_result.added, json.clone()));
+                }
+                for (removed_id, removed_message) in &push_result.removed {
+                    let update = stats::Update::Remove(*removed_id, removed_message.clone());
+                    self.stats.update(update);
+                }
+            }
+        }
+
+        if self.spill.is_some() {
+            // spilled messages stay in the indices until they are deleted from disk
+            push_result.removed.clear();
+        }
+
+        let notified_message = (self.entry_subscriber_count.load(atomic::Ordering::Acquire) > 0)
+            .then(|| buffer.get(push_result.added).map(|row| row.to_entry()))
+            .flatten();
+
+        self.add_to_index(push_result.added, target, notified_message.as_ref());
+        if let (Some(full_text), Some(words)) = (&self.full_text, words) {
+            full_text.add(push_result.added, words, notified_message.as_ref());
+        }
+        for (removed_id, removed_message) in push_result.removed {
             self.remove_from_index(removed_id, removed_message);
         }
+        if let Some(spill_start) = spill_start {
+            self.remove_before(spill_start);
+        }
     }
 
-    fn index_target(&self, message: &Entry) -> IndexTarget {
-        match message {
-            Entry::Raw(_) => IndexTarget::Raw,
-            Entry::Json(message) => {
-                // indices is only write-locked when a client requests a new index,
-                // which is relatively rare.
-                // little performance impact is expected from read-locking this field.
-                let matched = {
-                    let indices = self.indices.read();
-                    indices
-                        .iter()
-                        .filter(|(method, _)| should_index(method, message))
-                        .map(|(_, list)| Arc::clone(list))
-                        .collect()
-                };
+    /// Removes the messages deleted from disk from all indices.
+    fn remove_before(&self, start: MessageId) {
+        if let Some(full_text) = &self.full_text {
+            full_text.remove_before(start);
+        }
 
-                IndexTarget::Json { matched }
-            }
+        self.raw_index.remove_before(start);
+        let indices: Vec<_> = {
+            let indices = self.indices.read();
+            indices.values().map(|entry| Arc::clone(&entry.index)).collect()
+        };
+        for index in indices {
+            index.remove_before(start);
+        }
+
+        if self.all_subscriber_count.load(atomic::Ordering::Acquire) > 0 {
+            let subscribers = self.all_subscribers.read();
+            notify_evicted(&subscribers, MessageId(start.0 - 1));
         }
     }
 
-    fn add_to_index(&self, id: MessageId, target: IndexTarget) {
+    fn add_to_index(&self, id: MessageId, target: IndexTarget, message: Option<&Entry>) {
         match target {
-            IndexTarget::Raw => {
-                let mut raw_index = self.raw_index.write();
-                raw_index.push_back(id);
-            }
+            IndexTarget::Raw => self.raw_index.add(id, message),
             IndexTarget::Json { matched } => {
                 for index in matched {
-                    let mut index = index.write();
-                    index.add(id);
+                    index.add(id, message);
                 }
             }
         }
+
+        if self.all_subscriber_count.load(atomic::Ordering::Acquire) > 0 {
+            let subscribers = self.all_subscribers.read();
+            notify_appended(&subscribers, id, message);
+        }
     }
 
     fn remove_from_index(&self, id: MessageId, message: Entry) {
-        match self.index_target(&message) {
+        if let Some(full_text) = &self.full_text {
+            full_text.remove(id, full_text.words(&message));
+        }
+
+        let target = {
+            let indices = self.indices.read();
+            index_target(&indices, &message)
+        };
+
+        match target {
             IndexTarget::Raw => {
-                let mut index = self.raw_index.write();
-                assert_eq!(index.get(0), Some(&id), "raw index inconsistency");
-                index.pop_front();
+                let front = self.raw_index.queue.view().first().copied();
+                assert_eq!(front, Some(id), "raw index inconsistency");
+                self.raw_index.remove(id);
             }
             IndexTarget::Json { matched } => {
                 for index in matched {
-                    let mut index = index.write();
                     index.remove(id);
                 }
             }
         }
+
+        if self.all_subscriber_count.load(atomic::Ordering::Acquire) > 0 {
+            let subscribers = self.all_subscribers.read();
+            notify_evicted(&subscribers, id);
+        }
     }
 
     pub fn list_indices(&self) -> Vec<IndexMethod> {
         let indices = self.indices.read();
         indices.keys().cloned().collect()
     }
-}
 
-enum IndexTarget {
-    Raw,
-    Json { matched: Vec<Arc<RwLock<Index>>> },
-}
+    /// Creates an index for `method`, backfilled with the matching messages in the buffer.
+    ///
+    /// Returns `false` if the index already exists,
+    /// in which case it is no longer dropped along with its last subscriber.
+    pub fn create_index(&self, method: IndexMethod) -> bool { self.insert_index(method, true) }
 
-struct MessageBuffer {
-    start_index: MessageId,
-    bound:       usize,
-    deque:       VecDeque<Entry>,
-}
+    fn insert_index(&self, method: IndexMethod, pinned: bool) -> bool {
+        if let Some(entry) = self.indices.write().get_mut(&method) {
+            entry.pinned |= pinned;
+            return false;
+        }
+
+        let matcher = match Matcher::compile(method.condition(), &self.interner) {
+            Ok(matcher) => matcher,
+            Err(err) => {
+                log::warn!("Cannot compile index condition, the index will be empty: {err}");
+                Matcher::never()
+            }
+        };
 
-impl MessageBuffer {
-    fn new(bound: usize) -> Self {
-        Self { start_index: MessageId(0), bound, deque: VecDeque::new() }
+        // the stored messages are scanned without locking `indices`, which `push` waits for,
+        // then scanned again from where the previous scan ended until few new messages are left,
+        // which are scanned while it is locked
+        let index = Index::new();
+        let scan = |from: MessageId| {
+            let buffer = self.buffer.view();
+            if let Some(spill) = &self.spill {
+                spill.for_each(from..buffer.start(), |id, message| {
+                    if matches!(message.content, Content::Json(_)) && matcher.matches(message) {
+                        index.add(id, None);
+                    }
+                });
+            }
+            for (id, row) in buffer.range(from..buffer.end()) {
+                if row.matches(&matcher) {
+                    index.add(id, None);
+                }
+            }
+            buffer.end()
+        };
+
+        let mut scanned = scan(MessageId(0));
+        let mut unscanned = usize::MAX;
+        loop {
+            let pushed = self.buffer.view().end().0 - scanned.0;
+            // pushing may be faster than scanning if the disk is slow
+            if pushed <= MAX_LOCKED_SCAN || pushed >= unscanned {
+                break;
+            }
+            unscanned = pushed;
+            scanned = scan(scanned);
+        }
+
+        {
+            let mut indices = self.indices.write();
+            if let Some(entry) = indices.get_mut(&method) {
+                entry.pinned |= pinned;
+                return false;
+            }
+
+            scan(scanned);
+            // messages removed from the other indices during the scan
+            let start = match &self.spill {
+                Some(spill) => spill.start(),
+                None => self.buffer.view().start(),
+            };
+            index.remove_before(start);
+
+            indices.insert(method, IndexEntry { matcher, index: Arc::new(index), pinned });
+        }
+
+        _ = self.index_list_tx.send(()); // no sessions connected if this fails
+        true
     }
 
-    fn push(&mut self, message: Entry) -> PushResult {
-        assert!(self.deque.len() <= self.bound);
+    /// Drops the index for `method`.
+    ///
+    /// Returns `false` if the index does not exist.
+    pub fn drop_index(&self, method: &IndexMethod) -> bool {
+        let removed = {
+            let mut indices = self.indices.write();
+            indices.remove(method)
+        };
 
-        let removed = if self.deque.len() == self.bound {
-            let removed = self.start_index;
-            self.start_index.0 += 1;
-            let old_message = self.deque.pop_front().expect("bound > 0");
-            Some((removed, old_message))
-        } else {
-            None
+        if removed.is_none() {
+            return false;
+        }
+
+        _ = self.index_list_tx.send(()); // no sessions connected if this fails
+        true
+    }
+
+    /// Returns a copy of the message with the given ID,
+    /// if it is still in the buffer or spilled to disk.
+    pub fn get(&self, id: MessageId) -> Option<Entry> {
+        let (_, message) = self.entries(vec![id]).pop()?;
+        Some(message)
+    }
+
+    /// Returns copies of the messages in `range` that are still in the buffer or on disk.
+    pub fn range(&self, range: Range<MessageId>) -> Vec<(MessageId, Entry)> {
+        let buffer = self.buffer.view();
+        let mut entries = match &self.spill {
+            Some(spill) => {
+                let start = cmp::max(range.start, spill.start());
+                let end = cmp::min(range.end, buffer.start());
+                let ids: Vec<_> = (start.0..end.0).map(MessageId).collect();
+                spill.get_many(&ids)
+            }
+            None => Vec::new(),
+        };
+        entries.extend(buffer.range(range).map(|(id, row)| (id, row.to_entry())));
+        entries
+    }
+
+    /// Returns copies of the messages with the given IDs,
+    /// skipping those that are no longer in the buffer or on disk.
+    ///
+    /// `ids` must be in ascending order.
+    fn entries(&self, ids: Vec<MessageId>) -> Vec<(MessageId, Entry)> {
+        let buffer = self.buffer.view();
+        let spilled = ids.partition_point(|&id| id < buffer.start());
+
+        let mut entries = match &self.spill {
+            Some(spill) => spill.get_many(&ids[..spilled]),
+            None => Vec::new(),
         };
+        entries
+            .extend(ids[spilled..].iter().filter_map(|&id| Some((id, buffer.get(id)?.to_entry()))));
+        entries
+    }
+
+    /// Reads up to `limit` messages from `index`, starting from `anchor` inclusively.
+    ///
+    /// Returns `None` if `index` refers to an index that does not exist.
+    pub fn page(
+        &self,
+        index: &IndexRef,
+        anchor: Anchor,
+        direction: Direction,
+        limit: usize,
+    ) -> Option<Page> {
+        let (ids, next) = match index {
+            IndexRef::All => {
+                let (start, end) = {
+                    let buffer = self.buffer.view();
+                    let end = buffer.end().0;
+                    match &self.spill {
+                        Some(spill) => (spill.start().0, end),
+                        None => (buffer.start().0, end),
+                    }
+                };
+                let len = end - start;
+                let (positions, next) = page_positions(
+                    len,
+                    |id| cmp::min(id.0.saturating_sub(start), len),
+                    anchor,
+                    direction,
+                    limit,
+                );
 
-        let added = MessageId(self.start_index.0 + self.deque.len());
-        self.deque.push_back(message);
+                let ids = (start + positions.start..start + positions.end).map(MessageId).collect();
+                (ids, next.map(|pos| MessageId(start + pos)))
+            }
+            IndexRef::Raw => self.raw_index.page(anchor, direction, limit),
+            IndexRef::Method(method) => {
+                let index = {
+                    let indices = self.indices.read();
+                    Arc::clone(&indices.get(method)?.index)
+                };
+                index.page(anchor, direction, limit)
+            }
+            IndexRef::Text(query) => {
+                let full_text = self.full_text.as_ref()?;
+                let ids = full_text.search(query, |id| self.get(id));
+                page_queue(ids.len(), |position| ids[position], anchor, direction, limit)
+            }
+        };
 
-        PushResult { added, removed }
+        // the index may still contain a message that was just evicted
+        let entries = self.entries(ids);
+        Some(Page { entries, next })
+    }
+
+    /// Subscribes to messages appended to and evicted from `index`.
+    ///
+    /// Notifications are sent to `tx` tagged with `tag`.
+    /// If `include_entries` is true, appended notifications contain a copy of the message.
+    ///
+    /// An `IndexRef::Method` index is created if it does not exist,
+    /// and dropped again when its last subscriber unsubscribes unless `create_index` is called.
+    ///
+    /// Returns `None` if `index` refers to a full-text index that is not enabled.
+    /// The returned subscription must be passed to `unsubscribe` when it is no longer needed.
+    pub fn subscribe(
+        &self,
+        index: IndexRef,
+        tag: u64,
+        include_entries: bool,
+        tx: mpsc::UnboundedSender<Notification>,
+    ) -> Option<Subscription> {
+        let key = self.next_subscriber_key.fetch_add(1, atomic::Ordering::Relaxed);
+        let subscriber = Subscriber { key, tag, include_entries, tx };
+
+        match &index {
+            IndexRef::All => {
+                let mut subscribers = self.all_subscribers.write();
+                subscribers.push(subscriber);
+                self.all_subscriber_count.fetch_add(1, atomic::Ordering::Release);
+            }
+            IndexRef::Raw => self.raw_index.subscribers.write().push(subscriber),
+            IndexRef::Method(method) => loop {
+                {
+                    // `indices` stays locked so that `unsubscribe` does not drop the index
+                    // before the subscriber is added
+                    let indices = self.indices.read();
+                    if let Some(entry) = indices.get(method) {
+                        entry.index.subscribers.write().push(subscriber);
+                        break;
+                    }
+                }
+                self.insert_index(method.clone(), false);
+            },
+            IndexRef::Text(query) => self.full_text.as_ref()?.subscribe(query, subscriber),
+        }
+
+        if include_entries {
+            self.entry_subscriber_count.fetch_add(1, atomic::Ordering::Release);
+        }
+
+        Some(Subscription { key, index, include_entries })
+    }
+
+    /// Stops sending notifications for a subscription.
+    pub fn unsubscribe(&self, subscription: Subscription) {
+        fn remove(subscribers: &mut Vec<Subscriber>, key: u64) -> bool {
+            let len = subscribers.len();
+            subscribers.retain(|subscriber| subscriber.key != key);
+            subscribers.len() < len
+        }
+
+        let removed = match &subscription.index {
+            IndexRef::All => {
+                let mut subscribers = self.all_subscribers.write();
+                let removed = remove(&mut subscribers, subscription.key);
+                if removed {
+                    self.all_subscriber_count.fetch_sub(1, atomic::Ordering::Release);
+                }
+                removed
+            }
+            IndexRef::Raw => remove(&mut self.raw_index.subscribers.write(), subscription.key),
+            IndexRef::Method(method) => {
+                let mut indices = self.indices.write();
+                match indices.get(method) {
+                    Some(entry) => {
+                        let mut subscribers = entry.index.subscribers.write();
+                        let removed = remove(&mut subscribers, subscription.key);
+                        if subscribers.is_empty() && !entry.pinned {
+                            drop(subscribers);
+                            indices.remove(method);
+                            _ = self.index_list_tx.send(()); // no sessions connected if this fails
+                        }
+                        removed
+                    }
+                    None => true, // the index was dropped along with its subscribers
+                }
+            }
+            IndexRef::Text(_) => self
+                .full_text
+                .as_ref()
+                .is_some_and(|full_text| full_text.unsubscribe(subscription.key)),
+        };
+
+        if removed && subscription.include_entries {
+            self.entry_subscriber_count.fetch_sub(1, atomic::Ordering::Release);
+        }
+    }
+
+    /// Returns the current usage of the message buffer.
+    pub fn status(&self) -> server::StatusFeed {
+        let buffer = self.buffer.view();
+        let (spill_entries, spill_size) = match &self.spill {
+            Some(spill) => (buffer.start().0.saturating_sub(spill.start().0) as u64, spill.size()),
+            None => (0, 0),
+        };
+        server::StatusFeed {
+            buffer_entries: buffer.len() as u64,
+            buffer_memory: self.buffer.memory() as u64,
+            buffer_memory_limit: self.buffer.memory_bound().map(|bound| bound as u64),
+            spill_entries,
+            spill_size,
+            sources: self.sources.list(),
+        }
+    }
+
+    /// Returns the statistics of the keys of the JSON messages in the buffer,
+    /// with up to `top_values` most frequent values of each key.
+    ///
+    /// Messages evicted to disk are not included.
+    pub fn key_stats(&self, top_values: usize) -> KeyStats {
+        let stats = self.stats.read();
+        KeyStats { messages: stats.messages(), keys: stats.collect(top_values) }
+    }
+
+    pub(crate) fn interner(&self) -> &Arc<Interner> { &self.interner }
+
+    pub(crate) fn sources(&self) -> &Arc<Statuses> { &self.sources }
+
+    /// Subscribes to changes of the index list.
+    ///
+    /// The receiver is notified after each index creation and removal.
+    pub fn subscribe_index_list(&self) -> broadcast::Receiver<()> { self.index_list_tx.subscribe() }
+}
+
+/// A page of messages read from an index.
+pub struct Page {
+    /// The messages in the page, in ascending order of ID.
+    pub entries: Vec<(MessageId, Entry)>,
+    /// The anchor to read the next page in the same direction,
+    /// or `None` if the end of the index is reached.
+    pub next:    Option<MessageId>,
+}
+
+/// Statistics of the keys of the JSON messages in the buffer.
+pub struct KeyStats {
+    /// Number of JSON messages in the buffer.
+    pub messages: u64,
+    /// The keys in ascending order of their dotted paths.
+    pub keys:     Vec<server::KeyStat>,
+}
+
+/// Pages through a sorted list of `len` IDs, where `get(position)` returns the ID at a position.
+fn page_queue(
+    len: usize,
+    get: impl Fn(usize) -> MessageId,
+    anchor: Anchor,
+    direction: Direction,
+    limit: usize,
+) -> (Vec<MessageId>, Option<MessageId>) {
+    let position = |id| {
+        let (mut low, mut high) = (0, len);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if get(mid) < id {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    };
+    let (positions, next) = page_positions(len, position, anchor, direction, limit);
+    (positions.map(&get).collect(), next.map(&get))
+}
+
+/// Computes the positions of a page in a sorted list of `len` IDs,
+/// where `position(id)` is the number of IDs in the list less than `id`.
+///
+/// Returns the page and the position to start the next page from.
+fn page_positions(
+    len: usize,
+    position: impl Fn(MessageId) -> usize,
+    anchor: Anchor,
+    direction: Direction,
+    limit: usize,
+) -> (Range<usize>, Option<usize>) {
+    match direction {
+        Direction::Forward => {
+            let start = match anchor {
+                Anchor::Oldest => 0,
+                Anchor::Newest => len.saturating_sub(1),
+                Anchor::Id(id) => position(id),
+            };
+            let end = cmp::min(start.saturating_add(limit), len);
+            (start..end, (end < len).then_some(end))
+        }
+        Direction::Backward => {
+            let end = match anchor {
+                Anchor::Oldest => cmp::min(1, len),
+                Anchor::Newest => len,
+                Anchor::Id(id) => position(MessageId(id.0 + 1)),
+            };
+            let start = end.saturating_sub(limit);
+            (start..end, start.checked_sub(1))
+        }
+    }
+}
+
+fn index_target(indices: &IndexMap, message: &Entry) -> IndexTarget {
+    match message.content {
+        Content::Raw(_) => IndexTarget::Raw,
+        Content::Json(_) => {
+            // indices is only write-locked when a client requests a new index,
+            // which is relatively rare.
+            // little performance impact is expected from read-locking this field.
+            let matched = indices
+                .values()
+                .filter(|entry| entry.matcher.matches(message))
+                .map(|entry| Arc::clone(&entry.index))
+                .collect();
+
+            IndexTarget::Json { matched }
+        }
     }
 }
 
-struct PushResult {
-    added:   MessageId,
-    removed: Option<(MessageId, Entry)>,
+enum IndexTarget {
+    Raw,
+    Json { matched: Vec<Arc<Index>> },
 }
 
+/// The IDs of the messages matched by an index.
+///
+/// Messages are only added and removed by the holder of `Store::writer`,
+/// or before the index is inserted into `Store::indices`.
 struct Index {
-    queue: VecDeque<MessageId>,
+    /// The matched IDs in ascending order.
+    queue:       AppendQueue<MessageId>,
+    subscribers: RwLock<Vec<Subscriber>>,
 }
 
 impl Index {
-    fn add(&mut self, id: MessageId) {
-        if let Some(&last) = self.queue.back() {
+    fn new() -> Self { Self { queue: AppendQueue::new(), subscribers: RwLock::default() } }
+
+    fn add(&self, id: MessageId, message: Option<&Entry>) {
+        if let Some(last) = self.queue.last() {
             assert!(last < id);
         }
-        self.queue.push_back(id);
+        self.queue.push(id);
+
+        notify_appended(&self.subscribers.read(), id, message);
+    }
+
+    /// Removes all messages before `start`, which were deleted from disk.
+    fn remove_before(&self, start: MessageId) {
+        let queue = self.queue.view();
+        let end = queue.partition_point(|&id| id < start);
+        if end > queue.start() {
+            let last = *queue.get(end - 1).expect("position is in view");
+            self.queue.remove_before(end);
+            notify_evicted(&self.subscribers.read(), last);
+        }
     }
 
-    fn remove(&mut self, id: MessageId) {
-        match self.queue.front() {
+    fn remove(&self, id: MessageId) {
+        let queue = self.queue.view();
+        match queue.first() {
             // index did not exist when id was created
             Some(&front) if front > id => {}
             None => {}
 
             Some(&front) => {
                 assert!(front == id, "index contains obsolete message");
-                self.queue.pop_front();
+                self.queue.remove_before(queue.start() + 1);
+
+                notify_evicted(&self.subscribers.read(), id);
             }
         }
     }
+
+    fn page(
+        &self,
+        anchor: Anchor,
+        direction: Direction,
+        limit: usize,
+    ) -> (Vec<MessageId>, Option<MessageId>) {
+        let queue = self.queue.view();
+        let get = |position| *queue.get(queue.start() + position).expect("position is in view");
+        page_queue(queue.len(), get, anchor, direction, limit)
+    }
+}
+
+/// A handle to remove a subscription created by `Store::subscribe`.
+pub struct Subscription {
+    key:             u64,
+    index:           IndexRef,
+    include_entries: bool,
 }
 
-fn should_index(method: &IndexMethod, message: &JsonEntry) -> bool {
-    let mut fields = &message.0[..];
-    for unit in &*method.conditions {
-        let unit_key = unit.key();
+impl Subscription {
+    pub fn include_entries(&self) -> bool { self.include_entries }
+}
 
-        loop {
-            // loop over all fields in the message in order
-            let (field_key, field_value) = match fields.split_first() {
-                Some((first, rest)) => {
-                    fields = rest;
-                    first
-                }
-                None => return false, // no entries named `unit_key`
-            };
+struct Subscriber {
+    key:             u64,
+    tag:             u64,
+    include_entries: bool,
+    tx:              mpsc::UnboundedSender<Notification>,
+}
 
-            match field_key.as_str().cmp(unit_key) {
-                cmp::Ordering::Less => continue, // this field is not in the index key
-                cmp::Ordering::Greater => return false, // no entries named `unit_key`
-                cmp::Ordering::Equal => match unit {
-                    FieldCondition::HasKey(_) => {}
-                    FieldCondition::KeyValue(_, value) => {
-                        if field_value != value {
-                            return false;
-                        }
-                    }
-                },
-            }
-        }
+/// A change in a subscribed index.
+pub struct Notification {
+    /// The `tag` passed to `Store::subscribe`.
+    pub tag:   u64,
+    pub event: NotificationEvent,
+}
+
+pub enum NotificationEvent {
+    /// A message was appended to the index.
+    ///
+    /// The message is only included if the subscription requested entries.
+    Appended(MessageId, Option<Entry>),
+    /// A message was evicted from the index,
+    /// along with all older messages in the index.
+    Evicted(MessageId),
+}
+
+fn notify_appended(subscribers: &[Subscriber], id: MessageId, message: Option<&Entry>) {
+    for subscriber in subscribers {
+        let message = if subscriber.include_entries { message.cloned() } else { None };
+        let event = NotificationEvent::Appended(id, message);
+        // the session unsubscribes when it is closed
+        _ = subscriber.tx.unbounded_send(Notification { tag: subscriber.tag, event });
     }
+}
 
-    true
+fn notify_evicted(subscribers: &[Subscriber], id: MessageId) {
+    for subscriber in subscribers {
+        let event = NotificationEvent::Evicted(id);
+        // the session unsubscribes when it is closed
+        _ = subscriber.tx.unbounded_send(Notification { tag: subscriber.tag, event });
+    }
 }
 
 #[derive(clap::Parser)]
@@ -197,5 +781,166 @@ pub struct Options {
     ///
     /// The oldest messages that exceed the buffer are discarded.
     #[clap(long, value_parser, default_value = "1000000")]
-    pub buffer_size: usize,
+    pub buffer_size:   usize,
+    /// Maximum memory used by buffered messages, such as `512MiB`.
+    ///
+    /// The oldest messages are discarded until the buffer fits,
+    /// in addition to the `--buffer-size` limit.
+    /// Memory usage is estimated from the size of each message.
+    /// Messages are stored and discarded in blocks of 4096,
+    /// so the newest block is kept even if it alone exceeds the limit.
+    #[clap(long, value_parser = parse_byte_size)]
+    pub buffer_memory: Option<usize>,
+
+    /// Write messages discarded from the buffer to segment files on disk,
+    /// so that they can still be viewed.
+    ///
+    /// Segment files are stored in a directory created in the system temp directory
+    /// or in `--spill-dir`, which is removed on exit.
+    #[clap(long, action)]
+    pub spill:      bool,
+    /// The directory to create the directory of segment files in. Implies `--spill`.
+    #[clap(long, value_parser)]
+    pub spill_dir:  Option<PathBuf>,
+    /// Maximum total size of segment files, such as `1GiB`.
+    ///
+    /// The oldest segment files are deleted when this limit is exceeded.
+    #[clap(long, value_parser = parse_byte_size, default_value = "1GiB")]
+    pub spill_size: usize,
+
+    /// Build a full-text index over raw lines and JSON message fields.
+    #[clap(long, action)]
+    pub full_text:        bool,
+    /// A JSON field to include in the full-text index, as a dotted key path.
+    ///
+    /// Can be specified multiple times.
+    #[clap(long = "full-text-field", value_parser, default_values = &["msg", "message"])]
+    pub full_text_fields: Vec<String>,
+    /// Maximum memory used by the full-text index, such as `64MiB`.
+    ///
+    /// The oldest messages are dropped from the full-text index when it exceeds this limit,
+    /// but remain in the buffer.
+    #[clap(long, value_parser = parse_byte_size, default_value = "64MiB")]
+    pub full_text_memory: usize,
+}
+
+/// Parses a size in bytes with an optional unit, such as `512`, `100KB` or `64MiB`.
+fn parse_byte_size(input: &str) -> Result<usize, String> {
+    let input = input.trim();
+    let split = input.find(|ch: char| !ch.is_ascii_digit() && ch != '.').unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("invalid size {input:?}"))?;
+    let unit = unit.trim();
+    let multiplier: u64 = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "kib" => 1 << 10,
+        "m" | "mb" => 1_000_000,
+        "mib" => 1 << 20,
+        "g" | "gb" => 1_000_000_000,
+        "gib" => 1 << 30,
+        _ => return Err(format!("unknown size unit {unit:?}")),
+    };
+    Ok((number * multiplier as f64) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser as _;
+    use slv_proto::query::Query;
+    use slv_proto::{JsonEntry, Value};
+
+    use super::*;
+
+    fn store() -> Store { Store::new(Options::parse_from(["slv"])).expect("spill is disabled") }
+
+    fn json(store: &Store, level: &str) -> Entry {
+        let fields = vec![("level".into(), Value::String(level.into()))];
+        store
+            .interner
+            .entry(Entry { source: "test".into(), content: Content::Json(JsonEntry(fields)) })
+    }
+
+    fn method(query: &str) -> IndexMethod {
+        match query.parse::<Query>().expect("valid query").index_ref() {
+            IndexRef::Method(method) => method,
+            _ => panic!("{query} is not an index method"),
+        }
+    }
+
+    /// The queries of the indices in `store`, sorted.
+    fn indices(store: &Store) -> Vec<String> {
+        let mut queries: Vec<_> =
+            store.list_indices().iter().map(|method| method.condition().to_string()).collect();
+        queries.sort();
+        queries
+    }
+
+    fn subscribe(store: &Store, method: &IndexMethod) -> Subscription {
+        let index = IndexRef::Method(method.clone());
+        store.subscribe(index, 0, false, mpsc::unbounded().0).expect("not a full-text index")
+    }
+
+    #[test]
+    fn subscribed_index_is_dropped_with_last_subscriber() {
+        let store = store();
+        let method = method("level=error");
+
+        let first = subscribe(&store, &method);
+        let second = subscribe(&store, &method);
+        assert_eq!(indices(&store), ["level=error"]);
+
+        store.unsubscribe(first);
+        assert_eq!(indices(&store), ["level=error"]);
+        store.unsubscribe(second);
+        assert!(indices(&store).is_empty());
+    }
+
+    #[test]
+    fn created_index_is_kept_without_subscribers() {
+        let store = store();
+        let created = method("level=error");
+        let subscribed = method("level=warn");
+
+        assert!(store.create_index(created.clone()));
+        store.unsubscribe(subscribe(&store, &created));
+
+        let subscription = subscribe(&store, &subscribed);
+        assert!(!store.create_index(subscribed.clone()));
+        store.unsubscribe(subscription);
+
+        assert_eq!(indices(&store), ["level=error", "level=warn"]);
+    }
+
+    #[test]
+    fn created_index_includes_spilled_messages() {
+        let options = Options::parse_from(["slv", "--buffer-size", "10", "--spill"]);
+        let store = Store::new(options).expect("temp dir is writable");
+        for n in 0..30 {
+            store.push(json(&store, if n % 3 == 0 { "error" } else { "info" }));
+        }
+
+        let errors = method("level=error");
+        assert!(store.create_index(errors.clone()));
+        let page = store
+            .page(&IndexRef::Method(errors), Anchor::Oldest, Direction::Forward, 100)
+            .expect("index exists");
+        let ids: Vec<_> = page.entries.iter().map(|(id, _)| id.0).collect();
+        assert_eq!(ids, (0..30).step_by(3).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn statistics_read_during_push_are_updated_later() {
+        let store = store();
+        {
+            let _reading = store.stats.read();
+            store.push(json(&store, "error"));
+        }
+        store.push(json(&store, "info"));
+
+        let stats = store.key_stats(2);
+        assert_eq!(stats.messages, 2);
+        assert_eq!(stats.keys.len(), 1);
+        assert_eq!(stats.keys[0].count, 2);
+    }
 }
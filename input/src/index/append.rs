@@ -0,0 +1,144 @@
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use arc_swap::ArcSwap;
+
+/// Number of values in each chunk of an `AppendQueue`.
+const CHUNK_SIZE: usize = 1024;
+
+/// A queue that one writer appends to and removes from the front of,
+/// while any number of readers read it without locking.
+///
+/// Values are written once into fixed-size chunks.
+/// The writer publishes the end position after writing each value,
+/// and publishes a new chunk list when a chunk is added or dropped,
+/// so that readers holding an older list can still read its chunks.
+///
+/// Positions count all values ever pushed, including removed ones.
+/// `push` and `remove_before` must not be called concurrently.
+pub(super) struct AppendQueue<T> {
+    chunks: ArcSwap<Chunks<T>>,
+    /// The position of the oldest value that has not been removed.
+    start:  AtomicUsize,
+    /// The position after the newest published value.
+    end:    AtomicUsize,
+}
+
+struct Chunks<T> {
+    /// The position of the first slot in `list`.
+    first: usize,
+    list:  Vec<Arc<[OnceLock<T>]>>,
+}
+
+impl<T> AppendQueue<T> {
+    pub(super) fn new() -> Self {
+        Self {
+            chunks: ArcSwap::from_pointee(Chunks { first: 0, list: Vec::new() }),
+            start:  AtomicUsize::new(0),
+            end:    AtomicUsize::new(0),
+        }
+    }
+
+    pub(super) fn push(&self, value: T) {
+        let end = self.end.load(Ordering::Relaxed);
+        let chunks = self.chunks.load();
+        let chunk = match chunks.list.get((end - chunks.first) / CHUNK_SIZE) {
+            Some(chunk) => Arc::clone(chunk),
+            None => {
+                let chunk: Arc<[OnceLock<T>]> = (0..CHUNK_SIZE).map(|_| OnceLock::new()).collect();
+                let mut list = chunks.list.clone();
+                list.push(Arc::clone(&chunk));
+                self.chunks.store(Arc::new(Chunks { first: chunks.first, list }));
+                chunk
+            }
+        };
+
+        let slot = &chunk[(end - chunks.first) % CHUNK_SIZE];
+        assert!(slot.set(value).is_ok(), "AppendQueue was pushed concurrently");
+        self.end.store(end + 1, Ordering::Release);
+    }
+
+    /// Returns the number of values that have not been removed.
+    pub(super) fn len(&self) -> usize {
+        let end = self.end.load(Ordering::Acquire);
+        end.saturating_sub(self.start.load(Ordering::Acquire))
+    }
+
+    /// Returns the newest value, which may have been removed.
+    pub(super) fn last(&self) -> Option<T>
+    where
+        T: Clone,
+    {
+        let end = self.end.load(Ordering::Acquire).checked_sub(1)?;
+        let chunks = self.chunks.load();
+        let offset = end.checked_sub(chunks.first)?;
+        chunks.list.get(offset / CHUNK_SIZE)?[offset % CHUNK_SIZE].get().cloned()
+    }
+
+    /// Removes the values before `position`,
+    /// dropping the chunks that only contain removed values.
+    pub(super) fn remove_before(&self, position: usize) {
+        self.start.store(position, Ordering::Release);
+
+        let chunks = self.chunks.load();
+        let dropped = (position - chunks.first) / CHUNK_SIZE;
+        if dropped > 0 {
+            let list = chunks.list[dropped.min(chunks.list.len())..].to_vec();
+            self.chunks
+                .store(Arc::new(Chunks { first: chunks.first + dropped * CHUNK_SIZE, list }));
+        }
+    }
+
+    /// Returns the values published so far.
+    pub(super) fn view(&self) -> View<T> {
+        // the chunk list is loaded last so that it contains every position before `end`
+        let start = self.start.load(Ordering::Acquire);
+        let end = self.end.load(Ordering::Acquire);
+        let chunks = self.chunks.load_full();
+        // chunks may have been dropped after `end` was loaded
+        let start = start.max(chunks.first).min(end);
+        View { chunks, positions: start..end }
+    }
+}
+
+/// A consistent view of the values of an `AppendQueue`,
+/// unaffected by values pushed or removed after the view was created.
+pub(super) struct View<T> {
+    chunks:    Arc<Chunks<T>>,
+    positions: Range<usize>,
+}
+
+impl<T> View<T> {
+    pub(super) fn start(&self) -> usize { self.positions.start }
+
+    pub(super) fn end(&self) -> usize { self.positions.end }
+
+    pub(super) fn len(&self) -> usize { self.positions.len() }
+
+    pub(super) fn get(&self, position: usize) -> Option<&T> {
+        if !self.positions.contains(&position) {
+            return None;
+        }
+        let offset = position - self.chunks.first;
+        let value = self.chunks.list[offset / CHUNK_SIZE][offset % CHUNK_SIZE].get();
+        Some(value.expect("values before the end are published"))
+    }
+
+    pub(super) fn first(&self) -> Option<&T> { self.get(self.start()) }
+
+    /// Returns the first position whose value does not satisfy `pred`,
+    /// assuming that values satisfying `pred` precede all others.
+    pub(super) fn partition_point(&self, pred: impl Fn(&T) -> bool) -> usize {
+        let (mut low, mut high) = (self.start(), self.end());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if pred(self.get(mid).expect("position is in view")) {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+}
@@ -0,0 +1,618 @@
+use std::collections::{BTreeMap, HashMap};
+use std::mem;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use arc_swap::ArcSwap;
+use arcstr::ArcStr;
+use slv_proto::{Content, Entry, JsonEntry, MessageId, RawEntry, Value};
+
+use crate::matcher::{Fields, Matcher};
+
+/// Number of messages in each block of the buffer.
+const BLOCK_SIZE: usize = 4096;
+/// Maximum number of distinct values in a dictionary-encoded column of a block.
+const MAX_DICTIONARY_LEN: usize = u8::MAX as usize;
+
+/// The in-memory buffer of the most recent messages.
+///
+/// Messages are stored in blocks of `BLOCK_SIZE` consecutive messages.
+/// The newest block stores each message in a slot that is written once,
+/// and is converted to per-key columns when it is full,
+/// so that scanning one field does not touch the others.
+/// A block is dropped when all of its messages are evicted,
+/// which is when the memory of its messages is released,
+/// so the memory bound evicts whole blocks and never evicts the newest block.
+///
+/// The block list and the positions of the oldest and newest messages are published atomically,
+/// so that readers never block the writer.
+/// Only the holder of the `Writer` pushes messages.
+pub(super) struct MessageBuffer {
+    blocks:       ArcSwap<Blocks>,
+    /// The ID of the oldest message in the buffer.
+    start:        AtomicUsize,
+    /// The ID after the newest published message.
+    end:          AtomicUsize,
+    bound:        usize,
+    memory_bound: Option<usize>,
+    /// Approximate memory used by the messages in the buffer, in bytes.
+    memory:       AtomicUsize,
+}
+
+struct Blocks {
+    /// The ID of the first message in the first block.
+    first: MessageId,
+    list:  Vec<Arc<Block>>,
+}
+
+enum Block {
+    /// The newest block, with messages written into slots as they are pushed.
+    Rows(Box<[OnceLock<Entry>]>),
+    /// A full block.
+    Columns(Columns),
+}
+
+/// The state of a `MessageBuffer` only used while pushing messages.
+#[derive(Default)]
+pub(super) struct Writer {
+    /// The columns of the newest block, built as messages are pushed.
+    tail:         Columns,
+    /// The estimated memory of the copies of the messages in the slots of the newest block,
+    /// in addition to their columns in `tail`, released when the block is converted to `tail`.
+    slots_memory: usize,
+    memory:       usize,
+}
+
+impl MessageBuffer {
+    pub(super) fn new(bound: usize, memory_bound: Option<usize>) -> Self {
+        Self {
+            blocks: ArcSwap::from_pointee(Blocks { first: MessageId(0), list: Vec::new() }),
+            start: AtomicUsize::new(0),
+            end: AtomicUsize::new(0),
+            bound,
+            memory_bound,
+            memory: AtomicUsize::new(0),
+        }
+    }
+
+    /// Sets the ID of the next message if the buffer has never contained any message.
+    ///
+    /// Returns `false` if it has.
+    pub(super) fn reset_start(&self, _writer: &mut Writer, start: MessageId) -> bool {
+        if self.end.load(Ordering::Relaxed) != 0 {
+            return false;
+        }
+        self.blocks.store(Arc::new(Blocks { first: start, list: Vec::new() }));
+        self.start.store(start.0, Ordering::Release);
+        self.end.store(start.0, Ordering::Release);
+        true
+    }
+
+    /// Appends a message, then evicts the oldest messages until the buffer is within its bounds.
+    ///
+    /// The new message itself is never evicted, even if it alone exceeds the memory bound.
+    /// `on_evict` is called with each evicted message before readers stop seeing it in the buffer.
+    pub(super) fn push(
+        &self,
+        writer: &mut Writer,
+        message: Entry,
+        mut on_evict: impl FnMut(MessageId, &Entry),
+    ) -> PushResult {
+        let added = MessageId(self.end.load(Ordering::Relaxed));
+
+        let blocks = self.blocks.load();
+        let block = (added.0 - blocks.first.0) / BLOCK_SIZE;
+        let block = match blocks.list.get(block) {
+            Some(block) => Arc::clone(block),
+            None => {
+                let mut list = blocks.list.clone();
+                if let Some(last) = list.last_mut() {
+                    // the previous block is full, and its slots are released with it
+                    *last = Arc::new(Block::Columns(mem::take(&mut writer.tail)));
+                    writer.memory -= mem::take(&mut writer.slots_memory);
+                }
+                let block =
+                    Arc::new(Block::Rows((0..BLOCK_SIZE).map(|_| OnceLock::new()).collect()));
+                list.push(Arc::clone(&block));
+                self.blocks.store(Arc::new(Blocks { first: blocks.first, list }));
+                block
+            }
+        };
+        let Block::Rows(slots) = &*block else { unreachable!("only the newest block has rows") };
+
+        writer.memory += writer.tail.push(&message);
+        let slot_size = slot_size(&message);
+        writer.slots_memory += slot_size;
+        writer.memory += slot_size;
+        assert!(slots[writer.tail.len - 1].set(message).is_ok(), "slot is written once");
+        self.end.store(added.0 + 1, Ordering::Release);
+
+        let blocks = self.blocks.load();
+        let newest_block = blocks.first.0 + (blocks.list.len() - 1) * BLOCK_SIZE;
+        let mut removed = Vec::new();
+        let mut start = self.start.load(Ordering::Relaxed);
+        while added.0 + 1 - start > 1
+            && self.exceeds_bounds(writer, added.0 + 1 - start, start < newest_block)
+        {
+            let message = self.oldest(writer, start);
+            on_evict(MessageId(start), &message);
+            removed.push((MessageId(start), message));
+            start += 1;
+            if (start - blocks.first.0).is_multiple_of(BLOCK_SIZE) {
+                // the block is dropped below, since all of its messages were evicted
+                let block = &*blocks.list[(start - 1 - blocks.first.0) / BLOCK_SIZE];
+                let Block::Columns(columns) = block else {
+                    unreachable!("the newest message is not evicted")
+                };
+                writer.memory -= columns.memory;
+            }
+            self.start.store(start, Ordering::Release);
+        }
+        self.drop_evicted_blocks(start);
+        self.memory.store(writer.memory, Ordering::Relaxed);
+
+        PushResult { added, removed }
+    }
+
+    /// Whether the buffer has more than `len` messages or exceeds the memory bound,
+    /// which is only checked if evicting messages can release memory.
+    fn exceeds_bounds(&self, writer: &Writer, len: usize, releases_memory: bool) -> bool {
+        len > self.bound
+            || (releases_memory
+                && self.memory_bound.is_some_and(|memory_bound| writer.memory > memory_bound))
+    }
+
+    /// Returns a copy of the oldest message.
+    fn oldest(&self, writer: &Writer, start: usize) -> Entry {
+        let blocks = self.blocks.load();
+        let offset = start - blocks.first.0;
+        let (block, offset) = (&blocks.list[offset / BLOCK_SIZE], offset % BLOCK_SIZE);
+        let columns = match &**block {
+            Block::Rows(_) => &writer.tail,
+            Block::Columns(columns) => columns,
+        };
+        columns.row(offset).to_entry()
+    }
+
+    fn drop_evicted_blocks(&self, start: usize) {
+        let blocks = self.blocks.load();
+        let dropped = (start - blocks.first.0) / BLOCK_SIZE;
+        if dropped > 0 {
+            let list = blocks.list[dropped..].to_vec();
+            let first = MessageId(blocks.first.0 + dropped * BLOCK_SIZE);
+            self.blocks.store(Arc::new(Blocks { first, list }));
+        }
+    }
+
+    /// Returns the messages published so far.
+    pub(super) fn view(&self) -> View {
+        // the block list is loaded last so that it contains every message before `end`
+        let start = self.start.load(Ordering::Acquire);
+        let end = self.end.load(Ordering::Acquire);
+        let blocks = self.blocks.load_full();
+        // blocks may have been dropped after `end` was loaded
+        let start = start.max(blocks.first.0).min(end);
+        View { blocks, ids: start..end }
+    }
+
+    pub(super) fn memory(&self) -> usize { self.memory.load(Ordering::Relaxed) }
+
+    pub(super) fn memory_bound(&self) -> Option<usize> { self.memory_bound }
+}
+
+/// Estimates the memory of the copy of a message in a slot of the newest block.
+///
+/// Called after the message is pushed to the columns,
+/// so that the strings and raw lines shared with the columns are not counted again.
+fn slot_size(message: &Entry) -> usize {
+    let heap_size = match &message.content {
+        Content::Json(_) => message.heap_size(),
+        Content::Raw(_) => 0,
+    };
+    mem::size_of::<OnceLock<Entry>>() + heap_size
+}
+
+/// A consistent view of the messages in a `MessageBuffer`,
+/// unaffected by messages pushed or evicted after the view was created.
+pub(super) struct View {
+    blocks: Arc<Blocks>,
+    ids:    Range<usize>,
+}
+
+impl View {
+    /// The ID of the oldest message in the view.
+    pub(super) fn start(&self) -> MessageId { MessageId(self.ids.start) }
+
+    /// The ID after the newest message in the view.
+    pub(super) fn end(&self) -> MessageId { MessageId(self.ids.end) }
+
+    pub(super) fn len(&self) -> usize { self.ids.len() }
+
+    pub(super) fn get(&self, id: MessageId) -> Option<Row<'_>> {
+        if !self.ids.contains(&id.0) {
+            return None;
+        }
+
+        let offset = id.0 - self.blocks.first.0;
+        let (block, offset) = (&self.blocks.list[offset / BLOCK_SIZE], offset % BLOCK_SIZE);
+        Some(match &**block {
+            Block::Rows(slots) => {
+                Row::Entry(slots[offset].get().expect("messages before the end are published"))
+            }
+            Block::Columns(columns) => columns.row(offset),
+        })
+    }
+
+    pub(super) fn iter(&self) -> impl Iterator<Item = (MessageId, Row<'_>)> + '_ {
+        self.range(self.start()..self.end())
+    }
+
+    pub(super) fn range(
+        &self,
+        range: Range<MessageId>,
+    ) -> impl Iterator<Item = (MessageId, Row<'_>)> + '_ {
+        let (start, end) = (self.ids.start, self.ids.end);
+        let ids = range.start.0.clamp(start, end)..range.end.0.clamp(start, end);
+        ids.map(move |id| (MessageId(id), self.get(MessageId(id)).expect("id is in view")))
+    }
+}
+
+pub(super) struct PushResult {
+    pub(super) added:   MessageId,
+    /// The evicted messages in ascending order of ID.
+    pub(super) removed: Vec<(MessageId, Entry)>,
+}
+
+/// The messages of a full block, or those pushed so far to the newest block,
+/// with the fields of JSON messages stored in per-key columns.
+#[derive(Default)]
+struct Columns {
+    len:     usize,
+    /// The raw messages by offset in the block, possibly shorter than `len`.
+    raw:     Vec<Option<RawEntry>>,
+    /// The source labels of all messages by offset in the block.
+    sources: Vec<ArcStr>,
+    /// The fields of JSON messages by key,
+    /// sorted by key so that messages are reassembled with sorted fields.
+    columns: BTreeMap<ArcStr, Column>,
+    /// The estimated memory of the messages, released when the block is dropped.
+    memory:  usize,
+}
+
+impl Columns {
+    /// Appends a copy of a message.
+    ///
+    /// Returns the estimated memory used by the message.
+    fn push(&mut self, message: &Entry) -> usize {
+        let offset = self.len;
+        let mut size = mem::size_of::<ArcStr>();
+        self.sources.push(message.source.clone());
+
+        match &message.content {
+            Content::Raw(raw) => {
+                self.raw.resize(offset, None);
+                self.raw.push(Some(raw.clone()));
+                size += mem::size_of::<Option<RawEntry>>() + raw.0.len();
+            }
+            Content::Json(json) => {
+                for (key, value) in &json.0 {
+                    if !self.columns.contains_key(key) {
+                        self.columns.insert(key.clone(), Column::default());
+                    }
+                    let column = self.columns.get_mut(key).expect("inserted above");
+                    size += column.push(offset, value);
+                }
+            }
+        }
+
+        self.memory += size;
+        self.len += 1;
+        size
+    }
+
+    fn row(&self, offset: usize) -> Row<'_> {
+        let source = &self.sources[offset];
+        match self.raw.get(offset) {
+            Some(Some(raw)) => Row::Raw(source, raw),
+            _ => Row::Columns(ColumnRow { columns: &self.columns, source, offset }),
+        }
+    }
+}
+
+/// The values of one key in the columns of a block.
+///
+/// Rows after the end of a column do not have the key.
+enum Column {
+    /// The values of a low-cardinality key as 1-based positions in `values`,
+    /// with 0 for rows without the key.
+    Dictionary {
+        values:    Vec<Value>,
+        positions: HashMap<Value, u8>,
+        codes:     Vec<u8>,
+    },
+    Plain(Vec<Option<Value>>),
+}
+
+impl Default for Column {
+    fn default() -> Self {
+        Self::Dictionary { values: Vec::new(), positions: HashMap::new(), codes: Vec::new() }
+    }
+}
+
+impl Column {
+    /// Stores a copy of the value of the row at `offset`.
+    ///
+    /// Returns the estimated memory used by the value.
+    fn push(&mut self, offset: usize, value: &Value) -> usize {
+        if self.len() > offset {
+            return 0; // duplicate key in the same message
+        }
+
+        match self {
+            Self::Dictionary { values, positions, codes } => {
+                // measured before cloning, since shared strings are not counted
+                let (code, size) = match positions.get(value) {
+                    Some(&code) => (code, 1),
+                    None if values.len() < MAX_DICTIONARY_LEN => {
+                        let size = 1 + 2 * mem::size_of::<Value>() + value.heap_size();
+                        values.push(value.clone());
+                        let code = values.len() as u8;
+                        positions.insert(value.clone(), code);
+                        (code, size)
+                    }
+                    None => {
+                        // too many distinct values, store them directly from now on
+                        let plain: Vec<_> = codes
+                            .iter()
+                            .map(|&code| {
+                                code.checked_sub(1).map(|code| values[code as usize].clone())
+                            })
+                            .collect();
+                        // each code grows to a value, which is counted with this message;
+                        // the strings of the dictionary are shared with the plain values
+                        let converted = plain.len() * (mem::size_of::<Option<Value>>() - 1);
+                        *self = Self::Plain(plain);
+                        return converted + self.push(offset, value);
+                    }
+                };
+                codes.resize(offset, 0);
+                codes.push(code);
+                size
+            }
+            Self::Plain(plain) => {
+                let size = mem::size_of::<Option<Value>>() + value.heap_size();
+                plain.resize(offset, None);
+                plain.push(Some(value.clone()));
+                size
+            }
+        }
+    }
+
+    /// Number of rows up to the last row with the key.
+    fn len(&self) -> usize {
+        match self {
+            Self::Dictionary { codes, .. } => codes.len(),
+            Self::Plain(plain) => plain.len(),
+        }
+    }
+
+    fn get(&self, offset: usize) -> Option<&Value> {
+        match self {
+            Self::Dictionary { values, codes, .. } => {
+                let code = codes.get(offset)?.checked_sub(1)?;
+                Some(&values[code as usize])
+            }
+            Self::Plain(plain) => plain.get(offset)?.as_ref(),
+        }
+    }
+}
+
+/// A message in the buffer.
+pub(super) enum Row<'t> {
+    /// A message in the newest block.
+    Entry(&'t Entry),
+    /// A raw message in a full block, with its source label.
+    Raw(&'t ArcStr, &'t RawEntry),
+    /// A JSON message in a full block.
+    Columns(ColumnRow<'t>),
+}
+
+impl<'t> Row<'t> {
+    /// Returns a copy of the message, reassembled from its columns if necessary.
+    pub(super) fn to_entry(&self) -> Entry {
+        match self {
+            Self::Entry(entry) => (*entry).clone(),
+            Self::Raw(source, raw) => {
+                Entry { source: (*source).clone(), content: Content::Raw((*raw).clone()) }
+            }
+            Self::Columns(row) => row.to_entry(),
+        }
+    }
+
+    /// Whether the message is a JSON message matched by `matcher`.
+    ///
+    /// Only the columns of the keys in the condition are read.
+    pub(super) fn matches(&self, matcher: &Matcher) -> bool {
+        match self {
+            Self::Entry(entry) => {
+                matches!(entry.content, Content::Json(_)) && matcher.matches(*entry)
+            }
+            Self::Raw(..) => false,
+            Self::Columns(row) => matcher.matches(row),
+        }
+    }
+}
+
+/// The fields of a JSON message in a block converted to columns.
+pub(super) struct ColumnRow<'t> {
+    columns: &'t BTreeMap<ArcStr, Column>,
+    source:  &'t ArcStr,
+    offset:  usize,
+}
+
+impl<'t> ColumnRow<'t> {
+    fn get(&self, key: &str) -> Option<&'t Value> { self.columns.get(key)?.get(self.offset) }
+
+    fn to_entry(&self) -> Entry {
+        let fields = self
+            .columns
+            .iter()
+            .filter_map(|(key, column)| Some((key.clone(), column.get(self.offset)?.clone())))
+            .collect();
+        Entry { source: self.source.clone(), content: Content::Json(JsonEntry(fields)) }
+    }
+}
+
+impl<'t> Fields for ColumnRow<'t> {
+    fn get_path(&self, path: &str) -> Option<&Value> {
+        if let Some(value) = self.get(path) {
+            return Some(value);
+        }
+
+        for (dot, _) in path.match_indices('.') {
+            if let Some(value) =
+                self.get(&path[..dot]).and_then(|value| value.get_path(&path[dot + 1..]))
+            {
+                return Some(value);
+            }
+        }
+
+        None
+    }
+
+    fn any_value(&self, f: impl FnMut(&Value) -> bool) -> bool {
+        self.columns.values().filter_map(|column| column.get(self.offset)).any(f)
+    }
+
+    fn source(&self) -> &str { self.source }
+}
+
+#[cfg(test)]
+mod tests {
+    use slv_proto::Number;
+
+    use super::*;
+
+    fn raw(line: &str) -> Entry {
+        Entry {
+            source:  "test".into(),
+            content: Content::Raw(RawEntry(Arc::from(line.as_bytes()))),
+        }
+    }
+
+    fn json(id: u64) -> Entry {
+        let fields = vec![
+            ("id".into(), Value::Number(Number::UInt(id))),
+            ("level".into(), Value::String("info".into())),
+        ];
+        Entry { source: "test".into(), content: Content::Json(JsonEntry(fields)) }
+    }
+
+    fn push_all(
+        buffer: &MessageBuffer,
+        writer: &mut Writer,
+        messages: impl Iterator<Item = Entry>,
+    ) {
+        for message in messages {
+            buffer.push(writer, message, |_, _| {});
+        }
+    }
+
+    /// The memory of a buffer with only one raw message, in its slot and in its columns.
+    fn raw_memory() -> usize {
+        let buffer = MessageBuffer::new(1, None);
+        buffer.push(&mut Writer::default(), raw("line"), |_, _| {});
+        buffer.memory()
+    }
+
+    #[test]
+    fn messages_are_kept_across_blocks() {
+        let buffer = MessageBuffer::new(BLOCK_SIZE * 2, None);
+        let mut writer = Writer::default();
+        push_all(&buffer, &mut writer, (0..BLOCK_SIZE as u64 * 3).map(json));
+
+        let view = buffer.view();
+        assert_eq!(view.start(), MessageId(BLOCK_SIZE));
+        assert_eq!(view.end(), MessageId(BLOCK_SIZE * 3));
+        for (id, row) in view.range(MessageId(BLOCK_SIZE * 2 - 2)..MessageId(BLOCK_SIZE * 2 + 2)) {
+            let Content::Json(fields) = row.to_entry().content else { panic!("pushed as JSON") };
+            assert_eq!(fields.get_path("id"), Some(&Value::Number(Number::UInt(id.0 as u64))));
+        }
+    }
+
+    #[test]
+    fn evicted_messages_release_their_memory_with_their_block() {
+        let buffer = MessageBuffer::new(1, None);
+        let mut writer = Writer::default();
+        push_all(&buffer, &mut writer, (0..BLOCK_SIZE * 3 + 10).map(|_| raw("line")));
+        assert_eq!(buffer.view().len(), 1);
+        assert_eq!(buffer.memory(), 10 * raw_memory());
+    }
+
+    #[test]
+    fn slot_copies_are_released_when_the_block_is_full() {
+        let buffer = MessageBuffer::new(usize::MAX, None);
+        let mut writer = Writer::default();
+        push_all(&buffer, &mut writer, (0..BLOCK_SIZE).map(|_| raw("line")));
+        let full = buffer.memory();
+        assert_eq!(full, BLOCK_SIZE * raw_memory());
+
+        buffer.push(&mut writer, raw("line"), |_, _| {});
+        let slot = slot_size(&raw("line"));
+        assert_eq!(buffer.memory(), full - BLOCK_SIZE * slot + raw_memory());
+    }
+
+    #[test]
+    fn plain_columns_are_counted() {
+        let buffer = MessageBuffer::new(usize::MAX, None);
+        let mut writer = Writer::default();
+        push_all(&buffer, &mut writer, (0..MAX_DICTIONARY_LEN as u64).map(json));
+        let dictionary = buffer.memory();
+
+        // the `id` column is converted to plain values
+        buffer.push(&mut writer, json(MAX_DICTIONARY_LEN as u64), |_, _| {});
+        let converted = MAX_DICTIONARY_LEN * (mem::size_of::<Option<Value>>() - 1);
+        assert!(buffer.memory() - dictionary > converted);
+    }
+
+    #[test]
+    fn memory_does_not_drift_after_eviction() {
+        // JSON messages with a converted column are evicted by raw messages
+        // along with the rest of their block
+        let buffer = MessageBuffer::new(BLOCK_SIZE, None);
+        let mut writer = Writer::default();
+        push_all(&buffer, &mut writer, (0..300).map(json));
+        push_all(&buffer, &mut writer, (0..BLOCK_SIZE * 2).map(|_| raw("line")));
+
+        let slot = slot_size(&raw("line"));
+        let expected = BLOCK_SIZE * (raw_memory() - slot) + 300 * raw_memory();
+        assert_eq!(buffer.memory(), expected);
+    }
+
+    #[test]
+    fn memory_bound_evicts_oldest_block() {
+        let bound = BLOCK_SIZE * raw_memory();
+        let buffer = MessageBuffer::new(usize::MAX, Some(bound));
+        let mut writer = Writer::default();
+        let mut evicted = Vec::new();
+        for i in 0..BLOCK_SIZE * 2 {
+            buffer.push(&mut writer, raw("line"), |id, _| evicted.push(id.0));
+            assert!(buffer.memory() <= bound, "after {i} messages");
+        }
+        assert_eq!(evicted, (0..BLOCK_SIZE).collect::<Vec<_>>());
+        assert_eq!(buffer.view().start(), MessageId(BLOCK_SIZE));
+    }
+
+    #[test]
+    fn memory_bound_keeps_newest_block() {
+        let buffer = MessageBuffer::new(usize::MAX, Some(raw_memory() * 10));
+        let mut writer = Writer::default();
+        let mut evicted = Vec::new();
+        for _ in 0..25 {
+            buffer.push(&mut writer, raw("line"), |id, _| evicted.push(id.0));
+        }
+        assert!(evicted.is_empty());
+        assert_eq!(buffer.memory(), 25 * raw_memory());
+    }
+}
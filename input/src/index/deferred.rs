@@ -0,0 +1,84 @@
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use parking_lot::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// State that `Store::push` updates without waiting for readers.
+///
+/// If a reader holds the lock when the writer updates the state,
+/// the update is queued and applied by the next holder of the write lock,
+/// so that updates are applied in order and readers see all updates queued before they lock.
+pub(super) struct Deferred<T, U> {
+    state:       RwLock<T>,
+    /// Updates that could not be applied because the state was locked.
+    pending:     Mutex<Vec<U>>,
+    /// Whether `pending` may be non-empty,
+    /// so that the writer does not lock it when no reader was in the way.
+    has_pending: AtomicBool,
+}
+
+/// An update of the state of a `Deferred`.
+pub(super) trait Update<T> {
+    fn apply(self, state: &mut T);
+}
+
+impl<T, U: Update<T>> Deferred<T, U> {
+    pub(super) fn new(state: T) -> Self {
+        Self {
+            state:       RwLock::new(state),
+            pending:     Mutex::new(Vec::new()),
+            has_pending: AtomicBool::new(false),
+        }
+    }
+
+    /// Applies `update` now if the state is not locked, and queues it otherwise.
+    pub(super) fn update(&self, update: U) {
+        match self.try_write() {
+            Some(mut state) => update.apply(&mut state),
+            None => {
+                let mut pending = self.pending.lock();
+                pending.push(update);
+                self.has_pending.store(true, Ordering::Release);
+            }
+        }
+    }
+
+    /// Locks the state for writing without waiting, applying the queued updates first.
+    ///
+    /// Returns `None` if the state is locked.
+    pub(super) fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        let mut state = self.state.try_write()?;
+        self.apply_pending(&mut state);
+        Some(state)
+    }
+
+    /// Locks the state for writing, applying the queued updates first.
+    pub(super) fn write(&self) -> RwLockWriteGuard<'_, T> {
+        let mut state = self.state.write();
+        self.apply_pending(&mut state);
+        state
+    }
+
+    /// Locks the state for reading, applying the queued updates first.
+    pub(super) fn read(&self) -> RwLockReadGuard<'_, T> {
+        if !self.has_pending.load(Ordering::Acquire) {
+            self.state.read()
+        } else {
+            RwLockWriteGuard::downgrade(self.write())
+        }
+    }
+
+    fn apply_pending(&self, state: &mut T) {
+        if !self.has_pending.load(Ordering::Acquire) {
+            return;
+        }
+        let pending = {
+            let mut pending = self.pending.lock();
+            self.has_pending.store(false, Ordering::Relaxed);
+            mem::take(&mut *pending)
+        };
+        for update in pending {
+            update.apply(state);
+        }
+    }
+}
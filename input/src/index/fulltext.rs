@@ -0,0 +1,335 @@
+use std::collections::{HashMap, VecDeque};
+use std::mem;
+
+use slv_proto::{Content, Entry, MessageId, TextQuery, Value};
+
+use super::deferred::{self, Deferred};
+use super::{notify_appended, notify_evicted, Subscriber};
+
+/// Tokens are truncated to this many bytes,
+/// so that random data such as hashes does not dominate the memory usage.
+const MAX_TOKEN_LEN: usize = 64;
+/// Estimated memory used by each distinct token in addition to its bytes and postings.
+const TOKEN_OVERHEAD: usize =
+    mem::size_of::<Box<str>>() + mem::size_of::<VecDeque<MessageId>>() + mem::size_of::<u64>();
+const POSTING_SIZE: usize = mem::size_of::<MessageId>();
+
+/// An inverted index from words to the messages containing them.
+///
+/// Raw lines and the configured fields of JSON messages are split into words.
+/// The index covers the newest stored messages;
+/// the oldest messages are dropped from it when it exceeds its memory limit.
+///
+/// Searches do not block `Store::push`, whose updates are deferred while the index is read,
+/// so subscribers of a text query may be notified after the next push.
+pub(super) struct FullText {
+    fields: Vec<String>,
+    index:  Deferred<Postings, Update>,
+}
+
+struct Postings {
+    words:        HashMap<Box<str>, VecDeque<MessageId>>,
+    /// The oldest message that the index may contain.
+    start:        MessageId,
+    /// The ID after the newest indexed message.
+    end:          MessageId,
+    memory:       usize,
+    memory_limit: usize,
+    subscribers:  Vec<TextSubscriber>,
+}
+
+enum Update {
+    Add(MessageId, Words, Option<Entry>),
+    Remove(MessageId, Words),
+    RemoveBefore(MessageId),
+}
+
+impl deferred::Update<Postings> for Update {
+    fn apply(self, index: &mut Postings) {
+        match self {
+            Self::Add(id, words, message) => index.add(id, &words, message.as_ref()),
+            Self::Remove(id, words) => index.remove(id, &words),
+            Self::RemoveBefore(start) => {
+                if start > index.start {
+                    index.drop_before(start);
+                }
+            }
+        }
+    }
+}
+
+struct TextSubscriber {
+    query:      Query,
+    subscriber: Subscriber,
+}
+
+/// The words of a message, as one sequence per line or field value.
+pub(super) struct Words(Vec<Vec<Box<str>>>);
+
+impl Words {
+    fn unique(&self) -> Vec<&str> {
+        let mut words: Vec<&str> = self.0.iter().flatten().map(|word| &**word).collect();
+        words.sort_unstable();
+        words.dedup();
+        words
+    }
+}
+
+/// A tokenized `TextQuery`.
+struct Query {
+    words:  Vec<Box<str>>,
+    phrase: bool,
+}
+
+impl Query {
+    fn new(query: &TextQuery) -> Self {
+        let mut words = Vec::new();
+        tokenize(&query.text, &mut words);
+        Self { words, phrase: query.phrase }
+    }
+
+    fn matches(&self, message: &Words) -> bool {
+        if self.words.is_empty() {
+            return false;
+        }
+
+        if self.phrase {
+            message.0.iter().any(|sequence| {
+                sequence.windows(self.words.len()).any(|window| window == self.words)
+            })
+        } else {
+            self.words.iter().all(|word| message.0.iter().flatten().any(|other| other == word))
+        }
+    }
+}
+
+impl FullText {
+    pub(super) fn new(fields: Vec<String>, memory_limit: usize) -> Self {
+        Self {
+            fields,
+            index: Deferred::new(Postings {
+                words: HashMap::new(),
+                start: MessageId(0),
+                end: MessageId(0),
+                memory: 0,
+                memory_limit,
+                subscribers: Vec::new(),
+            }),
+        }
+    }
+
+    /// Splits a message into words.
+    ///
+    /// This does not lock the index and should be called before locking the buffer.
+    pub(super) fn words(&self, message: &Entry) -> Words {
+        let mut sequences = Vec::new();
+        match &message.content {
+            Content::Raw(raw) => {
+                let mut words = Vec::new();
+                tokenize(&String::from_utf8_lossy(&raw.0), &mut words);
+                sequences.push(words);
+            }
+            Content::Json(json) => {
+                for field in &self.fields {
+                    if let Some(value) = json.get_path(field) {
+                        value_words(value, &mut sequences);
+                    }
+                }
+            }
+        }
+        sequences.retain(|words| !words.is_empty());
+        Words(sequences)
+    }
+
+    pub(super) fn add(&self, id: MessageId, words: Words, message: Option<&Entry>) {
+        match self.index.try_write() {
+            Some(mut index) => index.add(id, &words, message),
+            None => self.index.update(Update::Add(id, words, message.cloned())),
+        }
+    }
+
+    /// Removes a message evicted from the buffer.
+    pub(super) fn remove(&self, id: MessageId, words: Words) {
+        self.index.update(Update::Remove(id, words));
+    }
+
+    /// Finds the messages matching a query, in ascending order of ID.
+    ///
+    /// Candidates are found by intersecting the postings of the words in the query.
+    /// Phrase queries are then verified against the candidates returned by `get`.
+    pub(super) fn search(
+        &self,
+        query: &TextQuery,
+        get: impl Fn(MessageId) -> Option<Entry>,
+    ) -> Vec<MessageId> {
+        let query = Query::new(query);
+        let mut candidates = self.candidates(&query);
+        if query.phrase && query.words.len() > 1 {
+            candidates
+                .retain(|&id| get(id).is_some_and(|message| query.matches(&self.words(&message))));
+        }
+        candidates
+    }
+
+    /// Removes all messages before `start`, which were deleted from disk.
+    pub(super) fn remove_before(&self, start: MessageId) {
+        self.index.update(Update::RemoveBefore(start));
+    }
+
+    fn candidates(&self, query: &Query) -> Vec<MessageId> {
+        let index = self.index.read();
+
+        let mut postings = Vec::with_capacity(query.words.len());
+        for word in &query.words {
+            match index.words.get(word) {
+                Some(list) => postings.push(list),
+                None => return Vec::new(),
+            }
+        }
+        postings.sort_by_key(|list| list.len());
+
+        let (shortest, others) = match postings.split_first() {
+            Some(split) => split,
+            None => return Vec::new(),
+        };
+        shortest
+            .iter()
+            .copied()
+            .filter(|&id| others.iter().all(|list| list.binary_search(&id).is_ok()))
+            .collect()
+    }
+
+    pub(super) fn subscribe(&self, query: &TextQuery, subscriber: Subscriber) {
+        let mut index = self.index.write();
+        index.subscribers.push(TextSubscriber { query: Query::new(query), subscriber });
+    }
+
+    /// Returns `false` if there is no subscriber with the key.
+    pub(super) fn unsubscribe(&self, key: u64) -> bool {
+        let mut index = self.index.write();
+        let len = index.subscribers.len();
+        index.subscribers.retain(|subscriber| subscriber.subscriber.key != key);
+        index.subscribers.len() < len
+    }
+}
+
+impl Postings {
+    fn add(&mut self, id: MessageId, words: &Words, message: Option<&Entry>) {
+        for word in words.unique() {
+            match self.words.get_mut(word) {
+                Some(postings) => postings.push_back(id),
+                None => {
+                    self.words.insert(word.into(), VecDeque::from([id]));
+                    self.memory += TOKEN_OVERHEAD + word.len();
+                }
+            }
+            self.memory += POSTING_SIZE;
+        }
+        self.end = MessageId(id.0 + 1);
+
+        for subscriber in &self.subscribers {
+            if subscriber.query.matches(words) {
+                notify_appended(std::slice::from_ref(&subscriber.subscriber), id, message);
+            }
+        }
+
+        if self.memory > self.memory_limit {
+            self.shrink();
+        }
+    }
+
+    /// Removes a message evicted from the buffer, which must be the oldest message in the index.
+    fn remove(&mut self, id: MessageId, words: &Words) {
+        if id < self.start {
+            return; // already dropped to stay under the memory limit
+        }
+
+        for word in words.unique() {
+            let postings = self.words.get_mut(word).expect("indexed word has postings");
+            assert_eq!(postings.front(), Some(&id), "full-text index inconsistency");
+            postings.pop_front();
+            self.memory -= POSTING_SIZE;
+            if postings.is_empty() {
+                self.words.remove(word);
+                self.memory -= TOKEN_OVERHEAD + word.len();
+            }
+        }
+        self.start = MessageId(id.0 + 1);
+
+        for subscriber in &self.subscribers {
+            if subscriber.query.matches(words) {
+                notify_evicted(std::slice::from_ref(&subscriber.subscriber), id);
+            }
+        }
+    }
+
+    /// Drops the oldest messages until the index is under its memory limit.
+    ///
+    /// A quarter of the covered messages is dropped at a time,
+    /// so that the cost of scanning all words is amortized.
+    fn shrink(&mut self) {
+        while self.memory > self.memory_limit && self.start < self.end {
+            let dropped = (self.end.0 - self.start.0).div_ceil(4);
+            let start = MessageId(self.start.0 + dropped);
+            self.drop_before(start);
+
+            log::debug!(
+                "Full-text index exceeded its memory limit, dropped messages before {}",
+                start.0
+            );
+        }
+    }
+
+    fn drop_before(&mut self, start: MessageId) {
+        let mut memory = 0;
+        self.words.retain(|word, postings| {
+            let len = postings.partition_point(|&id| id < start);
+            postings.drain(..len);
+            if postings.is_empty() {
+                return false;
+            }
+            memory += TOKEN_OVERHEAD + word.len() + postings.len() * POSTING_SIZE;
+            true
+        });
+        self.memory = memory;
+        self.start = start;
+
+        for subscriber in &self.subscribers {
+            notify_evicted(std::slice::from_ref(&subscriber.subscriber), MessageId(start.0 - 1));
+        }
+    }
+}
+
+/// Collects the words of the strings in a field value, one sequence per string.
+fn value_words(value: &Value, sequences: &mut Vec<Vec<Box<str>>>) {
+    match value {
+        Value::Array(items) => items.iter().for_each(|item| value_words(item, sequences)),
+        Value::Object(fields) => fields.iter().for_each(|(_, value)| value_words(value, sequences)),
+        Value::String(string) => {
+            let mut words = Vec::new();
+            tokenize(string, &mut words);
+            sequences.push(words);
+        }
+        Value::Number(_) | Value::Bool(_) => {
+            let mut words = Vec::new();
+            tokenize(&value.to_string(), &mut words);
+            sequences.push(words);
+        }
+        Value::Null => {}
+    }
+}
+
+/// Splits text into lowercase alphanumeric words.
+fn tokenize(text: &str, words: &mut Vec<Box<str>>) {
+    for word in text.split(|ch: char| !ch.is_alphanumeric()).filter(|word| !word.is_empty()) {
+        let mut word = word.to_lowercase();
+        if word.len() > MAX_TOKEN_LEN {
+            let mut len = MAX_TOKEN_LEN;
+            while !word.is_char_boundary(len) {
+                len -= 1;
+            }
+            word.truncate(len);
+        }
+        words.push(word.into_boxed_str());
+    }
+}
@@ -0,0 +1,313 @@
+use std::cmp;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use slv_proto::server::SourceState;
+use slv_proto::{Entry, IndexMethod, MessageId};
+
+use super::spill::{SharedSpill, SpillPin};
+use super::Store;
+
+/// Identifies snapshot files, followed by the little-endian `u32` format version.
+const MAGIC: &[u8; 8] = b"SLVSNAP\0";
+/// Incremented on every incompatible change to the snapshot format.
+const VERSION: u32 = 1;
+
+/// The MessagePack-encoded header of a snapshot,
+/// followed by the MessagePack encodings of `len` entries with consecutive IDs from `start`.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Header {
+    start:   MessageId,
+    len:     u64,
+    indices: Vec<IndexMethod>,
+}
+
+impl Store {
+    /// Writes the stored messages, including those spilled to disk,
+    /// along with the index definitions to a snapshot file.
+    ///
+    /// Returns the number of saved messages.
+    /// Messages pushed while the snapshot is written are not saved.
+    pub fn save(&self, path: &Path) -> Result<u64, SnapshotError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        // pinned before viewing the buffer, so that the messages evicted in between are kept
+        let mut pin = self.spill.as_ref().map(SharedSpill::pin);
+        let buffer = self.buffer.view();
+
+        let start = pin.as_ref().map_or(buffer.start(), SpillPin::start);
+        let len = buffer.end().0 - start.0;
+        let header = Header {
+            start,
+            len: len as u64,
+            // indices created by subscribing are recreated when subscribed to again
+            indices: self
+                .indices
+                .read()
+                .iter()
+                .filter(|(_, entry)| entry.pinned)
+                .map(|(method, _)| method.clone())
+                .collect(),
+        };
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        slv_proto::encode::write(&mut writer, &header)?;
+        if let Some(pin) = &mut pin {
+            pin.try_for_each(buffer.start(), |_, message| -> Result<(), SnapshotError> {
+                Ok(slv_proto::encode::write(&mut writer, message)?)
+            })?;
+        }
+        for (_, row) in buffer.iter() {
+            slv_proto::encode::write(&mut writer, &row.to_entry())?;
+        }
+        writer.flush()?;
+
+        Ok(len as u64)
+    }
+
+    /// Pushes the messages of a snapshot file into an empty store with their original IDs,
+    /// which rebuilds the raw index, then recreates the indices of the snapshot.
+    ///
+    /// Returns the number of loaded messages that are still stored,
+    /// which is less than the number in the snapshot if the buffer is too small to hold them
+    /// and they are not spilled.
+    pub fn load(&self, path: &Path) -> Result<u64, SnapshotError> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut magic = [0; 8];
+        let mut version = [0; 4];
+        reader.read_exact(&mut magic).and_then(|()| reader.read_exact(&mut version)).map_err(
+            |err| match err.kind() {
+                io::ErrorKind::UnexpectedEof => SnapshotError::NotSnapshot,
+                _ => SnapshotError::Io(err),
+            },
+        )?;
+        if &magic != MAGIC {
+            return Err(SnapshotError::NotSnapshot);
+        }
+        let version = u32::from_le_bytes(version);
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let header: Header = slv_proto::decode::from_read(&mut reader)?;
+
+        {
+            let mut writer = self.writer.lock();
+            if !self.buffer.reset_start(&mut writer, header.start) {
+                return Err(SnapshotError::NotEmpty);
+            }
+            if let Some(spill) = &self.spill {
+                spill.reset_start(header.start);
+            }
+        }
+
+        let mut sources = Vec::new();
+        for _ in 0..header.len {
+            let message: Entry = slv_proto::decode::from_read(&mut reader)?;
+            let message = self.interner.entry(message);
+            // inputs are few, and interned labels compare by pointer
+            if !sources.contains(&message.source) {
+                sources.push(message.source.clone());
+            }
+            self.push(message);
+        }
+        for source in sources {
+            self.sources.add(source, SourceState::Ended);
+        }
+
+        for method in header.indices {
+            self.create_index(method);
+        }
+
+        let buffer = self.buffer.view();
+        let start = self.spill.as_ref().map_or(buffer.start(), SharedSpill::start);
+        let retained = cmp::min((buffer.end().0 - start.0) as u64, header.len);
+        if retained < header.len {
+            log::warn!(
+                "Only the newest {retained} of the {} messages in {} fit in the buffer",
+                header.len,
+                path.display()
+            );
+        }
+        Ok(retained)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SnapshotError {
+    #[error("Cannot access snapshot file: {0}")]
+    Io(#[from] io::Error),
+    #[error("Cannot encode snapshot: {0}")]
+    Encode(#[from] slv_proto::encode::Error),
+    #[error("Cannot decode snapshot: {0}")]
+    Decode(#[from] slv_proto::decode::Error),
+    #[error("The file is not an slv snapshot")]
+    NotSnapshot,
+    #[error("Unsupported snapshot version {0}, expected version {VERSION}")]
+    UnsupportedVersion(u32),
+    #[error("Snapshots can only be loaded before any messages are received")]
+    NotEmpty,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use clap::Parser as _;
+    use slv_proto::query::Query;
+    use slv_proto::{Anchor, Content, Direction, IndexRef, JsonEntry, Number, RawEntry, Value};
+
+    use super::*;
+    use crate::index::Options;
+    use crate::test_util::TempDir;
+
+    fn store(args: &[&str]) -> Store {
+        let args = ["slv"].iter().chain(args);
+        Store::new(Options::parse_from(args)).expect("temp dir is writable")
+    }
+
+    fn json(source: &str, level: &str, n: u64) -> Entry {
+        let fields = vec![
+            ("level".into(), Value::String(level.into())),
+            ("n".into(), Value::Number(Number::UInt(n))),
+        ];
+        Entry { source: source.into(), content: Content::Json(JsonEntry(fields)) }
+    }
+
+    fn raw(source: &str, line: &str) -> Entry {
+        let line = Arc::from(line.as_bytes());
+        Entry { source: source.into(), content: Content::Raw(RawEntry(line)) }
+    }
+
+    fn errors() -> IndexMethod {
+        match "level=error".parse::<Query>().expect("valid query").index_ref() {
+            IndexRef::Method(method) => method,
+            _ => unreachable!("the query is not empty"),
+        }
+    }
+
+    /// The encoded messages in `index`, oldest first.
+    fn page(store: &Store, index: &IndexRef) -> Vec<(MessageId, Vec<u8>)> {
+        let page =
+            store.page(index, Anchor::Oldest, Direction::Forward, 1000).expect("index exists");
+        page.entries
+            .iter()
+            .map(|(id, entry)| (*id, slv_proto::encode::to_vec(entry).expect("encodable")))
+            .collect()
+    }
+
+    /// Saves a store with JSON and raw messages from two sources and loads it into a new store.
+    fn round_trip(args: &[&str], test: &str) {
+        let saved = store(args);
+        for n in 0..50 {
+            saved.push(json("a.log", if n % 3 == 0 { "error" } else { "info" }, n));
+            saved.push(raw("b.log", &format!("line {n}")));
+        }
+        saved.create_index(errors());
+        let dir = TempDir::new(&format!("snapshot-{test}"));
+        let file = dir.path("snapshot");
+        assert_eq!(saved.save(&file).expect("temp dir is writable"), 100);
+
+        let loaded = store(args);
+        assert_eq!(loaded.load(&file).expect("saved above"), 100);
+
+        assert_eq!(loaded.list_indices().len(), 1);
+        for index in [IndexRef::All, IndexRef::Raw, IndexRef::Method(errors())] {
+            assert_eq!(page(&loaded, &index), page(&saved, &index));
+        }
+        assert_eq!(page(&loaded, &IndexRef::Raw).len(), 50);
+        assert_eq!(page(&loaded, &IndexRef::Method(errors())).len(), 17);
+
+        let sources = loaded.status().sources;
+        let labels: Vec<_> = sources.iter().map(|source| source.label.as_str()).collect();
+        assert_eq!(labels, ["a.log", "b.log"]);
+        assert!(sources.iter().all(|source| source.state == SourceState::Ended));
+    }
+
+    #[test]
+    fn round_trip_buffer() { round_trip(&[], "buffer"); }
+
+    #[test]
+    fn round_trip_spilled() { round_trip(&["--buffer-size", "10", "--spill"], "spilled"); }
+
+    #[test]
+    fn snapshot_with_evicted_messages_is_saved_again() {
+        let evicted = store(&["--buffer-size", "10"]);
+        for n in 0..30 {
+            evicted.push(raw("a.log", &format!("line {n}")));
+        }
+        let dir = TempDir::new("snapshot-evicted");
+        let first = dir.path("first");
+        assert_eq!(evicted.save(&first).expect("temp dir is writable"), 10);
+
+        let opened = store(&["--buffer-size", "10", "--spill"]);
+        assert_eq!(opened.load(&first).expect("saved above"), 10);
+        let ids: Vec<_> = page(&opened, &IndexRef::All).into_iter().map(|(id, _)| id.0).collect();
+        assert_eq!(ids, (20..30).collect::<Vec<_>>());
+        assert_eq!(opened.status().spill_entries, 0);
+
+        let second = dir.path("second");
+        assert_eq!(opened.save(&second).expect("temp dir is writable"), 10);
+        let reopened = store(&["--buffer-size", "10", "--spill"]);
+        assert_eq!(reopened.load(&second).expect("saved above"), 10);
+        assert_eq!(page(&reopened, &IndexRef::All), page(&evicted, &IndexRef::All));
+    }
+
+    #[test]
+    fn load_counts_messages_that_fit_in_the_buffer() {
+        let saved = store(&[]);
+        for n in 0..30 {
+            saved.push(raw("a.log", &format!("line {n}")));
+        }
+        let dir = TempDir::new("snapshot-evicted-on-load");
+        let file = dir.path("snapshot");
+        assert_eq!(saved.save(&file).expect("temp dir is writable"), 30);
+
+        let small = store(&["--buffer-size", "10"]);
+        assert_eq!(small.load(&file).expect("saved above"), 10);
+        assert_eq!(page(&small, &IndexRef::All), page(&saved, &IndexRef::All)[20..]);
+    }
+
+    #[test]
+    fn new_messages_are_appended_after_loaded_ones() {
+        let saved = store(&[]);
+        saved.push(raw("a.log", "old"));
+        let dir = TempDir::new("snapshot-append");
+        let file = dir.path("snapshot");
+        saved.save(&file).expect("temp dir is writable");
+
+        let loaded = store(&[]);
+        loaded.load(&file).expect("saved above");
+        loaded.push(raw("a.log", "new"));
+        let ids: Vec<_> = page(&loaded, &IndexRef::All).into_iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, [MessageId(0), MessageId(1)]);
+    }
+
+    #[test]
+    fn load_errors() {
+        let dir = TempDir::new("snapshot-errors");
+        let file = dir.path("snapshot");
+
+        std::fs::write(&file, b"SLV").expect("temp dir is writable");
+        assert!(matches!(store(&[]).load(&file), Err(SnapshotError::NotSnapshot)));
+
+        std::fs::write(&file, b"not a snapshot file").expect("temp dir is writable");
+        assert!(matches!(store(&[]).load(&file), Err(SnapshotError::NotSnapshot)));
+
+        let mut newer = MAGIC.to_vec();
+        newer.extend_from_slice(&(VERSION + 1).to_le_bytes());
+        std::fs::write(&file, newer).expect("temp dir is writable");
+        let result = store(&[]).load(&file);
+        assert!(
+            matches!(result, Err(SnapshotError::UnsupportedVersion(version)) if version == VERSION + 1)
+        );
+
+        store(&[]).save(&file).expect("temp dir is writable");
+        let nonempty = store(&[]);
+        nonempty.push(raw("a.log", "line"));
+        assert!(matches!(nonempty.load(&file), Err(SnapshotError::NotEmpty)));
+    }
+}
@@ -0,0 +1,753 @@
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::{cmp, process};
+
+use parking_lot::{Mutex, MutexGuard};
+use slv_proto::{Entry, MessageId};
+
+use super::append::AppendQueue;
+
+/// Number of records between two offsets in the sparse offset table of a segment.
+const SPARSE_INTERVAL: usize = 64;
+/// Maximum size of a segment file, unless a quarter of the disk limit is smaller.
+const MAX_SEGMENT_SIZE: u64 = 64 << 20;
+/// Number of messages that `SharedSpill::for_each` reads from disk per lock.
+const BATCH_SIZE: usize = 1024;
+/// Number of queued messages beyond which `Store::push` waits for readers of the disk.
+const MAX_QUEUED: usize = 1 << 16;
+
+/// Append-only segment files holding the messages evicted from the in-memory buffer.
+///
+/// Each record is a little-endian `u32` length followed by the MessagePack encoding of an entry.
+/// Segments cover consecutive ranges of IDs, and the oldest segments are deleted
+/// when the total size exceeds the limit.
+pub(super) struct Spill {
+    /// The directory created for this store, which is removed on drop.
+    dir:          PathBuf,
+    segments:     VecDeque<Segment>,
+    /// The ID of the next message to be written.
+    end:          MessageId,
+    size:         u64,
+    size_limit:   u64,
+    segment_size: u64,
+    /// The writer of the last segment, or `None` if a new segment should be started.
+    writer:       Option<BufWriter<File>>,
+    /// The segment index and the ID of the next record that `read` is positioned at.
+    cursor:       Option<(usize, MessageId)>,
+    /// The oldest message still to be read by each `SpillPin`,
+    /// whose segment is not deleted even if the size limit is exceeded.
+    pinned:       Vec<MessageId>,
+}
+
+struct Segment {
+    start:  MessageId,
+    len:    usize,
+    size:   u64,
+    path:   PathBuf,
+    /// The offset of every `SPARSE_INTERVAL`-th record.
+    sparse: Vec<u64>,
+    reader: Option<BufReader<File>>,
+}
+
+impl Spill {
+    /// Creates a new segment directory in `parent`, or in the system temp directory if `None`,
+    /// so that several stores and processes can share the same parent.
+    pub(super) fn new(parent: Option<PathBuf>, size_limit: u64) -> io::Result<Self> {
+        let parent = parent.unwrap_or_else(std::env::temp_dir);
+        fs::create_dir_all(&parent)?;
+        let dir = create_unique_dir(&parent)?;
+
+        Ok(Self {
+            dir,
+            segments: VecDeque::new(),
+            end: MessageId(0),
+            size: 0,
+            size_limit,
+            segment_size: (size_limit / 4).clamp(1, MAX_SEGMENT_SIZE),
+            writer: None,
+            cursor: None,
+            pinned: Vec::new(),
+        })
+    }
+
+    /// The oldest message on disk, or `end()` if there is none.
+    pub(super) fn start(&self) -> MessageId {
+        self.segments.front().map_or(self.end, |segment| segment.start)
+    }
+
+    /// The ID after the newest message on disk.
+    pub(super) fn end(&self) -> MessageId { self.end }
+
+    /// Total size of the segment files in bytes.
+    pub(super) fn size(&self) -> u64 { self.size }
+
+    /// Writes an evicted message, which must immediately follow the last written message.
+    ///
+    /// Returns the new `start()` if older messages were deleted to stay under the size limit
+    /// or because of a write error.
+    pub(super) fn append(&mut self, id: MessageId, message: &Entry) -> Option<MessageId> {
+        if self.segments.is_empty() {
+            self.end = id;
+        }
+        assert_eq!(id, self.end, "spilled messages must be consecutive");
+
+        let deleted = match self.try_append(id, message) {
+            Ok(()) => self.delete_oldest(),
+            Err(err) => {
+                log::error!("Cannot write evicted messages to {}: {err}", self.dir.display());
+                self.clear();
+                true
+            }
+        };
+        self.end = MessageId(id.0 + 1);
+
+        deleted.then(|| self.start())
+    }
+
+    fn try_append(&mut self, id: MessageId, message: &Entry) -> io::Result<()> {
+        let record = slv_proto::encode::to_vec(message)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let len = u32::try_from(record.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "message too large"))?;
+
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => {
+                let path = self.dir.join(format!("segment-{}.bin", id.0));
+                let file = File::create(&path)?;
+                self.segments.push_back(Segment {
+                    start: id,
+                    len: 0,
+                    size: 0,
+                    path,
+                    sparse: Vec::new(),
+                    reader: None,
+                });
+                self.writer.insert(BufWriter::new(file))
+            }
+        };
+        writer.write_all(&len.to_le_bytes())?;
+        writer.write_all(&record)?;
+
+        let segment = self.segments.back_mut().expect("segment was created with the writer");
+        if segment.len.is_multiple_of(SPARSE_INTERVAL) {
+            segment.sparse.push(segment.size);
+        }
+        let record_size = 4 + u64::from(len);
+        segment.len += 1;
+        segment.size += record_size;
+        self.size += record_size;
+
+        if segment.size >= self.segment_size {
+            // start a new segment on the next write
+            let mut writer = self.writer.take().expect("writer was used above");
+            writer.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Deletes the oldest segments until the total size is under the limit,
+    /// except for pinned segments.
+    ///
+    /// Returns whether any segment was deleted.
+    fn delete_oldest(&mut self) -> bool {
+        let mut deleted = false;
+        while self.size > self.size_limit
+            && self.segments.len() > 1
+            && self.pinned.iter().all(|&pinned| pinned >= self.segments[1].start)
+        {
+            let segment = self.segments.pop_front().expect("len > 1");
+            self.size -= segment.size;
+            remove_file(&segment);
+            deleted = true;
+        }
+        if deleted {
+            self.cursor = None;
+        }
+        deleted
+    }
+
+    fn clear(&mut self) {
+        self.writer = None;
+        self.cursor = None;
+        for segment in self.segments.drain(..) {
+            remove_file(&segment);
+        }
+        self.size = 0;
+    }
+
+    /// Reads the messages with the given IDs, which must be in ascending order.
+    ///
+    /// IDs that are not on disk or cannot be read are skipped.
+    pub(super) fn get_many(
+        &mut self,
+        ids: impl IntoIterator<Item = MessageId>,
+    ) -> Vec<(MessageId, Entry)> {
+        let mut entries = Vec::new();
+        self.visit(ids, |id, message| entries.push((id, message)));
+        entries
+    }
+
+    /// Calls `f` with the messages in `range`, which must be on disk, in ascending order of ID,
+    /// stopping at the first error.
+    pub(super) fn try_for_each<E: From<io::Error>>(
+        &mut self,
+        range: Range<MessageId>,
+        mut f: impl FnMut(MessageId, Entry) -> Result<(), E>,
+    ) -> Result<(), E> {
+        assert!(self.start() <= range.start && range.end <= self.end, "messages are on disk");
+        for id in range.start.0..range.end.0 {
+            let message = self.read(MessageId(id))?;
+            f(MessageId(id), message)?;
+        }
+        Ok(())
+    }
+
+    fn visit(
+        &mut self,
+        ids: impl IntoIterator<Item = MessageId>,
+        mut f: impl FnMut(MessageId, Entry),
+    ) {
+        for id in ids {
+            if id < self.start() || id >= self.end {
+                continue;
+            }
+            match self.read(id) {
+                Ok(message) => f(id, message),
+                Err(err) => {
+                    log::warn!("Cannot read message {} from disk: {err}", id.0);
+                    self.cursor = None;
+                }
+            }
+        }
+    }
+
+    fn read(&mut self, id: MessageId) -> io::Result<Entry> {
+        if let Some(writer) = &mut self.writer {
+            writer.flush()?;
+        }
+
+        let index = self.segments.partition_point(|segment| segment.start <= id) - 1;
+        let segment = &mut self.segments[index];
+        let offset = id.0 - segment.start.0;
+
+        let reader = match &mut segment.reader {
+            Some(reader) => reader,
+            None => segment.reader.insert(BufReader::new(File::open(&segment.path)?)),
+        };
+
+        // continue from the previous read if it is closer than the nearest sparse offset
+        let mut position = match self.cursor {
+            Some((cursor_index, cursor)) if cursor_index == index && cursor <= id => {
+                let cursor_offset = cursor.0 - segment.start.0;
+                if offset - cursor_offset < offset % SPARSE_INTERVAL {
+                    cursor_offset
+                } else {
+                    seek_sparse(reader, &segment.sparse, offset)?
+                }
+            }
+            _ => seek_sparse(reader, &segment.sparse, offset)?,
+        };
+        self.cursor = None;
+
+        while position < offset {
+            let len = read_len(reader)?;
+            reader.seek_relative(i64::from(len))?;
+            position += 1;
+        }
+
+        let len = read_len(reader)?;
+        let mut record = vec![0; len as usize];
+        reader.read_exact(&mut record)?;
+        let message = slv_proto::decode::from_slice(&record)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        self.cursor = Some((index, MessageId(id.0 + 1)));
+        Ok(message)
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        self.clear();
+        if let Err(err) = fs::remove_dir(&self.dir) {
+            log::warn!("Cannot remove spill directory {}: {err}", self.dir.display());
+        }
+    }
+}
+
+/// The messages evicted from the buffer, shared by `Store::push` and the readers of the store.
+///
+/// Readers lock the segment files while reading them.
+/// Instead of waiting for them, `push` queues evicted messages in memory
+/// and writes them to disk once the lock is free.
+pub(super) struct SharedSpill {
+    disk:  Mutex<Spill>,
+    /// Evicted messages not written to disk yet, in ascending order of ID.
+    ///
+    /// Messages are only removed from the queue after they are written,
+    /// so that readers find them either in the queue or on disk.
+    queue: AppendQueue<(MessageId, Entry)>,
+    /// `Spill::start` after the last write.
+    start: AtomicUsize,
+    /// `Spill::size` after the last write.
+    size:  AtomicU64,
+}
+
+impl SharedSpill {
+    pub(super) fn new(parent: Option<PathBuf>, size_limit: u64) -> io::Result<Self> {
+        Ok(Self {
+            disk:  Mutex::new(Spill::new(parent, size_limit)?),
+            queue: AppendQueue::new(),
+            start: AtomicUsize::new(0),
+            size:  AtomicU64::new(0),
+        })
+    }
+
+    /// The oldest spilled message, which may be in the buffer if none was spilled.
+    pub(super) fn start(&self) -> MessageId { MessageId(self.start.load(Ordering::Acquire)) }
+
+    /// Sets the ID of the first message to be spilled, before any message is spilled.
+    ///
+    /// Called along with `MessageBuffer::reset_start`.
+    pub(super) fn reset_start(&self, start: MessageId) {
+        let mut disk = self.disk.lock();
+        assert!(disk.segments.is_empty() && self.queue.len() == 0, "messages were spilled");
+        disk.end = start;
+        self.start.store(start.0, Ordering::Release);
+    }
+
+    /// Total size of the segment files in bytes.
+    pub(super) fn size(&self) -> u64 { self.size.load(Ordering::Relaxed) }
+
+    /// Starts writing the messages evicted by a push.
+    pub(super) fn writer(&self) -> SpillWriter<'_> {
+        SpillWriter { spill: self, disk: None, start: None }
+    }
+
+    /// Reads the spilled messages with the given IDs, which must be in ascending order.
+    ///
+    /// IDs that are neither queued nor on disk are skipped.
+    pub(super) fn get_many(&self, ids: &[MessageId]) -> Vec<(MessageId, Entry)> {
+        let queued = self.queue.view();
+        let first = queued.first().map(|&(id, _)| id);
+        // the messages before the queue were written before they were removed from it
+        let on_disk = first.map_or(ids.len(), |first| ids.partition_point(|&id| id < first));
+
+        let mut entries = match on_disk {
+            0 => Vec::new(),
+            _ => self.disk.lock().get_many(ids[..on_disk].iter().copied()),
+        };
+        if let Some(first) = first {
+            entries.extend(ids[on_disk..].iter().filter_map(|&id| {
+                let (_, message) = queued.get(queued.start() + (id.0 - first.0))?;
+                Some((id, message.clone()))
+            }));
+        }
+        entries
+    }
+
+    /// Calls `f` with the spilled messages in `range` in ascending order of ID,
+    /// which must have been evicted before this is called.
+    ///
+    /// The disk is locked for a batch of messages at a time, so that `push` rarely waits for it.
+    pub(super) fn for_each(&self, range: Range<MessageId>, mut f: impl FnMut(MessageId, &Entry)) {
+        let mut next = range.start;
+        while next < range.end {
+            let queued = self.queue.view();
+            if let Some(&(first, _)) = queued.first().filter(|&&(first, _)| first <= next) {
+                for position in queued.start() + (next.0 - first.0)..queued.end() {
+                    let (id, message) = queued.get(position).expect("position is in view");
+                    if *id >= range.end {
+                        break;
+                    }
+                    f(*id, message);
+                }
+                return;
+            }
+
+            let mut disk = self.disk.lock();
+            next = cmp::max(next, disk.start());
+            let end = cmp::min(cmp::min(range.end, disk.end()), MessageId(next.0 + BATCH_SIZE));
+            if next >= end {
+                return; // deleted from disk
+            }
+            disk.visit((next.0..end.0).map(MessageId), |id, message| f(id, &message));
+            next = end;
+        }
+    }
+
+    /// Keeps the spilled messages from the oldest one on from being deleted from disk,
+    /// until they are read with `SpillPin::try_for_each` or the pin is dropped.
+    pub(super) fn pin(&self) -> SpillPin<'_> {
+        let mut disk = self.disk.lock();
+        let next = disk.start();
+        disk.pinned.push(next);
+        SpillPin { spill: self, next }
+    }
+}
+
+/// Keeps spilled messages from being deleted while they are read, see `SharedSpill::pin`.
+pub(super) struct SpillPin<'s> {
+    spill: &'s SharedSpill,
+    /// The oldest message that is kept.
+    next:  MessageId,
+}
+
+impl SpillPin<'_> {
+    /// The oldest message that is kept.
+    pub(super) fn start(&self) -> MessageId { self.next }
+
+    /// Calls `f` with the kept messages until `end` in ascending order of ID,
+    /// which must have been evicted before this is called, stopping at the first error.
+    ///
+    /// Like `SharedSpill::for_each`, the disk is locked for a batch of messages at a time,
+    /// and the pin is moved past each batch so that its segments can be deleted.
+    pub(super) fn try_for_each<E: From<io::Error>>(
+        &mut self,
+        end: MessageId,
+        mut f: impl FnMut(MessageId, &Entry) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let mut next = self.next;
+        while next < end {
+            let queued = self.spill.queue.view();
+            if let Some(&(first, _)) = queued.first().filter(|&&(first, _)| first <= next) {
+                for position in queued.start() + (next.0 - first.0)..queued.end() {
+                    let (id, message) = queued.get(position).expect("position is in view");
+                    if *id >= end {
+                        break;
+                    }
+                    f(*id, message)?;
+                    next = MessageId(id.0 + 1);
+                }
+                break;
+            }
+
+            let mut disk = self.spill.disk.lock();
+            let batch_end = cmp::min(cmp::min(end, disk.end()), MessageId(next.0 + BATCH_SIZE));
+            if next < disk.start() || next >= batch_end {
+                break; // deleted from disk after a write error
+            }
+            disk.try_for_each(next..batch_end, |id, message| f(id, &message))?;
+            next = batch_end;
+            self.move_to(&mut disk, next);
+        }
+
+        if next < end {
+            let missing = format!("spilled messages from {} are missing", next.0);
+            return Err(io::Error::new(io::ErrorKind::NotFound, missing).into());
+        }
+        Ok(())
+    }
+
+    fn move_to(&mut self, disk: &mut Spill, next: MessageId) {
+        let position = disk.pinned.iter().position(|&pinned| pinned == self.next);
+        disk.pinned[position.expect("pinned until dropped")] = next;
+        self.next = next;
+    }
+}
+
+impl Drop for SpillPin<'_> {
+    fn drop(&mut self) {
+        let mut disk = self.spill.disk.lock();
+        let position = disk.pinned.iter().position(|&pinned| pinned == self.next);
+        disk.pinned.swap_remove(position.expect("pinned until dropped"));
+    }
+}
+
+/// Writes the messages evicted by one push,
+/// holding the lock of the segment files once it was acquired.
+pub(super) struct SpillWriter<'s> {
+    spill: &'s SharedSpill,
+    disk:  Option<MutexGuard<'s, Spill>>,
+    /// The new start of the spilled messages if older messages were deleted from disk.
+    start: Option<MessageId>,
+}
+
+impl SpillWriter<'_> {
+    /// Writes an evicted message, or queues it if a reader is reading the disk.
+    ///
+    /// The message must immediately follow the previously evicted message.
+    pub(super) fn append(&mut self, id: MessageId, message: &Entry) {
+        self.try_lock();
+        match &mut self.disk {
+            Some(disk) => {
+                if let Some(start) = disk.append(id, message) {
+                    self.start = Some(start);
+                }
+            }
+            None => self.spill.queue.push((id, message.clone())),
+        }
+    }
+
+    /// Writes the queued messages unless a reader is reading the disk.
+    ///
+    /// Returns the new start of the spilled messages if older messages were deleted from disk.
+    pub(super) fn finish(mut self) -> Option<MessageId> {
+        if self.spill.queue.len() > 0 {
+            self.try_lock();
+        }
+        if let Some(disk) = &self.disk {
+            self.spill.size.store(disk.size(), Ordering::Relaxed);
+            self.spill.start.store(disk.start().0, Ordering::Release);
+        }
+        self.start
+    }
+
+    /// Locks the disk unless a reader holds the lock, and writes the queued messages.
+    ///
+    /// Waits for the lock if too many messages are queued.
+    fn try_lock(&mut self) {
+        if self.disk.is_some() {
+            return;
+        }
+        let queue = &self.spill.queue;
+        let disk = match self.spill.disk.try_lock() {
+            Some(disk) => disk,
+            None if queue.len() >= MAX_QUEUED => self.spill.disk.lock(),
+            None => return,
+        };
+
+        let disk = self.disk.insert(disk);
+        if queue.len() > 0 {
+            let queued = queue.view();
+            for position in queued.start()..queued.end() {
+                let (id, message) = queued.get(position).expect("position is in view");
+                if let Some(start) = disk.append(*id, message) {
+                    self.start = Some(start);
+                }
+            }
+            queue.remove_before(queued.end());
+        }
+    }
+}
+
+/// Creates a directory in `parent` named after this process and the number of earlier attempts.
+fn create_unique_dir(parent: &Path) -> io::Result<PathBuf> {
+    let mut attempt = 0;
+    loop {
+        let dir = parent.join(format!("slv-spill-{}-{attempt}", process::id()));
+        match fs::create_dir(&dir) {
+            Ok(()) => return Ok(dir),
+            // left behind by a process that had the same ID, or created by another store
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => attempt += 1,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Seeks to the nearest sparse offset not after the record at `offset`.
+///
+/// Returns the position of the record the reader is at.
+fn seek_sparse(reader: &mut BufReader<File>, sparse: &[u64], offset: usize) -> io::Result<usize> {
+    let slot = offset / SPARSE_INTERVAL;
+    reader.seek(SeekFrom::Start(sparse[slot]))?;
+    Ok(slot * SPARSE_INTERVAL)
+}
+
+fn read_len(reader: &mut impl Read) -> io::Result<u32> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    Ok(u32::from_le_bytes(len))
+}
+
+fn remove_file(segment: &Segment) {
+    if let Err(err) = fs::remove_file(&segment.path) {
+        log::warn!("Cannot remove spill segment {}: {err}", segment.path.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use slv_proto::{Content, RawEntry};
+
+    use super::*;
+
+    fn message(id: usize) -> Entry {
+        let line = format!("message {id}").into_bytes();
+        Entry { source: "test".into(), content: Content::Raw(RawEntry(Arc::from(line))) }
+    }
+
+    fn line(message: &Entry) -> String {
+        match &message.content {
+            Content::Raw(raw) => String::from_utf8(raw.0.to_vec()).expect("written as UTF-8"),
+            Content::Json(_) => panic!("only raw messages are written"),
+        }
+    }
+
+    /// Parses the record at `offset` in a segment file, returning it with the next offset.
+    fn parse_record(bytes: &[u8], offset: usize) -> (Entry, usize) {
+        let len = u32::from_le_bytes(bytes[offset..offset + 4].try_into().expect("4 bytes"));
+        let end = offset + 4 + len as usize;
+        let message = slv_proto::decode::from_slice(&bytes[offset + 4..end]).expect("valid record");
+        (message, end)
+    }
+
+    /// Writes `count` messages to a new spill directory in `parent`.
+    fn spill(parent: Option<PathBuf>, size_limit: u64, count: usize) -> Spill {
+        let mut spill = Spill::new(parent, size_limit).expect("temp dir is writable");
+        for id in 0..count {
+            spill.append(MessageId(id), &message(id));
+        }
+        if let Some(writer) = &mut spill.writer {
+            writer.flush().expect("temp dir is writable");
+        }
+        spill
+    }
+
+    #[test]
+    fn records_are_length_prefixed() {
+        let spill = spill(None, 1 << 20, 3);
+        let segment = &spill.segments[0];
+        let bytes = fs::read(&segment.path).expect("segment exists");
+        assert_eq!(bytes.len() as u64, spill.size());
+
+        let mut offset = 0;
+        for id in 0..3 {
+            let (message, next) = parse_record(&bytes, offset);
+            assert_eq!(line(&message), format!("message {id}"));
+            offset = next;
+        }
+        assert_eq!(offset, bytes.len());
+    }
+
+    #[test]
+    fn sparse_offsets_point_to_records() {
+        let count = SPARSE_INTERVAL * 3 + 5;
+        let spill = spill(None, 1 << 20, count);
+        let segment = &spill.segments[0];
+        assert_eq!(segment.sparse.len(), 4);
+
+        let bytes = fs::read(&segment.path).expect("segment exists");
+        for (slot, &offset) in segment.sparse.iter().enumerate() {
+            let (message, _) = parse_record(&bytes, offset as usize);
+            assert_eq!(line(&message), format!("message {}", slot * SPARSE_INTERVAL));
+        }
+    }
+
+    #[test]
+    fn read_by_id() {
+        let count = SPARSE_INTERVAL * 3 + 5;
+        let mut spill = spill(None, 1 << 20, count);
+
+        let ids = [0, 1, SPARSE_INTERVAL - 1, SPARSE_INTERVAL, SPARSE_INTERVAL * 2 + 3, count - 1];
+        let messages = spill.get_many(ids.iter().copied().map(MessageId));
+        let read: Vec<_> = messages.iter().map(|(id, message)| (id.0, line(message))).collect();
+        let expected: Vec<_> = ids.iter().map(|&id| (id, format!("message {id}"))).collect();
+        assert_eq!(read, expected);
+
+        // seeking backwards does not continue from the cursor
+        for id in (0..count).rev().step_by(7) {
+            assert_eq!(line(&spill.read(MessageId(id)).expect("on disk")), format!("message {id}"));
+        }
+        assert!(spill.get_many([MessageId(count)]).is_empty());
+    }
+
+    #[test]
+    fn oldest_segments_are_deleted() {
+        let record_size = 4 + slv_proto::encode::to_vec(&message(1000)).expect("encodable").len();
+        let size_limit = (record_size * 100) as u64;
+        let mut spill = spill(None, size_limit, 1000);
+
+        assert!(spill.segments.len() > 1);
+        assert!(spill.size() <= size_limit);
+        assert!(spill.start() > MessageId(0));
+        assert_eq!(spill.end(), MessageId(1000));
+        assert_eq!(fs::read_dir(&spill.dir).expect("dir exists").count(), spill.segments.len());
+
+        let mut expected = spill.start().0;
+        spill
+            .try_for_each(spill.start()..spill.end(), |id, message| -> io::Result<()> {
+                assert_eq!((id.0, line(&message)), (expected, format!("message {expected}")));
+                expected += 1;
+                Ok(())
+            })
+            .expect("segments are readable");
+        assert_eq!(expected, 1000);
+    }
+
+    #[test]
+    fn stores_have_separate_directories() {
+        let parent = std::env::temp_dir().join(format!("slv-spill-test-{}", process::id()));
+        let first = spill(Some(parent.clone()), 1 << 20, 10);
+        let second = spill(Some(parent.clone()), 1 << 20, 10);
+        assert_ne!(first.dir, second.dir);
+        assert!(first.dir.starts_with(&parent));
+
+        drop(first);
+        drop(second);
+        assert_eq!(fs::read_dir(&parent).expect("parent exists").count(), 0);
+        fs::remove_dir(parent).expect("parent is empty");
+    }
+
+    #[test]
+    fn evicted_messages_are_queued_while_disk_is_read() {
+        let spill = SharedSpill::new(None, 1 << 20).expect("temp dir is writable");
+        let mut writer = spill.writer();
+        writer.append(MessageId(0), &message(0));
+        assert_eq!(writer.finish(), None);
+
+        {
+            let _reading = spill.disk.lock();
+            let mut writer = spill.writer();
+            writer.append(MessageId(1), &message(1));
+            assert_eq!(writer.finish(), None);
+            assert_eq!(spill.queue.len(), 1);
+
+            // queued messages are read without locking the disk
+            let read = spill.get_many(&[MessageId(1)]);
+            assert_eq!(
+                read.iter().map(|(id, message)| (id.0, line(message))).collect::<Vec<_>>(),
+                [(1, "message 1".to_string())]
+            );
+        }
+
+        spill.writer().finish();
+        assert_eq!(spill.queue.len(), 0);
+        assert_eq!(spill.disk.lock().end(), MessageId(2));
+
+        let mut read = Vec::new();
+        spill.for_each(MessageId(0)..MessageId(2), |id, message| read.push((id.0, line(message))));
+        assert_eq!(read, [(0, "message 0".to_string()), (1, "message 1".to_string())]);
+    }
+
+    #[test]
+    fn pinned_messages_are_kept_until_read() {
+        let record_size = 4 + slv_proto::encode::to_vec(&message(1000)).expect("encodable").len();
+        let spill =
+            SharedSpill::new(None, (record_size * 100) as u64).expect("temp dir is writable");
+        let append = |ids: Range<usize>| {
+            let mut writer = spill.writer();
+            for id in ids {
+                writer.append(MessageId(id), &message(id));
+            }
+            writer.finish();
+        };
+        append(0..50);
+
+        let mut pin = spill.pin();
+        assert_eq!(pin.start(), MessageId(0));
+        append(50..1000);
+        assert_eq!(spill.start(), MessageId(0));
+
+        let mut read = Vec::new();
+        pin.try_for_each(MessageId(1000), |id, message| -> io::Result<()> {
+            read.push((id.0, line(message)));
+            Ok(())
+        })
+        .expect("segments are readable");
+        assert_eq!(read, (0..1000).map(|id| (id, format!("message {id}"))).collect::<Vec<_>>());
+
+        drop(pin);
+        append(1000..1001);
+        assert!(spill.start() > MessageId(0));
+        assert!(spill.size() <= (record_size * 100) as u64);
+    }
+}
@@ -0,0 +1,450 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use arcstr::ArcStr;
+use rustc_hash::FxHashMap;
+use slv_proto::{server, Content, Entry, JsonEntry, MessageId, Value};
+
+use super::deferred;
+
+/// Maximum number of distinct key paths in the buffer to collect statistics for.
+const MAX_KEYS: usize = 4096;
+/// Maximum number of distinct values of each key that are counted exactly.
+///
+/// Keys with more distinct values, such as request IDs, are considered high-cardinality,
+/// and their distinct values are estimated with a `Sketch` instead.
+const MAX_VALUES_PER_KEY: usize = 256;
+/// Number of hash bits selecting a register of a `Sketch`.
+const PRECISION: u32 = 8;
+/// Number of registers in a `Sketch`, giving a standard error of about 6.5%.
+const REGISTERS: usize = 1 << PRECISION;
+
+/// Statistics of the keys of the JSON messages in the buffer.
+///
+/// Messages are added when pushed and removed when evicted, in ascending order of ID.
+/// Nested objects are counted under dotted key paths.
+///
+/// Updated for every field of every message,
+/// so maps use `FxHashMap`, which hashes short keys much faster than the default hasher.
+#[derive(Default)]
+pub(super) struct Stats {
+    /// Number of JSON messages in the buffer.
+    messages:  u64,
+    /// The ID of the oldest message that has not been removed.
+    start:     MessageId,
+    keys:      FxHashMap<ArcStr, Key>,
+    /// Number of keys in `keys` and their children.
+    ///
+    /// Keys are removed when they no longer occur, making room for new keys.
+    key_count: usize,
+}
+
+/// A change of the messages in the buffer, deferred while the statistics are read.
+pub(super) enum Update {
+    Add(MessageId, JsonEntry),
+    Remove(MessageId, Entry),
+}
+
+impl deferred::Update<Stats> for Update {
+    fn apply(self, stats: &mut Stats) {
+        match self {
+            Self::Add(id, message) => stats.add(id, &message),
+            Self::Remove(id, message) => stats.remove(id, &message),
+        }
+    }
+}
+
+struct Key {
+    /// Number of occurrences of the key in the buffer.
+    count:    u64,
+    /// The ID of the first message the key was counted in,
+    /// since older messages may have been added when there were too many keys.
+    since:    MessageId,
+    /// The values of the key other than objects.
+    values:   Values,
+    /// The keys of object values.
+    children: FxHashMap<ArcStr, Key>,
+}
+
+#[derive(Default)]
+struct Values {
+    counted:   FxHashMap<Value, Counted>,
+    /// Number of occurrences of values that were not in `counted` when they were added.
+    uncounted: u64,
+    /// The hashes of all values in the buffer, only maintained while `uncounted` is nonzero.
+    sketch:    Option<Box<Sketch>>,
+}
+
+struct Counted {
+    count: u64,
+    /// The ID of the first occurrence counted,
+    /// since older occurrences may have been added when `counted` was full.
+    since: MessageId,
+    /// The ID of the newest occurrence.
+    last:  MessageId,
+}
+
+impl Stats {
+    /// Adds a pushed JSON message.
+    pub(super) fn add(&mut self, id: MessageId, message: &JsonEntry) {
+        self.messages += 1;
+        add_fields(&mut self.keys, &mut self.key_count, id, &message.0);
+    }
+
+    /// Removes an evicted message, which must be the oldest message in the buffer.
+    pub(super) fn remove(&mut self, id: MessageId, message: &Entry) {
+        self.start = MessageId(id.0 + 1);
+        if let Content::Json(json) = &message.content {
+            self.messages -= 1;
+            remove_fields(&mut self.keys, &mut self.key_count, id, &json.0);
+        }
+    }
+
+    pub(super) fn messages(&self) -> u64 { self.messages }
+
+    /// Returns the statistics of the keys that occur in the buffer, sorted by key path,
+    /// with up to `top_values` most frequent values of each key.
+    pub(super) fn collect(&self, top_values: usize) -> Vec<server::KeyStat> {
+        let mut stats = Vec::new();
+        collect_keys(&mut stats, &self.keys, None, self.start, top_values);
+        stats.sort_by(|a, b| a.key.cmp(&b.key));
+        stats
+    }
+}
+
+/// Skips repeated keys, which the buffer drops when it converts messages to columns,
+/// so that a message is removed with the same fields as it was added.
+fn unique_fields(fields: &[(ArcStr, Value)]) -> impl Iterator<Item = &(ArcStr, Value)> {
+    fields
+        .iter()
+        .enumerate()
+        .filter(|&(i, (key, _))| i == 0 || fields[i - 1].0 != *key)
+        .map(|(_, field)| field)
+}
+
+fn add_fields(
+    keys: &mut FxHashMap<ArcStr, Key>,
+    key_count: &mut usize,
+    id: MessageId,
+    fields: &[(ArcStr, Value)],
+) {
+    for (key, value) in unique_fields(fields) {
+        if !keys.contains_key(key) {
+            if *key_count >= MAX_KEYS {
+                continue;
+            }
+            *key_count += 1;
+            keys.insert(key.clone(), Key::new(id));
+        }
+        let entry = keys.get_mut(key).expect("inserted above");
+
+        entry.count += 1;
+        match value {
+            Value::Object(fields) => add_fields(&mut entry.children, key_count, id, fields),
+            value => entry.values.add(id, value),
+        }
+    }
+}
+
+fn remove_fields(
+    keys: &mut FxHashMap<ArcStr, Key>,
+    key_count: &mut usize,
+    id: MessageId,
+    fields: &[(ArcStr, Value)],
+) {
+    for (key, value) in unique_fields(fields) {
+        let entry = match keys.get_mut(key) {
+            Some(entry) if entry.since <= id => entry,
+            _ => continue, // too many keys when the message was added
+        };
+
+        entry.count -= 1;
+        match value {
+            Value::Object(fields) => remove_fields(&mut entry.children, key_count, id, fields),
+            value => entry.values.remove(id, value),
+        }
+
+        if entry.count == 0 {
+            let entry = keys.remove(key).expect("found above");
+            *key_count -= 1 + entry.descendants();
+        }
+    }
+}
+
+impl Key {
+    fn new(since: MessageId) -> Self {
+        Self { count: 0, since, values: Values::default(), children: FxHashMap::default() }
+    }
+
+    /// Number of keys among the children of this key and their children.
+    fn descendants(&self) -> usize {
+        self.children.values().map(|child| 1 + child.descendants()).sum()
+    }
+}
+
+fn collect_keys(
+    stats: &mut Vec<server::KeyStat>,
+    keys: &FxHashMap<ArcStr, Key>,
+    parent: Option<&str>,
+    start: MessageId,
+    top_values: usize,
+) {
+    for (key, entry) in keys {
+        let path = match parent {
+            Some(parent) => ArcStr::from(format!("{parent}.{key}")),
+            None => key.clone(),
+        };
+
+        if entry.count > 0 {
+            let (distinct, distinct_exact) = entry.values.distinct(start);
+            stats.push(server::KeyStat {
+                key: path.clone(),
+                count: entry.count,
+                distinct,
+                distinct_exact,
+                top_values: entry.values.top(top_values),
+            });
+        }
+        collect_keys(stats, &entry.children, Some(&path), start, top_values);
+    }
+}
+
+impl Values {
+    fn add(&mut self, id: MessageId, value: &Value) {
+        if let Some(counted) = self.counted.get_mut(value) {
+            counted.count += 1;
+            counted.last = id;
+        } else if self.counted.len() < MAX_VALUES_PER_KEY {
+            self.counted.insert(value.clone(), Counted { count: 1, since: id, last: id });
+        } else {
+            self.uncounted += 1;
+            if self.sketch.is_none() {
+                // all values in the buffer were counted until now
+                let mut sketch = Box::new(Sketch::new());
+                let mut counted: Vec<_> = self.counted.iter().collect();
+                counted.sort_by_key(|(_, counted)| counted.last);
+                for (value, counted) in counted {
+                    sketch.insert(hash(value), counted.last);
+                }
+                self.sketch = Some(sketch);
+            }
+        }
+
+        if let Some(sketch) = &mut self.sketch {
+            sketch.insert(hash(value), id);
+        }
+    }
+
+    fn remove(&mut self, id: MessageId, value: &Value) {
+        match self.counted.get_mut(value) {
+            Some(counted) if counted.since <= id => {
+                counted.count -= 1;
+                if counted.count == 0 {
+                    self.counted.remove(value);
+                }
+            }
+            _ => {
+                self.uncounted -= 1;
+                if self.uncounted == 0 {
+                    // the remaining values are all counted
+                    self.sketch = None;
+                }
+            }
+        }
+    }
+
+    /// Returns the number of distinct values in messages since `start`,
+    /// and whether the number is exact.
+    fn distinct(&self, start: MessageId) -> (u64, bool) {
+        let counted = self.counted.len() as u64;
+        match &self.sketch {
+            Some(sketch) => ((sketch.estimate(start).round() as u64).max(counted), false),
+            None => (counted, true),
+        }
+    }
+
+    fn top(&self, limit: usize) -> Vec<(Value, u64)> {
+        let mut top: Vec<_> =
+            self.counted.iter().map(|(value, counted)| (value.clone(), counted.count)).collect();
+        top.sort_by(|(a_value, a_count), (b_value, b_count)| {
+            b_count.cmp(a_count).then_with(|| a_value.cmp(b_value))
+        });
+        top.truncate(limit);
+        top
+    }
+}
+
+/// Hashes a value for a `Sketch`, which needs uniformly distributed bits.
+fn hash(value: &Value) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A HyperLogLog sketch estimating the number of distinct hashes in a sliding window of messages.
+///
+/// Instead of the maximum rank, each register keeps the ranks that may become the maximum
+/// once older messages leave the window, in ascending order of ID and descending order of rank.
+/// The maximum rank in a window is then the first one within the window.
+struct Sketch {
+    registers: Box<[Vec<(MessageId, u8)>]>,
+}
+
+impl Sketch {
+    fn new() -> Self { Self { registers: (0..REGISTERS).map(|_| Vec::new()).collect() } }
+
+    /// Inserts a hash occurring in a message not older than previously inserted ones.
+    fn insert(&mut self, hash: u64, id: MessageId) {
+        let register = &mut self.registers[(hash >> (u64::BITS - PRECISION)) as usize];
+        let rank = ((hash << PRECISION).leading_zeros().min(u64::BITS - PRECISION) + 1) as u8;
+
+        // older entries with lower ranks can no longer be the maximum
+        while register.last().is_some_and(|&(_, last_rank)| last_rank <= rank) {
+            register.pop();
+        }
+        register.push((id, rank));
+    }
+
+    /// Estimates the number of distinct hashes inserted with an ID not before `start`.
+    fn estimate(&self, start: MessageId) -> f64 {
+        let mut sum = 0.0;
+        let mut zeros = 0;
+        for register in &*self.registers {
+            let rank = register.iter().find(|&&(id, _)| id >= start).map_or(0, |&(_, rank)| rank);
+            sum += 2f64.powi(-i32::from(rank));
+            if rank == 0 {
+                zeros += 1;
+            }
+        }
+
+        let m = REGISTERS as f64;
+        let estimate = 0.7213 / (1.0 + 1.079 / m) * m * m / sum;
+        if estimate <= 2.5 * m && zeros > 0 {
+            // linear counting is more accurate for small cardinalities
+            m * (m / f64::from(zeros)).ln()
+        } else {
+            estimate
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use slv_proto::{Content, Number};
+
+    use super::*;
+
+    fn json(fields: Vec<(&str, Value)>) -> Entry {
+        let fields = fields.into_iter().map(|(key, value)| (ArcStr::from(key), value)).collect();
+        Entry { source: "test".into(), content: Content::Json(JsonEntry(fields)) }
+    }
+
+    fn string(value: &str) -> Value { Value::String(value.into()) }
+
+    fn object(key: &str, value: Value) -> Value { Value::Object(Arc::from([(key.into(), value)])) }
+
+    fn add(stats: &mut Stats, id: usize, message: &Entry) {
+        match &message.content {
+            Content::Json(json) => stats.add(MessageId(id), json),
+            Content::Raw(_) => unreachable!("messages are JSON"),
+        }
+    }
+
+    /// The count of each key path with at least one occurrence.
+    fn counts(stats: &Stats) -> Vec<(String, u64)> {
+        stats.collect(0).into_iter().map(|stat| (stat.key.to_string(), stat.count)).collect()
+    }
+
+    #[test]
+    fn counts_nested_keys_and_top_values() {
+        let mut stats = Stats::default();
+        let messages = [
+            json(vec![
+                ("level", string("info")),
+                ("http", object("status", Value::Number(Number::UInt(200)))),
+            ]),
+            json(vec![("level", string("error"))]),
+            json(vec![("level", string("info"))]),
+        ];
+        for (id, message) in messages.iter().enumerate() {
+            add(&mut stats, id, message);
+        }
+
+        assert_eq!(stats.messages(), 3);
+        assert_eq!(
+            counts(&stats),
+            [("http".into(), 1), ("http.status".into(), 1), ("level".into(), 3)]
+        );
+
+        let level = stats.collect(1).into_iter().find(|stat| stat.key == "level").expect("counted");
+        assert_eq!((level.distinct, level.distinct_exact), (2, true));
+        assert_eq!(level.top_values, [(string("info"), 2)]);
+    }
+
+    #[test]
+    fn removed_messages_are_not_counted() {
+        let mut stats = Stats::default();
+        let messages =
+            [json(vec![("a", string("x"))]), json(vec![("a", string("y")), ("b", string("z"))])];
+        for (id, message) in messages.iter().enumerate() {
+            add(&mut stats, id, message);
+        }
+        stats.remove(MessageId(0), &messages[0]);
+
+        assert_eq!(stats.messages(), 1);
+        assert_eq!(counts(&stats), [("a".into(), 1), ("b".into(), 1)]);
+        let a = stats.collect(2).into_iter().find(|stat| stat.key == "a").expect("counted");
+        assert_eq!(a.top_values, [(string("y"), 1)]);
+    }
+
+    #[test]
+    fn evicted_keys_make_room_for_new_keys() {
+        let mut stats = Stats::default();
+        let many: Vec<_> = (0..MAX_KEYS).map(|i| (format!("key{i}"), Value::Bool(true))).collect();
+        let many = json(many.iter().map(|(key, value)| (key.as_str(), value.clone())).collect());
+        let new = json(vec![("new", object("nested", Value::Bool(true)))]);
+
+        add(&mut stats, 0, &many);
+        add(&mut stats, 1, &new); // skipped, there are too many keys
+        assert_eq!(stats.key_count, MAX_KEYS);
+
+        stats.remove(MessageId(0), &many);
+        assert_eq!(stats.key_count, 0);
+        add(&mut stats, 2, &new);
+        assert_eq!(counts(&stats), [("new".into(), 1), ("new.nested".into(), 1)]);
+
+        // the skipped occurrence is not removed from the counted ones
+        stats.remove(MessageId(1), &new);
+        assert_eq!(counts(&stats), [("new".into(), 1), ("new.nested".into(), 1)]);
+        stats.remove(MessageId(2), &new);
+        assert!(counts(&stats).is_empty());
+        assert_eq!(stats.key_count, 0);
+    }
+
+    #[test]
+    fn many_distinct_values_are_estimated() {
+        let mut stats = Stats::default();
+        let messages: Vec<_> = (0..MAX_VALUES_PER_KEY * 4)
+            .map(|i| json(vec![("id", string(&format!("request-{i}")))]))
+            .collect();
+        for (id, message) in messages.iter().enumerate() {
+            add(&mut stats, id, message);
+        }
+
+        let id = &stats.collect(0)[0];
+        assert!(!id.distinct_exact);
+        let expected = messages.len() as f64;
+        assert!(
+            (id.distinct as f64 - expected).abs() < expected * 0.2,
+            "estimated {}",
+            id.distinct
+        );
+
+        for (id, message) in messages.iter().enumerate() {
+            stats.remove(MessageId(id), message);
+        }
+        assert!(stats.collect(0).is_empty());
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::{HashMap, HashSet};
+
+use arcstr::ArcStr;
+use parking_lot::RwLock;
+use slv_proto::{Content, Entry, JsonEntry, Value};
+
+/// Maximum number of distinct keys to intern.
+const MAX_KEYS: usize = 4096;
+/// Longer keys are never interned, so that the memory held by the interner is bounded.
+const MAX_KEY_LEN: usize = 64;
+/// Maximum number of distinct values to intern for each key.
+///
+/// Keys with more distinct values, such as request IDs, are considered high-cardinality,
+/// and their values are no longer interned.
+const MAX_VALUES_PER_KEY: usize = 256;
+/// Longer values are never interned, since they rarely repeat.
+const MAX_VALUE_LEN: usize = 64;
+
+/// Deduplicates JSON keys and low-cardinality string values,
+/// so that identical strings share one allocation.
+///
+/// Interned strings live as long as the interner.
+/// Comparisons between interned strings are fast because `ArcStr` compares pointers first.
+///
+/// Since interned strings are shared, they are not included in the estimated memory
+/// of the messages in the buffer, see `Value::heap_size`.
+/// Instead, the memory of the interner is bounded by the limits above:
+/// `MAX_KEYS` keys with up to `MAX_VALUES_PER_KEY` values each, all up to 64 bytes long,
+/// take about 100 MiB with their hash tables if every key has that many distinct values,
+/// but usually a few kilobytes for logs with a fixed set of keys and enumerated values.
+///
+/// Strings are looked up under a read lock, so that threads parsing inputs
+/// only wait for each other when they intern a string for the first time.
+#[derive(Default)]
+pub(crate) struct Interner {
+    state: RwLock<State>,
+}
+
+#[derive(Default)]
+struct State {
+    keys:    HashSet<ArcStr>,
+    values:  HashMap<ArcStr, Values>,
+    sources: HashSet<ArcStr>,
+}
+
+enum Values {
+    Interned(HashSet<ArcStr>),
+    HighCardinality,
+}
+
+impl Interner {
+    /// Returns the interned copy of a key.
+    pub(crate) fn key(&self, key: &str) -> ArcStr {
+        {
+            let state = self.state.read();
+            if let Some(interned) = state.keys.get(key) {
+                return interned.clone();
+            }
+            if key.len() > MAX_KEY_LEN || state.keys.len() >= MAX_KEYS {
+                return ArcStr::from(key);
+            }
+        }
+
+        let mut state = self.state.write();
+        // interned by another thread since the lookup
+        if let Some(interned) = state.keys.get(key) {
+            return interned.clone();
+        }
+
+        let key = ArcStr::from(key);
+        if state.keys.len() < MAX_KEYS {
+            state.keys.insert(key.clone());
+        }
+        key
+    }
+
+    /// Returns the interned copy of a string value of the field `key`.
+    ///
+    /// `key` should be returned by `Interner::key`.
+    pub(crate) fn value(&self, key: &ArcStr, value: &str) -> ArcStr {
+        if value.len() > MAX_VALUE_LEN {
+            return ArcStr::from(value);
+        }
+
+        {
+            let state = self.state.read();
+            match state.values.get(key) {
+                Some(Values::Interned(interned)) => {
+                    if let Some(value) = interned.get(value) {
+                        return value.clone();
+                    }
+                }
+                Some(Values::HighCardinality) => return ArcStr::from(value),
+                None if !state.keys.contains(key) => return ArcStr::from(value), // too many keys
+                None => {}
+            }
+        }
+
+        let State { keys, values, .. } = &mut *self.state.write();
+        if !values.contains_key(key) {
+            if !keys.contains(key) {
+                return ArcStr::from(value); // too many keys
+            }
+            values.insert(key.clone(), Values::Interned(HashSet::new()));
+        }
+        let values = values.get_mut(key).expect("inserted above");
+
+        let interned = match values {
+            Values::Interned(interned) => interned,
+            Values::HighCardinality => return ArcStr::from(value),
+        };
+        // interned by another thread since the lookup
+        if let Some(value) = interned.get(value) {
+            return value.clone();
+        }
+
+        let value = ArcStr::from(value);
+        if interned.len() < MAX_VALUES_PER_KEY {
+            interned.insert(value.clone());
+        } else {
+            log::debug!("Stopped interning values of the high-cardinality key {key:?}");
+            *values = Values::HighCardinality;
+        }
+        value
+    }
+
+    /// Returns the interned copy of a string value of the field `key` without interning it,
+    /// or `None` if it is not interned.
+    pub(crate) fn get_value(&self, key: &str, value: &str) -> Option<ArcStr> {
+        let state = self.state.read();
+        match state.values.get(key)? {
+            Values::Interned(interned) => interned.get(value).cloned(),
+            Values::HighCardinality => None,
+        }
+    }
+
+    /// Interns the strings of an entry that was not parsed from the input, such as a snapshot entry.
+    pub(crate) fn entry(&self, entry: Entry) -> Entry {
+        let source = self.source(&entry.source);
+        let content = match entry.content {
+            Content::Json(json) => Content::Json(JsonEntry(self.fields(&json.0))),
+            raw @ Content::Raw(_) => raw,
+        };
+        Entry { source, content }
+    }
+
+    /// Returns the interned copy of a source label.
+    ///
+    /// Labels are few and shared by all messages of an input, so they are always interned.
+    pub(crate) fn source(&self, label: &str) -> ArcStr {
+        if let Some(interned) = self.state.read().sources.get(label) {
+            return interned.clone();
+        }
+
+        let mut state = self.state.write();
+        if let Some(interned) = state.sources.get(label) {
+            return interned.clone();
+        }
+        let label = ArcStr::from(label);
+        state.sources.insert(label.clone());
+        label
+    }
+
+    fn fields(&self, fields: &[(ArcStr, Value)]) -> Vec<(ArcStr, Value)> {
+        fields
+            .iter()
+            .map(|(key, value)| {
+                let key = self.key(key);
+                let value = self.field_value(&key, value);
+                (key, value)
+            })
+            .collect()
+    }
+
+    fn field_value(&self, key: &ArcStr, value: &Value) -> Value {
+        match value {
+            Value::String(string) => Value::String(self.value(key, string)),
+            Value::Array(items) => {
+                Value::Array(items.iter().map(|item| self.field_value(key, item)).collect())
+            }
+            Value::Object(fields) => Value::Object(self.fields(fields).into()),
+            value => value.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interned_strings_are_shared() {
+        let interner = Interner::default();
+        let key = interner.key("level");
+        assert!(ArcStr::ptr_eq(&key, &interner.key("level")));
+
+        let value = interner.value(&key, "info");
+        assert!(ArcStr::ptr_eq(&value, &interner.value(&key, "info")));
+        let found = interner.get_value("level", "info").expect("interned above");
+        assert!(ArcStr::ptr_eq(&value, &found));
+    }
+
+    #[test]
+    fn get_value_does_not_intern() {
+        let interner = Interner::default();
+        assert!(interner.get_value("level", "info").is_none());
+
+        let key = interner.key("level");
+        assert!(interner.get_value("level", "info").is_none());
+        interner.value(&key, "warn");
+        assert!(interner.get_value("level", "info").is_none());
+        assert!(interner.get_value("level", "warn").is_some());
+    }
+
+    #[test]
+    fn high_cardinality_values_are_not_interned() {
+        let interner = Interner::default();
+        let key = interner.key("request_id");
+        for id in 0..=MAX_VALUES_PER_KEY {
+            interner.value(&key, &id.to_string());
+        }
+        assert!(interner.get_value("request_id", "0").is_none());
+        let value = interner.value(&key, "0");
+        assert!(!ArcStr::ptr_eq(&value, &interner.value(&key, "0")));
+    }
+
+    #[test]
+    fn long_keys_are_not_interned() {
+        let interner = Interner::default();
+        let long = "x".repeat(MAX_KEY_LEN + 1);
+        assert!(!ArcStr::ptr_eq(&interner.key(&long), &interner.key(&long)));
+    }
+
+    #[test]
+    fn long_values_are_not_interned() {
+        let interner = Interner::default();
+        let key = interner.key("msg");
+        let long = "x".repeat(MAX_VALUE_LEN + 1);
+        interner.value(&key, &long);
+        assert!(interner.get_value("msg", &long).is_none());
+    }
+}
@@ -1,19 +1,27 @@
 use std::future::Future;
+use std::io;
+use std::path::Path;
 use std::sync::Arc;
 
 use tokio::sync::broadcast;
 
 pub mod index;
+mod interner;
+mod matcher;
 pub mod session;
 mod source;
+#[cfg(test)]
+mod test_util;
 
 pub async fn init(
     options: Options,
     shutdown: broadcast::Receiver<()>,
 ) -> Result<(Arc<index::Store>, impl Future<Output = ()>), InitError> {
-    let store = Arc::new(index::Store::new(options.index));
+    let store = Arc::new(index::Store::new(options.index).map_err(InitError::Spill)?);
     let input = source::init(
         options.source,
+        Arc::clone(store.interner()),
+        Arc::clone(store.sources()),
         {
             let store = Arc::clone(&store);
             move |message| store.push(message)
@@ -25,6 +33,16 @@ pub async fn init(
     Ok((store, input))
 }
 
+/// Loads a snapshot saved by `index::Store::save` into a new store.
+///
+/// The returned store does not receive new messages.
+pub fn open(options: index::Options, path: &Path) -> Result<Arc<index::Store>, InitError> {
+    let store = index::Store::new(options).map_err(InitError::Spill)?;
+    let len = store.load(path)?;
+    log::info!("Loaded {len} messages from {}", path.display());
+    Ok(Arc::new(store))
+}
+
 #[derive(clap::Parser)]
 pub struct Options {
     #[clap(flatten)]
@@ -37,4 +55,8 @@ pub struct Options {
 pub enum InitError {
     #[error("{0}")]
     Source(#[from] source::InitError),
+    #[error("Failed to create spill directory: {0}")]
+    Spill(io::Error),
+    #[error("Failed to load snapshot: {0}")]
+    Snapshot(#[from] index::SnapshotError),
 }
@@ -0,0 +1,332 @@
+use std::borrow::Cow;
+use std::cmp;
+
+use regex::{Regex, RegexBuilder};
+use slv_proto::{Condition, Content, Entry, FieldCondition, Value};
+
+use crate::interner::Interner;
+
+/// A condition prepared for evaluation against many entries.
+///
+/// Regular expressions are compiled once when the matcher is created,
+/// and interned string values are reused so that equal field values are compared by pointer.
+pub(crate) enum Matcher {
+    Field { condition: FieldCondition, regex: Option<Regex> },
+    And(Vec<Matcher>),
+    Or(Vec<Matcher>),
+    Not(Box<Matcher>),
+}
+
+impl Matcher {
+    pub(crate) fn compile(
+        condition: &Condition,
+        interner: &Interner,
+    ) -> Result<Self, regex::Error> {
+        Ok(match condition {
+            Condition::Field(field) => {
+                let regex = match field {
+                    FieldCondition::Regex(_, pattern) | FieldCondition::AnyRegex(pattern) => {
+                        Some(Regex::new(pattern)?)
+                    }
+                    FieldCondition::Contains(_, text) => Some(text_regex("", text, "")?),
+                    FieldCondition::StartsWith(_, text) => Some(text_regex("^", text, "")?),
+                    FieldCondition::EndsWith(_, text) => Some(text_regex("", text, "$")?),
+                    _ => None,
+                };
+                let condition = match field {
+                    FieldCondition::KeyValue(path, Value::String(value)) => {
+                        // fields are interned by their innermost key;
+                        // typed values are only looked up so that queries do not fill the interner
+                        let key = path.rsplit('.').next().unwrap_or(path);
+                        let value = interner.get_value(key, value).unwrap_or_else(|| value.clone());
+                        FieldCondition::KeyValue(path.clone(), Value::String(value))
+                    }
+                    field => field.clone(),
+                };
+                Self::Field { condition, regex }
+            }
+            Condition::And(children) => Self::And(
+                children
+                    .iter()
+                    .map(|child| Self::compile(child, interner))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Condition::Or(children) => Self::Or(
+                children
+                    .iter()
+                    .map(|child| Self::compile(child, interner))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Condition::Not(child) => Self::Not(Box::new(Self::compile(child, interner)?)),
+        })
+    }
+
+    /// A matcher that never matches, used in place of conditions that cannot be compiled.
+    pub(crate) fn never() -> Self { Self::Or(Vec::new()) }
+
+    pub(crate) fn matches(&self, message: &impl Fields) -> bool {
+        match self {
+            Self::Field { condition, regex } => field_matches(condition, regex.as_ref(), message),
+            Self::And(children) => children.iter().all(|child| child.matches(message)),
+            Self::Or(children) => children.iter().any(|child| child.matches(message)),
+            Self::Not(child) => !child.matches(message),
+        }
+    }
+}
+
+/// The fields and the source of a JSON message that conditions are evaluated against.
+pub(crate) trait Fields {
+    /// Looks up a field by a dotted key path such as `http.request.method`.
+    fn get_path(&self, path: &str) -> Option<&Value>;
+
+    /// Whether `f` returns `true` for the value of any top-level field.
+    fn any_value(&self, f: impl FnMut(&Value) -> bool) -> bool;
+
+    /// The label of the input the message was read from.
+    fn source(&self) -> &str;
+}
+
+/// Only JSON entries are matched against conditions, so raw entries have no fields.
+impl Fields for Entry {
+    fn get_path(&self, path: &str) -> Option<&Value> {
+        match &self.content {
+            Content::Json(json) => json.get_path(path),
+            Content::Raw(_) => None,
+        }
+    }
+
+    fn any_value(&self, mut f: impl FnMut(&Value) -> bool) -> bool {
+        match &self.content {
+            Content::Json(json) => json.0.iter().any(|(_, value)| f(value)),
+            Content::Raw(_) => false,
+        }
+    }
+
+    fn source(&self) -> &str { &self.source }
+}
+
+/// Builds a case-insensitive regex matching `text` literally.
+fn text_regex(prefix: &str, text: &str, suffix: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(&format!("{prefix}{}{suffix}", regex::escape(text)))
+        .case_insensitive(true)
+        .build()
+}
+
+fn field_matches(condition: &FieldCondition, regex: Option<&Regex>, message: &impl Fields) -> bool {
+    if let FieldCondition::Source(label) = condition {
+        return message.source() == label.as_str();
+    }
+
+    let key = match condition.key() {
+        Some(key) => key,
+        None => {
+            let regex = regex.expect("AnyRegex is compiled with a regex");
+            return message.any_value(|value| any_value_matches(value, regex));
+        }
+    };
+
+    let field_value = match message.get_path(key) {
+        Some(value) => value,
+        None => return false, // no entries named `key`
+    };
+
+    match condition {
+        FieldCondition::HasKey(_) => true,
+        FieldCondition::KeyValue(_, value) => value_matches(field_value, value),
+        FieldCondition::Lt(_, bound) => {
+            bound.compare(field_value).is_some_and(cmp::Ordering::is_lt)
+        }
+        FieldCondition::Le(_, bound) => {
+            bound.compare(field_value).is_some_and(cmp::Ordering::is_le)
+        }
+        FieldCondition::Gt(_, bound) => {
+            bound.compare(field_value).is_some_and(cmp::Ordering::is_gt)
+        }
+        FieldCondition::Ge(_, bound) => {
+            bound.compare(field_value).is_some_and(cmp::Ordering::is_ge)
+        }
+        FieldCondition::Between(_, low, high) => {
+            low.compare(field_value).is_some_and(cmp::Ordering::is_ge)
+                && high.compare(field_value).is_some_and(cmp::Ordering::is_le)
+        }
+        FieldCondition::Regex(..)
+        | FieldCondition::Contains(..)
+        | FieldCondition::StartsWith(..)
+        | FieldCondition::EndsWith(..)
+        | FieldCondition::AnyRegex(_) => {
+            let regex = regex.expect("text matches are compiled with a regex");
+            text(field_value).is_some_and(|text| regex.is_match(&text))
+        }
+        FieldCondition::Source(_) => unreachable!("matched above"),
+    }
+}
+
+/// Whether `value` or any value nested in it matches `regex`.
+fn any_value_matches(value: &Value, regex: &Regex) -> bool {
+    match value {
+        Value::Array(items) => items.iter().any(|item| any_value_matches(item, regex)),
+        Value::Object(fields) => fields.iter().any(|(_, value)| any_value_matches(value, regex)),
+        value => text(value).is_some_and(|text| regex.is_match(&text)),
+    }
+}
+
+/// The text that text matches are applied to.
+///
+/// Numbers and booleans are matched by their textual representation.
+fn text(value: &Value) -> Option<Cow<'_, str>> {
+    match value {
+        Value::String(string) => Some(Cow::Borrowed(string)),
+        Value::Number(_) | Value::Bool(_) => Some(Cow::Owned(value.to_string())),
+        _ => None,
+    }
+}
+
+/// Compares a field value with the value in a condition.
+///
+/// A string condition also matches numbers and booleans with the same textual representation,
+/// since conditions typed by users cannot always tell the intended type.
+fn value_matches(field_value: &Value, value: &Value) -> bool {
+    match (field_value, value) {
+        (Value::Number(field_number), Value::Number(number)) => {
+            field_number == number || field_number.as_f64() == number.as_f64()
+        }
+        (Value::Number(_) | Value::Bool(_), Value::String(string)) => {
+            field_value.to_string() == string.as_str()
+        }
+        _ => field_value == value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arcstr::ArcStr;
+    use slv_proto::query::Query;
+    use slv_proto::{JsonEntry, Number, RawEntry};
+
+    use super::*;
+
+    fn string(string: &str) -> Value { Value::String(string.into()) }
+
+    fn uint(number: u64) -> Value { Value::Number(Number::UInt(number)) }
+
+    /// An entry with the fields `msg`, `status`, `ok` and `http.method`, read from `api.log`.
+    fn entry() -> Entry {
+        let http = Value::Object(Arc::from([("method".into(), string("GET"))]));
+        let fields = vec![
+            ("http".into(), http),
+            ("msg".into(), string("Connection reset by peer")),
+            ("ok".into(), Value::Bool(false)),
+            ("status".into(), uint(503)),
+        ];
+        Entry { source: "api.log".into(), content: Content::Json(JsonEntry(fields)) }
+    }
+
+    fn matches(query: &str) -> bool {
+        let condition = query.parse::<Query>().expect("valid query").condition;
+        let matcher = Matcher::compile(&condition, &Interner::default()).expect("valid regex");
+        matcher.matches(&entry())
+    }
+
+    #[test]
+    fn strings_match_numbers_and_booleans_by_text() {
+        assert!(value_matches(&uint(503), &string("503")));
+        assert!(value_matches(&Value::Bool(false), &string("false")));
+        assert!(!value_matches(&uint(503), &string("503.0")));
+        assert!(!value_matches(&string("503"), &uint(503)));
+        assert!(value_matches(&uint(2), &Value::Number(Number::Float(slv_proto::Float(2.0)))));
+        assert!(!value_matches(&Value::Null, &string("null")));
+    }
+
+    #[test]
+    fn key_values() {
+        assert!(matches("status=503"));
+        assert!(matches("status=\"503\""));
+        assert!(matches("http.method=GET"));
+        assert!(!matches("http.method=get"));
+        assert!(matches("ok=false"));
+        assert!(!matches("missing=503"));
+    }
+
+    #[test]
+    fn regexes() {
+        assert!(matches("msg~^Conn"));
+        assert!(!matches("msg~^conn"));
+        assert!(matches("msg~\"(?i)^conn\""));
+        assert!(matches("status~^5\\d\\d$"));
+        assert!(matches("ok~fal"));
+        assert!(!matches("http~GET")); // objects have no text
+        assert!(!matches("missing~."));
+    }
+
+    #[test]
+    fn substrings_ignore_case() {
+        assert!(matches("msg*=RESET"));
+        assert!(matches("msg^=connection"));
+        assert!(matches("msg$=PEER"));
+        assert!(!matches("msg^=reset"));
+        assert!(!matches("msg$=reset"));
+        assert!(matches("status*=03"));
+    }
+
+    #[test]
+    fn substrings_are_literal() {
+        assert!(!matches("msg*=\"Connection.reset\""));
+        assert!(!matches("msg*=.*"));
+        assert!(matches("msg*=\"n r\""));
+    }
+
+    #[test]
+    fn any_field_regex_includes_nested_fields() {
+        assert!(matches("~GET"));
+        assert!(matches("~^503$"));
+        assert!(!matches("~api"));
+    }
+
+    #[test]
+    fn combinators() {
+        assert!(matches("status=503 OR status=200"));
+        assert!(!matches("status=503 NOT msg*=reset"));
+        assert!(matches("NOT (status=200 OR missing=1)"));
+    }
+
+    #[test]
+    fn sources() {
+        assert!(matches("source:api.log"));
+        assert!(!matches("source:api"));
+    }
+
+    #[test]
+    fn compiling_does_not_intern_values() {
+        let interner = Interner::default();
+        let key = interner.key("level");
+        let info = interner.value(&key, "info");
+
+        let condition = "level=info OR level=warn".parse::<Query>().expect("valid query").condition;
+        let matcher = Matcher::compile(&condition, &interner).expect("no regex");
+        assert!(interner.get_value("level", "warn").is_none());
+
+        let values: Vec<_> = match &matcher {
+            Matcher::Or(children) => children
+                .iter()
+                .map(|child| match child {
+                    Matcher::Field { condition: FieldCondition::KeyValue(_, value), .. } => value,
+                    _ => panic!("expected key-value conditions"),
+                })
+                .collect(),
+            _ => panic!("expected an OR condition"),
+        };
+        assert!(matches!(values[0], Value::String(value) if ArcStr::ptr_eq(value, &info)));
+        assert!(matches!(values[1], Value::String(value) if value == "warn"));
+    }
+
+    #[test]
+    fn raw_entries_have_no_fields() {
+        let condition = "~.".parse::<Query>().expect("valid query").condition;
+        let matcher = Matcher::compile(&condition, &Interner::default()).expect("valid regex");
+        let raw =
+            Entry { source: "api.log".into(), content: Content::Raw(RawEntry(Arc::from(*b"x"))) };
+        assert!(!matcher.matches(&raw));
+    }
+}
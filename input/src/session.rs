@@ -1,9 +1,17 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
 use futures::channel::mpsc;
 use futures::{Sink, SinkExt, Stream, StreamExt as _};
 use slv_proto::{client, server};
+use tokio::sync::broadcast;
+use tokio::time;
 
 use crate::index;
 
+/// The interval to check for changes in the buffer usage reported to clients.
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
 pub async fn handle(
     stream: impl Stream<Item = client::Message> + Unpin,
     sink: impl Sink<server::Message, Error = mpsc::SendError> + Unpin,
@@ -19,10 +27,105 @@ async fn handle_fallible(
     mut sink: impl Sink<server::Message, Error = mpsc::SendError> + Unpin,
     index: &index::Store,
 ) -> Result<(), Error> {
-    while let Some(message) = stream.next().await {
-        match message {
-            client::Message::Handshake(_) => return Err(Error::MultiAuth),
-            client::Message::ListKeys(_) => {
+    let mut index_list = index.subscribe_index_list();
+    let (notification_tx, mut notification_rx) = mpsc::unbounded();
+    let mut subscriptions = Subscriptions { store: index, map: HashMap::new() };
+    let mut status_interval = time::interval(STATUS_INTERVAL);
+    let mut last_status = None;
+
+    // indices may exist before the session starts, e.g. when loaded from a snapshot
+    sink.send(server::Message::UpdateKeyList(index.list_indices())).await?;
+
+    loop {
+        tokio::select! {
+            message = stream.next() => {
+                let message = match message {
+                    Some(message) => message,
+                    None => break,
+                };
+
+                match message {
+                    client::Message::Handshake(_) => return Err(Error::MultiAuth),
+                    client::Message::ListKeys(_) => {
+                        let keys = index.list_indices();
+                        sink.send(server::Message::UpdateKeyList(keys)).await?;
+                    }
+                    client::Message::CreateIndex(method) => {
+                        if !index.create_index(method) {
+                            log::debug!("Client requested to create an existing index");
+                        }
+                    }
+                    client::Message::DropIndex(method) => {
+                        if !index.drop_index(&method) {
+                            log::debug!("Client requested to drop a nonexistent index");
+                        }
+                    }
+                    client::Message::FetchEntries(request) => {
+                        let page = index
+                            .page(&request.index, request.anchor, request.direction, request.limit)
+                            .unwrap_or_else(|| {
+                                log::debug!("Client requested entries from a nonexistent index");
+                                index::Page { entries: Vec::new(), next: None }
+                            });
+                        sink.send(server::Message::Entries(server::Entries {
+                            request_id: request.request_id,
+                            entries:    page.entries,
+                            next:       page.next,
+                        }))
+                        .await?;
+                    }
+                    client::Message::Subscribe(request) => {
+                        let subscription = index.subscribe(
+                            request.index,
+                            request.subscription_id,
+                            request.include_entries,
+                            notification_tx.clone(),
+                        );
+                        match subscription {
+                            Some(subscription) => {
+                                subscriptions.insert(request.subscription_id, subscription);
+                            }
+                            None => log::debug!("Client subscribed to a disabled full-text index"),
+                        }
+                    }
+                    client::Message::Unsubscribe(request) => {
+                        subscriptions.remove(request.subscription_id);
+                    }
+                    client::Message::KeyStats(request) => {
+                        let stats = index.key_stats(request.top_values);
+                        sink.send(server::Message::KeyStats(server::KeyStats {
+                            request_id: request.request_id,
+                            messages:   stats.messages,
+                            keys:       stats.keys,
+                        }))
+                        .await?;
+                    }
+                }
+            }
+            notification = notification_rx.next() => {
+                let notification = notification.expect("notification_tx is owned by this function");
+                let mut batch = vec![notification];
+                while let Ok(Some(notification)) = notification_rx.try_next() {
+                    batch.push(notification);
+                }
+
+                for message in batch_notifications(batch, &subscriptions) {
+                    sink.send(message).await?;
+                }
+            }
+            _ = status_interval.tick() => {
+                let status = index.status();
+                if last_status.as_ref() != Some(&status) {
+                    last_status = Some(status.clone());
+                    sink.send(server::Message::StatusFeed(status)).await?;
+                }
+            }
+            changed = index_list.recv() => {
+                if let Err(broadcast::error::RecvError::Closed) = changed {
+                    break;
+                }
+                // lagged receivers only need to send the latest list once
+
                 let keys = index.list_indices();
                 sink.send(server::Message::UpdateKeyList(keys)).await?;
             }
@@ -32,6 +135,83 @@ async fn handle_fallible(
     Ok(())
 }
 
+/// The subscriptions of a session, which are removed from the store when the session ends.
+struct Subscriptions<'t> {
+    store: &'t index::Store,
+    map:   HashMap<u64, index::Subscription>,
+}
+
+impl<'t> Subscriptions<'t> {
+    fn insert(&mut self, id: u64, subscription: index::Subscription) {
+        if let Some(old) = self.map.insert(id, subscription) {
+            self.store.unsubscribe(old);
+        }
+    }
+
+    fn remove(&mut self, id: u64) {
+        if let Some(subscription) = self.map.remove(&id) {
+            self.store.unsubscribe(subscription);
+        }
+    }
+}
+
+impl<'t> Drop for Subscriptions<'t> {
+    fn drop(&mut self) {
+        for (_, subscription) in self.map.drain() {
+            self.store.unsubscribe(subscription);
+        }
+    }
+}
+
+/// Merges consecutive notifications of the same subscription into one message.
+fn batch_notifications(
+    batch: Vec<index::Notification>,
+    subscriptions: &Subscriptions<'_>,
+) -> Vec<server::Message> {
+    let mut messages = Vec::new();
+
+    for notification in batch {
+        let subscription = match subscriptions.map.get(&notification.tag) {
+            Some(subscription) => subscription,
+            None => continue, // notification sent before unsubscription
+        };
+
+        match notification.event {
+            index::NotificationEvent::Appended(id, mut entry) => {
+                if entry.is_none() && subscription.include_entries() {
+                    // the subscription was created while the message was being pushed
+                    entry = subscriptions.store.get(id);
+                }
+
+                match messages.last_mut() {
+                    Some(server::Message::Appended(appended))
+                        if appended.subscription_id == notification.tag =>
+                    {
+                        appended.entries.push((id, entry));
+                    }
+                    _ => messages.push(server::Message::Appended(server::Appended {
+                        subscription_id: notification.tag,
+                        entries:         vec![(id, entry)],
+                    })),
+                }
+            }
+            index::NotificationEvent::Evicted(id) => match messages.last_mut() {
+                Some(server::Message::Evicted(evicted))
+                    if evicted.subscription_id == notification.tag =>
+                {
+                    evicted.until = id;
+                }
+                _ => messages.push(server::Message::Evicted(server::Evicted {
+                    subscription_id: notification.tag,
+                    until:           id,
+                })),
+            },
+        }
+    }
+
+    messages
+}
+
 #[derive(Debug, thiserror::Error)]
 enum Error {
     #[error("Client should not send auth message multiple times")]
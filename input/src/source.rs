@@ -1,30 +1,187 @@
-use std::collections::BTreeMap;
-use std::convert::Infallible;
+use std::ffi::OsString;
+use std::os::unix::fs::MetadataExt as _;
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::sync::Arc;
 use std::time::Duration;
 
+use arcstr::ArcStr;
+use futures::future::Either;
 use futures::{future, Future, Stream, StreamExt as _};
 use inotify::{Inotify, WatchMask};
-use slv_proto::{Entry, JsonEntry, RawEntry};
+use parking_lot::RwLock;
+use slv_proto::server::{SourceState, SourceStatus};
+use slv_proto::{Content, Entry, JsonEntry, Number, RawEntry, Value};
 use tokio::io::{self, AsyncBufReadExt as _, AsyncSeekExt as _};
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, Notify};
 use tokio::{fs, time};
 
+use self::checkpoint::{Checkpoint, Checkpoints};
+use self::compression::Compression;
+use self::dir::{DirInput, DirWatch};
+use crate::interner::Interner;
+
+mod checkpoint;
+mod compression;
+mod dir;
+
+/// Interval to save the checkpoint file at, if it changed.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Opens all inputs and returns a future reading each of them in its own loop
+/// until all inputs end or `shutdown` is received.
+///
+/// Messages are labelled with their input, see `label`,
+/// and the state of each input is reported to `statuses`.
+/// If `options.checkpoint` is set, the read positions of watched files are saved periodically
+/// and once more after reading stops.
 pub async fn init(
     options: Options,
-    receiver: impl Fn(Entry),
+    interner: Arc<Interner>,
+    statuses: Arc<Statuses>,
+    receiver: impl Fn(Entry) + Clone,
     shutdown: broadcast::Receiver<()>,
 ) -> Result<impl Future<Output = ()>, InitError> {
-    let input = if options.input.as_os_str() == "-" {
+    if options.input.iter().filter(|path| is_stdin(path)).count() > 1 {
+        return Err(InitError::DuplicateStdin);
+    }
+
+    let checkpoints = match &options.checkpoint {
+        Some(path) => Some(Arc::new(
+            Checkpoints::load(path.clone())
+                .map_err(|err| InitError::Checkpoint(path.clone(), err))?,
+        )),
+        None => None,
+    };
+
+    let mut inputs = Vec::with_capacity(options.input.len());
+    let mut dirs = Vec::new();
+    for path in &options.input {
+        match DirInput::parse(path).await? {
+            Some(dir) => dirs.push(
+                DirWatch::new(
+                    dir,
+                    &options,
+                    checkpoints.clone(),
+                    Arc::clone(&statuses),
+                    Arc::clone(&interner),
+                    receiver.clone(),
+                    shutdown.resubscribe(),
+                )
+                .map_err(|err| InitError::OpenInput(path.clone(), err))?,
+            ),
+            None => {
+                let input = open(&options, path, checkpoints.as_ref()).await?;
+                inputs.push((input, interner.source(&label(path))));
+            }
+        }
+    }
+
+    let loops = inputs.into_iter().map(|(input, source)| {
+        let position = statuses.add(source.clone(), SourceState::Reading);
+        let status = StatusHandle { statuses: Arc::clone(&statuses), position };
+        Either::Left(watch_loop(
+            input,
+            source,
+            status,
+            Arc::clone(&interner),
+            receiver.clone(),
+            shutdown.resubscribe(),
+        ))
+    });
+    let dirs = dirs.into_iter().map(|dir| Either::Right(dir.run()));
+    let reading = future::join_all(loops.chain(dirs));
+
+    Ok(async move {
+        let checkpoints = match checkpoints {
+            Some(checkpoints) => checkpoints,
+            None => {
+                reading.await;
+                return;
+            }
+        };
+
+        tokio::pin!(reading);
+        let mut interval = time::interval(CHECKPOINT_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = &mut reading => break,
+                _ = interval.tick() => save_checkpoints(&checkpoints),
+            }
+        }
+        save_checkpoints(&checkpoints);
+    })
+}
+
+fn save_checkpoints(checkpoints: &Checkpoints) {
+    if let Err(err) = checkpoints.save() {
+        log::error!("Cannot save checkpoint file {}: {err}", checkpoints.path().display());
+    }
+}
+
+/// The states of the inputs of a store, reported to clients in `server::StatusFeed`.
+#[derive(Default)]
+pub(crate) struct Statuses {
+    list: RwLock<Vec<SourceStatus>>,
+}
+
+impl Statuses {
+    /// Registers an input, returning its position in the list.
+    ///
+    /// An input with the same label as an earlier one, such as a file in a watched directory
+    /// that was removed and created again, takes over its entry.
+    pub(crate) fn add(&self, label: ArcStr, state: SourceState) -> usize {
+        let mut list = self.list.write();
+        if let Some(position) = list.iter().position(|status| status.label == label) {
+            list[position].state = state;
+            return position;
+        }
+        list.push(SourceStatus { label, state });
+        list.len() - 1
+    }
+
+    pub(crate) fn list(&self) -> Vec<SourceStatus> { self.list.read().clone() }
+}
+
+/// Updates the state of one input in `Statuses`.
+struct StatusHandle {
+    statuses: Arc<Statuses>,
+    position: usize,
+}
+
+impl StatusHandle {
+    fn set(&self, state: SourceState) { self.statuses.list.write()[self.position].state = state; }
+}
+
+fn is_stdin(path: &Path) -> bool { path.as_os_str() == "-" }
+
+/// The source label of the messages read from `path`.
+fn label(path: &Path) -> String {
+    if is_stdin(path) {
+        String::from("stdin")
+    } else {
+        path.display().to_string()
+    }
+}
+
+async fn open(
+    options: &Options,
+    path: &Path,
+    checkpoints: Option<&Arc<Checkpoints>>,
+) -> Result<Input, InitError> {
+    Ok(if is_stdin(path) {
         Input::stream(io::BufReader::new(Box::pin(io::stdin())))
-    } else if options.watch {
+    } else if options.watch
+        && !options.stop_at_eof
+        // compressed files do not grow, so they are read to their end without watching,
+        // while missing files are watched until they are created
+        && !matches!(Compression::detect(path).await, Ok(Some(_)))
+    {
         let inotify = if options.inotify {
-            match setup_inotify(&options.input) {
+            match setup_file_inotify(path) {
                 Ok(inotify) => Some(inotify),
                 Err(err) => {
-                    log::warn!("Cannot enable inotify for input: {err}");
+                    log::warn!("Cannot enable inotify for {}: {err}", path.display());
                     None
                 }
             }
@@ -37,171 +194,572 @@ pub async fn init(
             None => Notifier::Timer { interval: options.watch_interval.into(), current: None },
         };
 
-        Input::watch_file(options.input.clone(), notifier)
+        Input::watch_file(path.to_path_buf(), notifier, checkpoints.cloned(), false)
     } else {
-        let file = fs::File::open(&options.input).await.map_err(InitError::OpenInput)?;
-        Input::stream(io::BufReader::new(Box::pin(file)))
-    };
+        open_stream(path).await.map_err(|err| InitError::OpenInput(path.to_path_buf(), err))?
+    })
+}
 
-    Ok(watch_loop(input, receiver, shutdown))
+/// Opens a file to read to its end, decompressing it if it is compressed.
+async fn open_stream(path: &Path) -> io::Result<Input> {
+    let file = fs::File::open(path).await?;
+    Ok(match Compression::detect(path).await? {
+        Some(compression) => {
+            log::debug!("Decompressing {} as {compression}", path.display());
+            Input::stream(compression.decoder(io::BufReader::new(file)))
+        }
+        None => Input::stream(file),
+    })
 }
 
 type InotifyStream = Pin<Box<dyn Stream<Item = io::Result<inotify::EventOwned>> + Send>>;
 
-fn setup_inotify(path: &Path) -> io::Result<InotifyStream> {
+/// Watches the directory of `path` rather than the file itself,
+/// since a watch on the file would follow the old inode when the file is rotated.
+fn setup_file_inotify(path: &Path) -> io::Result<(InotifyStream, OsString)> {
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "input path has no file name")
+    })?;
+    Ok((setup_inotify(parent_dir(path))?, name.to_os_string()))
+}
+
+/// The directory containing `path`, which is `.` for a bare file name.
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// Watches the files in a directory for changes, creation, renaming and removal.
+fn setup_inotify(dir: &Path) -> io::Result<InotifyStream> {
     let mut inotify = Inotify::init()?;
-    inotify.add_watch(path, WatchMask::MODIFY | WatchMask::MOVE | WatchMask::CREATE)?;
+    inotify.add_watch(
+        dir,
+        WatchMask::MODIFY | WatchMask::CREATE | WatchMask::MOVE | WatchMask::DELETE,
+    )?;
     Ok(Box::pin(inotify.event_stream([0; 4096])?))
 }
 
 enum Input {
-    Stream {
-        reader: io::BufReader<Pin<Box<dyn io::AsyncRead + Send>>>,
-        buf:    Vec<u8>,
-    },
+    /// A pipe or a file that is not watched, which ends at EOF.
+    Stream { reader: io::BufReader<Pin<Box<dyn io::AsyncRead + Send>>>, buf: Vec<u8> },
+    /// A file that is followed across truncation and rotation.
     WatchFile {
-        path:         PathBuf,
-        notifier:     Notifier,
-        previous_len: u64,
-        file:         Option<io::BufReader<fs::File>>,
-        buf:          Vec<u8>,
+        path:             PathBuf,
+        notifier:         Notifier,
+        checkpoints:      Option<Arc<Checkpoints>>,
+        /// Whether the input ends when the file is removed,
+        /// instead of waiting for a new file to be created at the path.
+        end_when_removed: bool,
+        /// The open file, or `None` if it has not been opened or has been rotated.
+        file:             Option<WatchedFile>,
+        /// Whether the last attempt to open the file failed.
+        open_failed:      bool,
+        buf:              Vec<u8>,
     },
 }
 
+struct WatchedFile {
+    reader:   io::BufReader<fs::File>,
+    /// The device and inode number of the file,
+    /// which differ from those of the path once the file is rotated.
+    id:       (u64, u64),
+    /// Number of bytes read from the file.
+    offset:   u64,
+    /// Whether the path refers to another file,
+    /// so the file is read to its end once more before switching to the new one.
+    replaced: bool,
+}
+
+impl WatchedFile {
+    /// Opens the file at `path`, resuming from `checkpoint` if it is still valid for the file.
+    async fn open(path: &Path, checkpoint: Option<Checkpoint>) -> io::Result<Self> {
+        let mut file = fs::File::open(path).await?;
+        let metadata = file.metadata().await?;
+        let id = (metadata.dev(), metadata.ino());
+
+        // the file may have been rotated or truncated since the checkpoint was saved
+        let offset = match checkpoint {
+            Some(Checkpoint { dev, ino, offset })
+                if (dev, ino) == id && offset <= metadata.len() =>
+            {
+                log::info!("Resuming {} from byte {offset}", path.display());
+                file.seek(io::SeekFrom::Start(offset)).await?;
+                offset
+            }
+            _ => 0,
+        };
+
+        Ok(Self { reader: io::BufReader::new(file), id, offset, replaced: false })
+    }
+}
+
 impl Input {
     fn stream(reader: impl io::AsyncRead + Send + 'static) -> Self {
         let reader = io::BufReader::new(Box::pin(reader) as Pin<Box<dyn io::AsyncRead + Send>>);
         Self::Stream { reader, buf: Vec::new() }
     }
 
-    fn watch_file(path: PathBuf, notifier: Notifier) -> Self {
-        Self::WatchFile { path, notifier, previous_len: 0, file: None, buf: Vec::new() }
+    fn watch_file(
+        path: PathBuf,
+        notifier: Notifier,
+        checkpoints: Option<Arc<Checkpoints>>,
+        end_when_removed: bool,
+    ) -> Self {
+        Self::WatchFile {
+            path,
+            notifier,
+            checkpoints,
+            end_when_removed,
+            file: None,
+            open_failed: false,
+            buf: Vec::new(),
+        }
     }
 
     /// Reads the next line, cancel-safe
-    async fn next_line(&mut self) -> io::Result<Entry> {
+    ///
+    /// Returns `None` at the end of a stream.
+    async fn next_line(&mut self, interner: &Interner) -> io::Result<Option<Content>> {
         let message = match self {
             Self::Stream { reader, buf } => {
                 let len = reader.read_until(b'\n', buf).await?;
                 if len == 0 {
-                    future::pending::<Infallible>().await;
+                    return Ok(None);
                 }
 
-                let message = parse_entry(&buf[..]);
-                buf.clear();
-                message
+                take_line(buf, interner)
             }
-            Self::WatchFile { notifier, previous_len, path, file, buf } => loop {
-                let file = match file {
-                    Some(file) => {
-                        let metadata = fs::metadata(path.as_path()).await?;
-                        if metadata.len() != *previous_len {
-                            // file truncated, seek from start
-                            file.seek(io::SeekFrom::Start(0)).await?;
+            Self::WatchFile {
+                path,
+                notifier,
+                checkpoints,
+                end_when_removed,
+                file,
+                open_failed,
+                buf,
+            } => loop {
+                let watched = match file {
+                    Some(watched) => watched,
+                    None => {
+                        let checkpoint = checkpoints.as_ref().and_then(|c| c.get(path));
+                        match WatchedFile::open(path, checkpoint).await {
+                            Ok(watched) => {
+                                *open_failed = false;
+                                file.insert(watched)
+                            }
+                            Err(err)
+                                if *end_when_removed && err.kind() == io::ErrorKind::NotFound =>
+                            {
+                                return Ok(None);
+                            }
+                            // report the error once, then wait for the file to appear
+                            Err(err) if !*open_failed => {
+                                *open_failed = true;
+                                return Err(err);
+                            }
+                            Err(_) => {
+                                notifier.wait().await?;
+                                continue;
+                            }
                         }
-                        *previous_len = metadata.len();
-                        file
                     }
-                    None => file.insert(io::BufReader::new(fs::File::open(path.as_path()).await?)),
                 };
 
-                let read = file.read_until(b'\n', buf).await?;
-                if read == 0 {
-                    // EOF, go to re-read loop
-                    notifier.wait().await?;
+                let read = watched.reader.read_until(b'\n', buf).await?;
+                watched.offset += read as u64;
+                if buf.ends_with(b"\n") {
+                    if let Some(checkpoints) = checkpoints {
+                        let (dev, ino) = watched.id;
+                        checkpoints.set(path, Checkpoint { dev, ino, offset: watched.offset });
+                    }
+                    break take_line(buf, interner);
+                }
+
+                // EOF, possibly after a partial line, which is kept in `buf`
+                // until the writer completes it.
+                // Check whether the file was truncated or rotated.
+                if watched.replaced {
+                    log::info!("{} was rotated, reading the new file", path.display());
+                    *file = None;
+                    if !buf.is_empty() {
+                        break take_line(buf, interner); // the old file ended without a newline
+                    }
                     continue;
                 }
+                match fs::metadata(path.as_path()).await {
+                    Ok(metadata) if (metadata.dev(), metadata.ino()) == watched.id => {
+                        if metadata.len() < watched.offset {
+                            // truncated in place, e.g. by logrotate with `copytruncate`
+                            log::info!("{} was truncated, reading from start", path.display());
+                            watched.reader.seek(io::SeekFrom::Start(0)).await?;
+                            watched.offset = 0;
+                            if !buf.is_empty() {
+                                break take_line(buf, interner);
+                            }
+                            continue;
+                        }
+                    }
+                    Ok(_) => {
+                        // replaced by a new file, e.g. by logrotate with `create`;
+                        // drain what was written to the old file in the meantime
+                        watched.replaced = true;
+                        continue;
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::NotFound && *end_when_removed => {
+                        // the file was read to its end above, and ends when it cannot be reopened
+                        *file = None;
+                        if !buf.is_empty() {
+                            break take_line(buf, interner);
+                        }
+                        continue;
+                    }
+                    // renamed but not yet recreated, keep following the old file
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                    Err(err) => return Err(err),
+                }
 
-                let message = parse_entry(&buf[..]);
-                buf.clear();
-                break message;
+                notifier.wait().await?;
             },
         };
-        Ok(message)
+        Ok(Some(message))
     }
 }
 
+/// Parses the line in `buf` and clears it for the next line.
+fn take_line(buf: &mut Vec<u8>, interner: &Interner) -> Content {
+    let message = parse_entry(&buf[..], interner);
+    buf.clear();
+    message
+}
+
 enum Notifier {
-    Inotify { inotify: InotifyStream },
-    Timer { interval: Duration, current: Option<time::Instant> },
+    /// Events in the directory of the file, filtered by the file name.
+    Inotify {
+        inotify: InotifyStream,
+        name:    OsString,
+    },
+    Timer {
+        interval: Duration,
+        current:  Option<time::Instant>,
+    },
+    /// Woken by the `dir::DirWatch` following the file.
+    Directory {
+        notify: Arc<Notify>,
+    },
 }
 
 impl Notifier {
-    fn inotify(inotify: InotifyStream) -> Self { Self::Inotify { inotify } }
+    fn inotify((inotify, name): (InotifyStream, OsString)) -> Self {
+        Self::Inotify { inotify, name }
+    }
 
     async fn wait(&mut self) -> io::Result<()> {
         match self {
-            Self::Inotify { inotify } => {
-                let result = inotify.next().await.expect("InotifyStream never closes");
-                result?; // inotify error
-            }
+            Self::Inotify { inotify, name } => loop {
+                let event = inotify.next().await.expect("InotifyStream never closes")?;
+                if event.name.as_deref() == Some(name.as_os_str()) {
+                    break;
+                }
+            },
             Self::Timer { interval, current } => {
                 let until = current.get_or_insert_with(|| time::Instant::now() + *interval);
                 time::sleep_until(*until).await;
+                *current = None;
             }
+            Self::Directory { notify } => notify.notified().await,
         }
         Ok(())
     }
 }
 
-fn parse_entry(bytes: &[u8]) -> Entry {
-    match serde_json::from_slice::<BTreeMap<_, _>>(bytes) {
-        Ok(fields) => Entry::Json(JsonEntry({
-            let fields: Vec<_> = fields.into_iter().collect();
-            assert!(fields.windows(2).all(|pair| pair[0].0 < pair[1].0)); // BTreeMap iterates in order
-            fields
-        })),
+/// Parses a line as a JSON object, or as a raw line if it is not one.
+///
+/// Keys and low-cardinality string values are interned.
+fn parse_entry(bytes: &[u8], interner: &Interner) -> Content {
+    match serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(bytes) {
+        Ok(fields) => Content::Json(JsonEntry(convert_object(fields, interner))),
         Err(_) => {
-            let stripped = bytes.strip_suffix(b"\r\n").unwrap_or(bytes);
-            Entry::Raw(RawEntry(Arc::from(stripped)))
+            let stripped = bytes.strip_suffix(b"\n").unwrap_or(bytes);
+            let stripped = stripped.strip_suffix(b"\r").unwrap_or(stripped);
+            Content::Raw(RawEntry(Arc::from(stripped)))
         }
     }
 }
 
+fn convert_object(
+    fields: serde_json::Map<String, serde_json::Value>,
+    interner: &Interner,
+) -> Vec<(ArcStr, Value)> {
+    let fields: Vec<_> = fields
+        .into_iter()
+        .map(|(key, value)| {
+            let key = interner.key(&key);
+            let value = convert_value(value, &key, interner);
+            (key, value)
+        })
+        .collect();
+    assert!(fields.windows(2).all(|pair| pair[0].0 < pair[1].0)); // serde_json::Map is a BTreeMap
+    fields
+}
+
+/// Converts the value of the field `key`, or an item of it if it is an array.
+fn convert_value(value: serde_json::Value, key: &ArcStr, interner: &Interner) -> Value {
+    match value {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(bool) => Value::Bool(bool),
+        serde_json::Value::Number(number) => Value::Number(if let Some(number) = number.as_u64() {
+            Number::UInt(number)
+        } else if let Some(number) = number.as_i64() {
+            Number::from_i64(number)
+        } else {
+            Number::Float(slv_proto::Float(
+                number.as_f64().expect("arbitrary_precision is disabled"),
+            ))
+        }),
+        serde_json::Value::String(string) => Value::String(interner.value(key, &string)),
+        serde_json::Value::Array(items) => {
+            Value::Array(items.into_iter().map(|item| convert_value(item, key, interner)).collect())
+        }
+        serde_json::Value::Object(fields) => Value::Object(convert_object(fields, interner).into()),
+    }
+}
+
+/// Reads messages from one input until it ends or `shutdown` is received.
 async fn watch_loop(
     mut input: Input,
+    source: ArcStr,
+    status: StatusHandle,
+    interner: Arc<Interner>,
     mut receiver: impl FnMut(Entry),
     mut shutdown: broadcast::Receiver<()>,
 ) {
+    // only changes are written to `status`, since errors may repeat on every poll
+    let mut last_error = None;
     loop {
-        let message = tokio::select! {
+        let content = tokio::select! {
             _ = shutdown.recv() => break,
-            message = input.next_line() => message,
+            content = input.next_line(&interner) => content,
         };
 
-        let message = match message {
-            Ok(message) => message,
+        let content = match content {
+            Ok(Some(content)) => content,
+            Ok(None) => {
+                log::info!("Reached the end of {source}");
+                status.set(SourceState::Ended);
+                break;
+            }
             Err(err) => {
-                log::error!("Cannot poll message: {err}");
+                log::error!("Cannot poll message from {source}: {err}");
+                let err = err.to_string();
+                if last_error.as_ref() != Some(&err) {
+                    status.set(SourceState::Failed(err.clone()));
+                    last_error = Some(err);
+                }
                 continue;
             }
         };
+        if last_error.take().is_some() {
+            status.set(SourceState::Reading);
+        }
 
-        receiver(message);
+        receiver(Entry { source: source.clone(), content });
     }
 }
 
 #[derive(clap::Parser)]
 pub struct Options {
-    /// Path to log file, or `-` to read from stdin.
+    /// Paths to log files, or `-` to read from stdin.
+    ///
+    /// Files compressed with gzip, zstd or xz are decompressed,
+    /// and read to their end without watching.
+    ///
+    /// A directory or a path with wildcards in its file name, such as `'/var/log/app/*.json'`,
+    /// reads all matching files, oldest first, and, unless `--no-watch`,
+    /// follows the uncompressed ones and files created later.
+    /// Directories skip hidden files.
+    ///
+    /// Messages from all inputs are read into the same buffer,
+    /// labelled with the path of their file or `stdin`.
     #[clap(value_parser, default_value = "-")]
-    pub input: PathBuf,
+    pub input: Vec<PathBuf>,
 
-    /// Watch file for updates. No effect if input is stdin.
+    /// Watch files for updates, following them when truncated or rotated. No effect on stdin.
     #[clap(long = "no-watch", action = clap::ArgAction::SetFalse)]
     pub watch:          bool,
-    /// Use inotify to watch file. No effect if `--no-watch`.
+    /// Use inotify to watch files. No effect if `--no-watch`.
     #[clap(long = "no-inotify", action = clap::ArgAction::SetFalse)]
     pub inotify:        bool,
     /// The interval to try to read new data from a file, if inotify is unavailable.
     #[clap(long, value_parser, default_value_t = Duration::from_millis(10).into())]
     pub watch_interval: humantime::Duration,
+
+    /// Save the read positions of watched files to this file,
+    /// and resume reading from them when restarted with the same file.
+    ///
+    /// Files are read from the start if they were rotated or truncated in the meantime.
+    /// Compressed files are not watched, so they are always read in full.
+    /// No effect if `--no-watch`.
+    #[clap(long, value_parser)]
+    pub checkpoint: Option<PathBuf>,
+
+    /// Stop reading files at their end instead of watching them for more data.
+    #[clap(skip)]
+    pub stop_at_eof: bool,
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum InitError {
-    #[error("Failed to open input file: {0}")]
-    OpenInput(io::Error),
+    #[error("Failed to open input file {}: {1}", .0.display())]
+    OpenInput(PathBuf, io::Error),
+    #[error("Failed to load checkpoint file {}: {1}", .0.display())]
+    Checkpoint(PathBuf, io::Error),
+    #[error("Invalid file name pattern in {}: {1}", .0.display())]
+    Pattern(PathBuf, glob::PatternError),
+    #[error("Standard input can only be read once")]
+    DuplicateStdin,
     #[error("Failed to set up inotify for input file: {0}")]
     Inotify(io::Error),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{append, TempDir};
+
+    fn watch(path: &Path, checkpoints: Option<Arc<Checkpoints>>, end_when_removed: bool) -> Input {
+        let notifier = Notifier::Timer { interval: Duration::from_millis(5), current: None };
+        Input::watch_file(path.to_path_buf(), notifier, checkpoints, end_when_removed)
+    }
+
+    /// Reads the next line, or returns `None` if there is none within a short time.
+    async fn next(input: &mut Input, interner: &Interner) -> Option<String> {
+        let content = time::timeout(Duration::from_millis(200), input.next_line(interner))
+            .await
+            .ok()?
+            .expect("file is readable")?;
+        match content {
+            Content::Raw(raw) => Some(String::from_utf8(raw.0.to_vec()).expect("written as UTF-8")),
+            Content::Json(_) => panic!("only raw lines are written"),
+        }
+    }
+
+    #[tokio::test]
+    async fn follows_file_truncated_in_place() {
+        let dir = TempDir::new("source-copytruncate");
+        let path = dir.path("app.log");
+        let interner = Interner::default();
+        append(&path, "first line\nsecond line\n");
+
+        let mut input = watch(&path, None, false);
+        assert_eq!(next(&mut input, &interner).await.as_deref(), Some("first line"));
+        assert_eq!(next(&mut input, &interner).await.as_deref(), Some("second line"));
+
+        std::fs::copy(&path, dir.path("app.log.1")).expect("temp dir is writable");
+        std::fs::File::create(&path).expect("temp dir is writable"); // truncates
+        append(&path, "new\n");
+        assert_eq!(next(&mut input, &interner).await.as_deref(), Some("new"));
+        assert_eq!(next(&mut input, &interner).await, None);
+    }
+
+    #[tokio::test]
+    async fn follows_file_replaced_by_rotation() {
+        let dir = TempDir::new("source-create");
+        let path = dir.path("app.log");
+        let interner = Interner::default();
+        append(&path, "before\n");
+
+        let mut input = watch(&path, None, false);
+        assert_eq!(next(&mut input, &interner).await.as_deref(), Some("before"));
+
+        let rotated = dir.path("app.log.1");
+        std::fs::rename(&path, &rotated).expect("temp dir is writable");
+        // written by the application before it reopens the path
+        append(&rotated, "late\n");
+        append(&path, "fresh\n");
+
+        assert_eq!(next(&mut input, &interner).await.as_deref(), Some("late"));
+        assert_eq!(next(&mut input, &interner).await.as_deref(), Some("fresh"));
+        append(&path, "more\n");
+        assert_eq!(next(&mut input, &interner).await.as_deref(), Some("more"));
+    }
+
+    #[tokio::test]
+    async fn appended_lines_are_read_once() {
+        let dir = TempDir::new("source-append");
+        let path = dir.path("app.log");
+        let interner = Interner::default();
+        append(&path, "a\nb\n");
+
+        let mut input = watch(&path, None, false);
+        assert_eq!(next(&mut input, &interner).await.as_deref(), Some("a"));
+        assert_eq!(next(&mut input, &interner).await.as_deref(), Some("b"));
+        assert_eq!(next(&mut input, &interner).await, None);
+
+        append(&path, "c\n");
+        assert_eq!(next(&mut input, &interner).await.as_deref(), Some("c"));
+        assert_eq!(next(&mut input, &interner).await, None);
+    }
+
+    #[tokio::test]
+    async fn partial_line_is_completed_by_later_write() {
+        let dir = TempDir::new("source-partial");
+        let path = dir.path("app.log");
+        let interner = Interner::default();
+        append(&path, "whole\npar");
+
+        let mut input = watch(&path, None, false);
+        assert_eq!(next(&mut input, &interner).await.as_deref(), Some("whole"));
+        assert_eq!(next(&mut input, &interner).await, None);
+
+        append(&path, "tial\n");
+        assert_eq!(next(&mut input, &interner).await.as_deref(), Some("partial"));
+    }
+
+    #[tokio::test]
+    async fn resumes_from_checkpoint() {
+        let dir = TempDir::new("source-checkpoint");
+        let path = dir.path("app.log");
+        let checkpoint = dir.path("checkpoint.json");
+        let interner = Interner::default();
+        append(&path, "a\nb\npar");
+
+        {
+            let checkpoints = Arc::new(Checkpoints::load(checkpoint.clone()).expect("no file yet"));
+            let mut input = watch(&path, Some(Arc::clone(&checkpoints)), false);
+            assert_eq!(next(&mut input, &interner).await.as_deref(), Some("a"));
+            assert_eq!(next(&mut input, &interner).await.as_deref(), Some("b"));
+            assert_eq!(next(&mut input, &interner).await, None);
+            checkpoints.save().expect("temp dir is writable");
+        }
+
+        append(&path, "tial\n");
+        let checkpoints = Arc::new(Checkpoints::load(checkpoint).expect("saved above"));
+        let mut input = watch(&path, Some(checkpoints), false);
+        assert_eq!(next(&mut input, &interner).await.as_deref(), Some("partial"));
+        assert_eq!(next(&mut input, &interner).await, None);
+    }
+
+    #[tokio::test]
+    async fn checkpoint_of_replaced_file_is_ignored() {
+        let dir = TempDir::new("source-checkpoint-replaced");
+        let path = dir.path("app.log");
+        let checkpoint = dir.path("checkpoint.json");
+        let interner = Interner::default();
+        append(&path, "old\n");
+
+        {
+            let checkpoints = Arc::new(Checkpoints::load(checkpoint.clone()).expect("no file yet"));
+            let mut input = watch(&path, Some(Arc::clone(&checkpoints)), false);
+            assert_eq!(next(&mut input, &interner).await.as_deref(), Some("old"));
+            checkpoints.save().expect("temp dir is writable");
+        }
+
+        std::fs::rename(&path, dir.path("app.log.1")).expect("temp dir is writable");
+        append(&path, "new\n");
+        let checkpoints = Arc::new(Checkpoints::load(checkpoint).expect("saved above"));
+        let mut input = watch(&path, Some(checkpoints), false);
+        assert_eq!(next(&mut input, &interner).await.as_deref(), Some("new"));
+    }
+}
@@ -0,0 +1,78 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use parking_lot::Mutex;
+
+/// The read position in a watched file.
+#[derive(Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub(super) struct Checkpoint {
+    /// The device and inode number of the file,
+    /// so that a file rotated while slv was not running is read from the start.
+    pub(super) dev:    u64,
+    pub(super) ino:    u64,
+    /// The offset after the last complete line read.
+    pub(super) offset: u64,
+}
+
+/// The read positions of the watched files, saved to a JSON file
+/// so that a restarted slv resumes reading where it left off.
+///
+/// Files are identified by their paths as given on the command line.
+pub(super) struct Checkpoints {
+    path:  PathBuf,
+    state: Mutex<State>,
+}
+
+struct State {
+    files: BTreeMap<PathBuf, Checkpoint>,
+    /// Whether `files` changed since it was last saved.
+    dirty: bool,
+}
+
+impl Checkpoints {
+    /// Loads the checkpoint file at `path`, which is created on the first save if it does not exist.
+    pub(super) fn load(path: PathBuf) -> io::Result<Self> {
+        let files = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(err),
+        };
+        Ok(Self { path, state: Mutex::new(State { files, dirty: false }) })
+    }
+
+    pub(super) fn get(&self, file: &Path) -> Option<Checkpoint> {
+        self.state.lock().files.get(file).copied()
+    }
+
+    /// Updates the read position in `file`, called after every line.
+    pub(super) fn set(&self, file: &Path, checkpoint: Checkpoint) {
+        let mut state = self.state.lock();
+        match state.files.get_mut(file) {
+            Some(existing) => *existing = checkpoint,
+            None => _ = state.files.insert(file.to_path_buf(), checkpoint),
+        }
+        state.dirty = true;
+    }
+
+    /// Writes the checkpoint file if any position changed since the last save.
+    ///
+    /// The file is replaced atomically, so that it is intact if slv is killed while saving.
+    pub(super) fn save(&self) -> io::Result<()> {
+        let files = {
+            let mut state = self.state.lock();
+            if !state.dirty {
+                return Ok(());
+            }
+            state.dirty = false;
+            state.files.clone()
+        };
+
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        std::fs::write(&temp, serde_json::to_vec_pretty(&files)?)?;
+        std::fs::rename(&temp, &self.path)
+    }
+
+    pub(super) fn path(&self) -> &Path { &self.path }
+}
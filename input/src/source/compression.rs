@@ -0,0 +1,162 @@
+use std::fmt;
+use std::path::Path;
+use std::pin::Pin;
+
+use async_compression::tokio::bufread::{GzipDecoder, XzDecoder, ZstdDecoder};
+use tokio::fs;
+use tokio::io::{self, AsyncReadExt as _};
+
+/// The longest magic number of a `Compression`.
+const MAGIC_LEN: usize = 6;
+
+/// A compression format that input files are transparently decompressed from.
+#[derive(Clone, Copy)]
+pub(super) enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    const ALL: [Self; 3] = [Self::Gzip, Self::Zstd, Self::Xz];
+
+    fn magic(self) -> &'static [u8] {
+        match self {
+            Self::Gzip => &[0x1f, 0x8b],
+            Self::Zstd => &[0x28, 0xb5, 0x2f, 0xfd],
+            Self::Xz => &[0xfd, b'7', b'z', b'X', b'Z', 0],
+        }
+    }
+
+    fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Self::Gzip => &["gz"],
+            Self::Zstd => &["zst", "zstd"],
+            Self::Xz => &["xz"],
+        }
+    }
+
+    /// Detects the compression of the file at `path` from its magic number,
+    /// or from its extension if the file is too short to tell, such as while it is being written.
+    ///
+    /// Returns `None` for uncompressed files.
+    pub(super) async fn detect(path: &Path) -> io::Result<Option<Self>> {
+        let mut file = fs::File::open(path).await?;
+        let mut head = [0; MAGIC_LEN];
+        let mut len = 0;
+        while len < MAGIC_LEN {
+            match file.read(&mut head[len..]).await? {
+                0 => break,
+                read => len += read,
+            }
+        }
+
+        if let Some(compression) =
+            Self::ALL.into_iter().find(|c| head[..len].starts_with(c.magic()))
+        {
+            return Ok(Some(compression));
+        }
+        if len == MAGIC_LEN {
+            return Ok(None);
+        }
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        Ok(Self::ALL.into_iter().find(|c| extension.is_some_and(|e| c.extensions().contains(&e))))
+    }
+
+    /// Wraps `reader` in a streaming decoder.
+    ///
+    /// Concatenated streams, such as appended gzip members, are decoded one after another.
+    pub(super) fn decoder(
+        self,
+        reader: impl io::AsyncBufRead + Send + 'static,
+    ) -> Pin<Box<dyn io::AsyncRead + Send>> {
+        match self {
+            Self::Gzip => {
+                let mut decoder = GzipDecoder::new(reader);
+                decoder.multiple_members(true);
+                Box::pin(decoder)
+            }
+            Self::Zstd => {
+                let mut decoder = ZstdDecoder::new(reader);
+                decoder.multiple_members(true);
+                Box::pin(decoder)
+            }
+            Self::Xz => {
+                let mut decoder = XzDecoder::new(reader);
+                decoder.multiple_members(true);
+                Box::pin(decoder)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+            Self::Xz => "xz",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_compression::tokio::bufread::{GzipEncoder, XzEncoder, ZstdEncoder};
+
+    use super::*;
+    use crate::test_util::TempDir;
+
+    async fn read_all(mut reader: impl io::AsyncRead + Unpin) -> Vec<u8> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).await.expect("valid stream");
+        data
+    }
+
+    async fn compress(compression: Compression, data: &[u8]) -> Vec<u8> {
+        match compression {
+            Compression::Gzip => read_all(GzipEncoder::new(data)).await,
+            Compression::Zstd => read_all(ZstdEncoder::new(data)).await,
+            Compression::Xz => read_all(XzEncoder::new(data)).await,
+        }
+    }
+
+    async fn detect(name: &str, data: &[u8]) -> Option<String> {
+        let dir = TempDir::new(&format!("compression-{name}"));
+        let path = dir.path(name);
+        std::fs::write(&path, data).expect("temp dir is writable");
+        let compression = Compression::detect(&path).await.expect("file exists");
+        compression.map(|compression| compression.to_string())
+    }
+
+    #[tokio::test]
+    async fn detects_magic_numbers() {
+        for compression in Compression::ALL {
+            let data = compress(compression, b"line\n").await;
+            // the extension is ignored if the magic number can be read
+            let detected = detect(&format!("{compression}.log"), &data).await;
+            assert_eq!(detected, Some(compression.to_string()));
+        }
+        assert_eq!(detect("plain.gz", b"{\"level\":\"info\"}\n").await, None);
+    }
+
+    #[tokio::test]
+    async fn detects_extension_of_short_files() {
+        assert_eq!(detect("short.gz", b"").await.as_deref(), Some("gzip"));
+        assert_eq!(detect("short.zst", &[0x28, 0xb5]).await.as_deref(), Some("zstd"));
+        assert_eq!(detect("short.zstd", b"").await.as_deref(), Some("zstd"));
+        assert_eq!(detect("short.xz", &[0xfd]).await.as_deref(), Some("xz"));
+        assert_eq!(detect("short.log", b"a\n").await, None);
+    }
+
+    #[tokio::test]
+    async fn decodes_concatenated_members() {
+        for compression in Compression::ALL {
+            let mut data = compress(compression, b"first\n").await;
+            data.extend(compress(compression, b"second\n").await);
+
+            let decoded = read_all(compression.decoder(std::io::Cursor::new(data))).await;
+            assert_eq!(decoded, b"first\nsecond\n", "{compression}");
+        }
+    }
+}
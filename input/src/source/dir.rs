@@ -0,0 +1,605 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::os::unix::fs::MetadataExt as _;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use arcstr::ArcStr;
+use futures::stream::FuturesUnordered;
+use futures::{Future, StreamExt as _};
+use inotify::EventMask;
+use slv_proto::server::SourceState;
+use slv_proto::Entry;
+use tokio::sync::{broadcast, Notify};
+use tokio::{fs, io, time};
+
+use super::checkpoint::Checkpoints;
+use super::compression::Compression;
+use super::{
+    label, open_stream, setup_inotify, watch_loop, InitError, InotifyStream, Input, Notifier,
+    Options, StatusHandle, Statuses,
+};
+use crate::interner::Interner;
+
+/// The files in a directory that an input refers to.
+pub(super) struct DirInput {
+    /// The directory as given, which is empty for a bare file name pattern.
+    dir:     PathBuf,
+    /// The pattern of file names, or `None` for all files except hidden ones.
+    pattern: Option<glob::Pattern>,
+}
+
+impl DirInput {
+    /// Returns the files that `path` refers to,
+    /// or `None` if `path` is neither a directory nor has wildcards in its file name.
+    ///
+    /// Wildcards are only supported in the file name, not in the directories.
+    pub(super) async fn parse(path: &Path) -> Result<Option<Self>, InitError> {
+        if fs::metadata(path).await.is_ok_and(|metadata| metadata.is_dir()) {
+            return Ok(Some(Self { dir: path.to_path_buf(), pattern: None }));
+        }
+
+        let name = match path.file_name().and_then(OsStr::to_str) {
+            Some(name) if name.contains(['*', '?', '[']) => name,
+            _ => return Ok(None),
+        };
+        let pattern =
+            glob::Pattern::new(name).map_err(|err| InitError::Pattern(path.to_path_buf(), err))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+        Ok(Some(Self { dir, pattern: Some(pattern) }))
+    }
+
+    fn matches(&self, name: &OsStr) -> bool {
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => return false,
+        };
+        match &self.pattern {
+            Some(pattern) => {
+                let options =
+                    glob::MatchOptions { require_literal_leading_dot: true, ..Default::default() };
+                pattern.matches_with(name, options)
+            }
+            None => !name.starts_with('.'),
+        }
+    }
+
+    /// The directory to list, which is `.` for a bare file name pattern.
+    fn dir(&self) -> &Path {
+        if self.dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            &self.dir
+        }
+    }
+
+    fn path(&self, name: &OsStr) -> PathBuf { self.dir.join(name) }
+
+    /// Lists the matching regular files, oldest first.
+    async fn list(&self) -> io::Result<Listing> {
+        let mut entries = fs::read_dir(self.dir()).await?;
+        let mut files = Vec::new();
+        let mut complete = true;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            if !self.matches(&name) {
+                continue;
+            }
+            match fs::metadata(self.path(&name)).await {
+                Ok(metadata) if metadata.is_file() => {
+                    let replaced = metadata.ino() != entry.ino()
+                        && entry.file_type().await.is_ok_and(|file_type| file_type.is_file());
+                    if replaced {
+                        complete = false; // renamed since listed, and replaced by another file
+                    }
+                    let id = (metadata.dev(), metadata.ino());
+                    files.push((metadata.modified().ok(), name, id));
+                }
+                Ok(_) => {}                 // not a file
+                Err(_) => complete = false, // removed or renamed since listed
+            }
+        }
+        files.sort();
+        let files = files.into_iter().map(|(_, name, id)| (name, id)).collect();
+        Ok(Listing { files, complete })
+    }
+}
+
+/// The matching regular files in a directory, see `DirInput::list`.
+struct Listing {
+    /// The names of the files with their device and inode numbers, oldest first.
+    files:    Vec<(OsString, (u64, u64))>,
+    /// Whether every listed file still existed when its metadata was read,
+    /// otherwise a file renamed meanwhile may be missing under both names.
+    complete: bool,
+}
+
+/// Follows the matching files in a directory,
+/// including files that are created later, until `shutdown` is received.
+///
+/// The files in the directory are read oldest first.
+/// Compressed files are rotated archives, which are read to their end before the others,
+/// while the uncompressed files are followed like single watched files
+/// until they are removed.
+/// Compressed files created later are not read,
+/// since they are archives of files that were already read,
+/// and neither are followed files renamed within the directory, e.g. by `delaycompress`.
+///
+/// Without watching, all files are read to their end one after another.
+pub(super) struct DirWatch<R> {
+    input:        DirInput,
+    /// Changes in the directory, or `None` if not watching.
+    events:       Option<Events>,
+    checkpoints:  Option<Arc<Checkpoints>>,
+    statuses:     Arc<Statuses>,
+    interner:     Arc<Interner>,
+    receiver:     R,
+    shutdown:     broadcast::Receiver<()>,
+    /// Wakes the reader of each followed file, by file name.
+    followers:    HashMap<OsString, Arc<Notify>>,
+    /// The names of the compressed files, which are not followed.
+    archives:     HashSet<OsString>,
+    /// The cookie of the last move of a followed file,
+    /// which also identifies the new name if the file was moved within the directory.
+    moved_cookie: Option<u32>,
+    /// The device and inode numbers of the files that were followed,
+    /// to recognize them by when listing the directory after they were renamed.
+    ///
+    /// Only pruned when listing, so not checked for inotify events,
+    /// since the inode of a removed file may be reused by a new file in the meantime.
+    followed_ids: HashSet<(u64, u64)>,
+}
+
+enum Events {
+    /// Events in the directory, shared by the readers of all files in it.
+    Inotify(InotifyStream),
+    /// Interval to list the directory and wake all readers at, if inotify is unavailable.
+    Timer(time::Interval),
+}
+
+enum Event {
+    Changed {
+        name:   OsString,
+        mask:   EventMask,
+        cookie: u32,
+    },
+    /// Events may have been missed, e.g. when the inotify queue overflowed.
+    Rescan,
+}
+
+impl Events {
+    async fn next(&mut self) -> io::Result<Event> {
+        match self {
+            Self::Inotify(inotify) => {
+                let event = inotify.next().await.expect("InotifyStream never closes")?;
+                Ok(match event.name {
+                    Some(name) => Event::Changed { name, mask: event.mask, cookie: event.cookie },
+                    None => Event::Rescan,
+                })
+            }
+            Self::Timer(interval) => {
+                interval.tick().await;
+                Ok(Event::Rescan)
+            }
+        }
+    }
+}
+
+impl<R: FnMut(Entry) + Clone> DirWatch<R> {
+    /// Starts watching the directory unless `--no-watch`,
+    /// so that files created before `run` is called are not missed.
+    pub(super) fn new(
+        input: DirInput,
+        options: &Options,
+        checkpoints: Option<Arc<Checkpoints>>,
+        statuses: Arc<Statuses>,
+        interner: Arc<Interner>,
+        receiver: R,
+        shutdown: broadcast::Receiver<()>,
+    ) -> io::Result<Self> {
+        let dir = input.dir();
+        std::fs::read_dir(dir)?; // fail early if the directory cannot be listed
+
+        let events = if options.watch && !options.stop_at_eof {
+            let inotify = if options.inotify {
+                match setup_inotify(dir) {
+                    Ok(inotify) => Some(inotify),
+                    Err(err) => {
+                        log::warn!("Cannot enable inotify for {}: {err}", dir.display());
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+
+            Some(match inotify {
+                Some(inotify) => Events::Inotify(inotify),
+                None => {
+                    let mut interval = time::interval(options.watch_interval.into());
+                    interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+                    Events::Timer(interval)
+                }
+            })
+        } else {
+            None
+        };
+
+        Ok(Self {
+            input,
+            events,
+            checkpoints,
+            statuses,
+            interner,
+            receiver,
+            shutdown,
+            followers: HashMap::new(),
+            archives: HashSet::new(),
+            moved_cookie: None,
+            followed_ids: HashSet::new(),
+        })
+    }
+
+    pub(super) async fn run(mut self) {
+        let events = self.events.take();
+        let files = match self.input.list().await {
+            Ok(listing) => listing.files,
+            Err(err) => {
+                log::error!("Cannot list directory {}: {err}", self.input.dir().display());
+                Vec::new()
+            }
+        };
+
+        let mut live = Vec::new();
+        for (name, id) in files {
+            let path = self.input.path(&name);
+            if events.is_some() && !matches!(Compression::detect(&path).await, Ok(Some(_))) {
+                live.push((name, id));
+                continue;
+            }
+
+            if !matches!(self.shutdown.try_recv(), Err(broadcast::error::TryRecvError::Empty)) {
+                return; // shutdown received, or all senders dropped
+            }
+            self.archives.insert(name);
+            self.read_to_end(path).await;
+        }
+
+        let mut events = match events {
+            Some(events) => events,
+            None => return,
+        };
+        let mut readers = FuturesUnordered::new();
+        for (name, id) in live {
+            readers.push(self.follow(name, id));
+        }
+
+        loop {
+            let candidates = tokio::select! {
+                _ = self.shutdown.recv() => break,
+                Some(name) = readers.next() => {
+                    // the file may have been created again before its reader ended
+                    self.followers.remove(&name);
+                    vec![name]
+                }
+                event = events.next() => match event {
+                    Ok(Event::Changed { name, mask, cookie }) => {
+                        self.changed(name, mask, cookie).await.into_iter().collect()
+                    }
+                    Ok(Event::Rescan) => {
+                        for notify in self.followers.values() {
+                            notify.notify_one();
+                        }
+                        self.scan().await
+                    }
+                    Err(err) => {
+                        log::error!("Cannot watch directory {}: {err}", self.input.dir().display());
+                        Vec::new()
+                    }
+                },
+            };
+
+            for name in candidates {
+                if let Some(id) = self.should_follow(&name).await {
+                    readers.push(self.follow(name, id));
+                }
+            }
+        }
+    }
+
+    /// Returns the names of the matching files that are neither followed nor archives,
+    /// nor were followed under another name.
+    async fn scan(&mut self) -> Vec<OsString> {
+        let Listing { files, complete } = match self.input.list().await {
+            Ok(listing) => listing,
+            Err(err) => {
+                log::error!("Cannot list directory {}: {err}", self.input.dir().display());
+                return Vec::new();
+            }
+        };
+
+        // a follower reads the file at its path, which may have been replaced since it started
+        let mut followed_ids: HashSet<_> = files
+            .iter()
+            .filter(|&(name, id)| {
+                self.followers.contains_key(name) || self.followed_ids.contains(id)
+            })
+            .map(|&(_, id)| id)
+            .collect();
+        let mut candidates = Vec::new();
+        for (name, id) in files {
+            if self.followers.contains_key(&name) || self.archives.contains(&name) {
+                continue;
+            }
+            if followed_ids.contains(&id) {
+                // e.g. rotated from `app.log` to `app.log.1` between two scans
+                log::debug!(
+                    "Not following {}, renamed from a followed file",
+                    name.to_string_lossy()
+                );
+                continue;
+            }
+            candidates.push(name);
+        }
+        if !complete {
+            followed_ids.extend(self.followed_ids.drain());
+        }
+        self.followed_ids = followed_ids;
+        candidates
+    }
+
+    /// Handles an inotify event for the file `name`,
+    /// returning the name if the file may need to be followed from now on.
+    async fn changed(&mut self, name: OsString, mask: EventMask, cookie: u32) -> Option<OsString> {
+        if mask.contains(EventMask::ISDIR) {
+            return None;
+        }
+
+        if let Some(notify) = self.followers.get(&name) {
+            if mask.contains(EventMask::MOVED_FROM) {
+                self.moved_cookie = Some(cookie);
+            }
+            notify.notify_one();
+            return None;
+        }
+        if self.archives.contains(&name) {
+            if mask.intersects(EventMask::DELETE | EventMask::MOVED_FROM) {
+                self.archives.remove(&name);
+            }
+            return None;
+        }
+
+        if !mask.intersects(EventMask::CREATE | EventMask::MOVED_TO) {
+            return None;
+        }
+        if mask.contains(EventMask::MOVED_TO) && self.moved_cookie == Some(cookie) {
+            // e.g. rotated from `app.log` to `app.log.1`, which was read as `app.log`
+            log::debug!("Not following {}, renamed from a followed file", name.to_string_lossy());
+            if let Ok(metadata) = fs::metadata(self.input.path(&name)).await {
+                self.followed_ids.insert((metadata.dev(), metadata.ino()));
+            }
+            return None;
+        }
+        Some(name)
+    }
+
+    /// Whether to start following the file `name`, which is not followed yet,
+    /// returning its device and inode numbers if so.
+    async fn should_follow(&mut self, name: &OsStr) -> Option<(u64, u64)> {
+        let path = self.input.path(name);
+        if !self.input.matches(name) {
+            return None;
+        }
+        let id = match fs::metadata(&path).await {
+            Ok(metadata) if metadata.is_file() => (metadata.dev(), metadata.ino()),
+            _ => return None,
+        };
+        if matches!(Compression::detect(&path).await, Ok(Some(_))) {
+            log::debug!("Not reading {}, archived after the directory was read", path.display());
+            self.archives.insert(name.to_os_string());
+            return None;
+        }
+        Some(id)
+    }
+
+    /// Registers the file at `path` in the statuses, returning its source label.
+    fn register(&self, path: &Path) -> (ArcStr, StatusHandle) {
+        let source = self.interner.source(&label(path));
+        let position = self.statuses.add(source.clone(), SourceState::Reading);
+        (source, StatusHandle { statuses: Arc::clone(&self.statuses), position })
+    }
+
+    /// Reads the file at `path` to its end without watching it.
+    async fn read_to_end(&mut self, path: PathBuf) {
+        let (source, status) = self.register(&path);
+        match open_stream(&path).await {
+            Ok(input) => {
+                watch_loop(
+                    input,
+                    source,
+                    status,
+                    Arc::clone(&self.interner),
+                    self.receiver.clone(),
+                    self.shutdown.resubscribe(),
+                )
+                .await;
+            }
+            Err(err) => {
+                log::error!("Cannot open {}: {err}", path.display());
+                status.set(SourceState::Failed(err.to_string()));
+            }
+        }
+    }
+
+    /// Returns the reader of the file `name` with the device and inode numbers `id`,
+    /// which returns the name when the file is removed.
+    fn follow(&mut self, name: OsString, id: (u64, u64)) -> impl Future<Output = OsString> {
+        let path = self.input.path(&name);
+        log::info!("Following {}", path.display());
+
+        let notify = Arc::new(Notify::new());
+        self.followers.insert(name.clone(), Arc::clone(&notify));
+        self.followed_ids.insert(id);
+
+        let (source, status) = self.register(&path);
+        let input =
+            Input::watch_file(path, Notifier::Directory { notify }, self.checkpoints.clone(), true);
+        let reader = watch_loop(
+            input,
+            source,
+            status,
+            Arc::clone(&self.interner),
+            self.receiver.clone(),
+            self.shutdown.resubscribe(),
+        );
+        async move {
+            reader.await;
+            name
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use async_compression::tokio::bufread::GzipEncoder;
+    use clap::Parser as _;
+    use parking_lot::Mutex;
+    use tokio::io::AsyncReadExt as _;
+
+    use super::*;
+    use crate::test_util::{append, wait_until, TempDir};
+
+    /// What a `DirWatch` created by `watch` reported.
+    struct Watched {
+        statuses: Arc<Statuses>,
+        /// The received lines, prefixed with their file names.
+        received: Arc<Mutex<Vec<String>>>,
+        shutdown: broadcast::Sender<()>,
+    }
+
+    impl Watched {
+        fn received(&self) -> Vec<String> { self.received.lock().clone() }
+
+        fn state(&self, path: &Path) -> Option<SourceState> {
+            let label = path.display().to_string();
+            let list = self.statuses.list();
+            list.into_iter().find(|status| status.label == label).map(|status| status.state)
+        }
+    }
+
+    /// Creates a `DirWatch` for `path` that polls for changes without inotify.
+    async fn watch(path: &Path) -> (DirWatch<impl FnMut(Entry) + Clone>, Watched) {
+        let options = Options::parse_from(["slv", "--no-inotify", "--watch-interval", "5ms"]);
+        let input = DirInput::parse(path).await.expect("valid pattern").expect("directory input");
+        let statuses = Arc::new(Statuses::default());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let receiver = {
+            let received = Arc::clone(&received);
+            move |entry: Entry| {
+                let name =
+                    Path::new(entry.source.as_str()).file_name().expect("labelled with path");
+                let line = match entry.content {
+                    slv_proto::Content::Raw(raw) => String::from_utf8_lossy(&raw.0).into_owned(),
+                    slv_proto::Content::Json(_) => panic!("only raw lines are written"),
+                };
+                received.lock().push(format!("{}: {line}", name.to_string_lossy()));
+            }
+        };
+        let (shutdown_tx, shutdown) = broadcast::channel(1);
+        let watch = DirWatch::new(
+            input,
+            &options,
+            None,
+            Arc::clone(&statuses),
+            Arc::default(),
+            receiver,
+            shutdown,
+        )
+        .expect("dir exists");
+        (watch, Watched { statuses, received, shutdown: shutdown_tx })
+    }
+
+    #[tokio::test]
+    async fn follows_new_files_until_removed() {
+        let dir = TempDir::new("dir-follow");
+        append(&dir.path("a.log"), "a1\n");
+        append(&dir.path("ignored.txt"), "ignored\n");
+
+        let (watch, watched) = watch(&dir.path("*.log")).await;
+        let steps = async {
+            wait_until(|| watched.received() == ["a.log: a1"]).await;
+
+            append(&dir.path("b.log"), "b1\n");
+            wait_until(|| watched.received().len() == 2).await;
+            append(&dir.path("a.log"), "a2\n");
+            wait_until(|| watched.received().len() == 3).await;
+            assert_eq!(watched.received(), ["a.log: a1", "b.log: b1", "a.log: a2"]);
+
+            std::fs::remove_file(dir.path("a.log")).expect("created above");
+            wait_until(|| watched.state(&dir.path("a.log")) == Some(SourceState::Ended)).await;
+            assert!(watched.state(&dir.path("b.log")) == Some(SourceState::Reading));
+
+            watched.shutdown.send(()).expect("watch is running");
+        };
+        tokio::join!(watch.run(), steps);
+    }
+
+    #[tokio::test]
+    async fn reads_archives_before_following_uncompressed_files() {
+        let dir = TempDir::new("dir-archives");
+        let mut archive = GzipEncoder::new(&b"archived\n"[..]);
+        let mut compressed = Vec::new();
+        archive.read_to_end(&mut compressed).await.expect("in memory");
+        std::fs::write(dir.path("app.log.2.gz"), compressed).expect("temp dir is writable");
+        append(&dir.path("app.log.1"), "rotated\n");
+        append(&dir.path("app.log"), "current\n");
+
+        let (watch, watched) = watch(&dir.0).await;
+        let steps = async {
+            wait_until(|| watched.received().len() == 3).await;
+            let mut received = watched.received();
+            assert_eq!(received[0], "app.log.2.gz: archived");
+            received[1..].sort();
+            assert_eq!(received[1..], ["app.log.1: rotated", "app.log: current"]);
+            assert!(watched.state(&dir.path("app.log.2.gz")) == Some(SourceState::Ended));
+
+            // another application may still write to older files
+            append(&dir.path("app.log.1"), "late\n");
+            wait_until(|| watched.received().len() == 4).await;
+            assert_eq!(watched.received()[3], "app.log.1: late");
+            assert!(watched.state(&dir.path("app.log.1")) == Some(SourceState::Reading));
+
+            watched.shutdown.send(()).expect("watch is running");
+        };
+        tokio::join!(watch.run(), steps);
+    }
+
+    #[tokio::test]
+    async fn renamed_file_is_not_read_again() {
+        let dir = TempDir::new("dir-renamed");
+        append(&dir.path("app.log"), "first\n");
+
+        let (watch, watched) = watch(&dir.0).await;
+        let steps = async {
+            wait_until(|| watched.received() == ["app.log: first"]).await;
+
+            // rotated twice, as with `delaycompress` but without compressing
+            for (line, rotated) in [("second\n", "app.log.1"), ("third\n", "app.log.2")] {
+                std::fs::rename(dir.path("app.log"), dir.path(rotated)).expect("created above");
+                time::sleep(Duration::from_millis(20)).await; // a few scans between the steps
+                append(&dir.path("app.log"), line);
+                time::sleep(Duration::from_millis(20)).await;
+            }
+            wait_until(|| watched.received().len() == 3).await;
+            time::sleep(Duration::from_millis(20)).await;
+            assert_eq!(watched.received(), ["app.log: first", "app.log: second", "app.log: third"]);
+            assert!(watched.state(&dir.path("app.log.1")).is_none());
+            assert!(watched.state(&dir.path("app.log.2")).is_none());
+
+            watched.shutdown.send(()).expect("watch is running");
+        };
+        tokio::join!(watch.run(), steps);
+    }
+}
@@ -0,0 +1,49 @@
+//! Fixtures shared by the tests of this crate.
+
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+use tokio::time;
+
+/// A directory for the files of one test, removed on drop.
+///
+/// `test` must be unique among the tests of this crate.
+pub(crate) struct TempDir(pub(crate) PathBuf);
+
+impl TempDir {
+    pub(crate) fn new(test: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("slv-test-{}-{test}", process::id()));
+        _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).expect("temp dir is writable");
+        Self(dir)
+    }
+
+    pub(crate) fn path(&self, name: &str) -> PathBuf { self.0.join(name) }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) { _ = std::fs::remove_dir_all(&self.0); }
+}
+
+/// Appends `data` to the file at `path`, creating it if necessary.
+pub(crate) fn append(path: &Path, data: &str) {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .expect("temp dir is writable");
+    file.write_all(data.as_bytes()).expect("temp dir is writable");
+}
+
+/// Waits until `done` returns true, panicking after a few seconds.
+pub(crate) async fn wait_until(mut done: impl FnMut() -> bool) {
+    for _ in 0..1000 {
+        if done() {
+            return;
+        }
+        time::sleep(Duration::from_millis(5)).await;
+    }
+    panic!("timed out");
+}
@@ -0,0 +1,328 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use time::format_description::well_known::Rfc3339;
+use time::{OffsetDateTime, Time};
+
+use crate::{Float, Number, Value};
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+/// A bound of a range condition.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Deserialize, serde::Serialize,
+)]
+pub enum Bound {
+    /// Compared numerically with numbers and numeric strings,
+    /// and as a Unix timestamp with RFC3339 strings.
+    Number(Number),
+    /// A point in time, in nanoseconds since the Unix epoch.
+    ///
+    /// Compared chronologically with RFC3339 strings and Unix timestamps.
+    Time(i64),
+    /// A time of day, in nanoseconds since midnight.
+    ///
+    /// Compared with the time of day of RFC3339 strings in their own offset,
+    /// or of Unix timestamps in UTC.
+    TimeOfDay(u64),
+}
+
+impl Bound {
+    /// Compares a field value with this bound.
+    ///
+    /// Returns `None` if the value cannot be interpreted as the type of this bound.
+    pub fn compare(&self, value: &Value) -> Option<Ordering> {
+        match *self {
+            Self::Number(bound) => {
+                let number = match value {
+                    Value::Number(number) => *number,
+                    Value::String(string) => match parse_number(string) {
+                        Some(number) => number,
+                        // e.g. `time>1700000000` for fields such as `2023-11-14T22:13:20Z`
+                        None => {
+                            let datetime = OffsetDateTime::parse(string, &Rfc3339).ok()?;
+                            return Some(datetime.cmp(&to_datetime(&Value::Number(bound))?));
+                        }
+                    },
+                    _ => return None,
+                };
+                compare_numbers(number, bound)
+            }
+            Self::Time(bound) => {
+                Some(to_datetime(value)?.unix_timestamp_nanos().cmp(&i128::from(bound)))
+            }
+            Self::TimeOfDay(bound) => {
+                let time = match value {
+                    Value::String(string) => match parse_time_of_day(string) {
+                        Some(time) => time,
+                        None => to_datetime(value)?.time(),
+                    },
+                    _ => to_datetime(value)?.time(),
+                };
+                Some(time_of_day_nanos(time).cmp(&bound))
+            }
+        }
+    }
+}
+
+impl FromStr for Bound {
+    type Err = ();
+
+    /// Parses a number, an RFC3339 timestamp or a time of day in the form `HH:MM[:SS[.fff]]`.
+    fn from_str(string: &str) -> Result<Self, ()> {
+        if let Some(number) = parse_number(string) {
+            return Ok(Self::Number(number));
+        }
+        if let Ok(datetime) = OffsetDateTime::parse(string, &Rfc3339) {
+            // out of range for years beyond 1677..=2262
+            let nanos = i64::try_from(datetime.unix_timestamp_nanos()).map_err(|_| ())?;
+            return Ok(Self::Time(nanos));
+        }
+        if let Some(time) = parse_time_of_day(string) {
+            return Ok(Self::TimeOfDay(time_of_day_nanos(time)));
+        }
+        Err(())
+    }
+}
+
+impl fmt::Display for Bound {
+    /// Formats the bound such that it parses back to the same bound.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Number(number) => write!(f, "{number}"),
+            Self::Time(nanos) => {
+                let datetime = OffsetDateTime::from_unix_timestamp_nanos(nanos.into())
+                    .expect("constructed from a valid timestamp");
+                let formatted =
+                    datetime.format(&Rfc3339).expect("UTC timestamps are valid RFC3339");
+                write!(f, "{formatted}")
+            }
+            Self::TimeOfDay(nanos) => {
+                let seconds = nanos / NANOS_PER_SECOND;
+                write!(f, "{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)?;
+                let fraction = nanos % NANOS_PER_SECOND;
+                if fraction > 0 {
+                    let digits = format!("{fraction:09}");
+                    write!(f, ".{}", digits.trim_end_matches('0'))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Parses a number, excluding special floats such as `inf` and `NaN`.
+pub(crate) fn parse_number(string: &str) -> Option<Number> {
+    if let Ok(number) = string.parse::<u64>() {
+        return Some(Number::UInt(number));
+    }
+    if let Ok(number) = string.parse::<i64>() {
+        return Some(Number::from_i64(number));
+    }
+    // exclude words like `inf` and `NaN` that Rust would accept as floats
+    let numeric =
+        string.chars().all(|ch| ch.is_ascii_digit() || matches!(ch, '+' | '-' | '.' | 'e' | 'E'));
+    match string.parse::<f64>() {
+        Ok(number) if numeric => Some(Number::Float(Float(number))),
+        _ => None,
+    }
+}
+
+fn compare_numbers(left: Number, right: Number) -> Option<Ordering> {
+    match (left, right) {
+        (Number::UInt(left), Number::UInt(right)) => Some(left.cmp(&right)),
+        (Number::Int(left), Number::Int(right)) => Some(left.cmp(&right)),
+        // all `Int`s are negative
+        (Number::UInt(_), Number::Int(_)) => Some(Ordering::Greater),
+        (Number::Int(_), Number::UInt(_)) => Some(Ordering::Less),
+        (left, right) => left.as_f64().partial_cmp(&right.as_f64()),
+    }
+}
+
+/// Interprets a value as a point in time.
+///
+/// Numbers are Unix timestamps, in seconds, milliseconds, microseconds or nanoseconds
+/// depending on their magnitude.
+fn to_datetime(value: &Value) -> Option<OffsetDateTime> {
+    match value {
+        Value::String(string) => OffsetDateTime::parse(string, &Rfc3339).ok(),
+        Value::Number(number) => {
+            let value = number.as_f64();
+            let nanos = match value.abs() {
+                magnitude if magnitude < 1e11 => value * 1e9,
+                magnitude if magnitude < 1e14 => value * 1e6,
+                magnitude if magnitude < 1e17 => value * 1e3,
+                _ => value,
+            };
+            let nanos = match *number {
+                // avoid float rounding for integral nanoseconds
+                Number::UInt(nanos) if value >= 1e17 => i128::from(nanos),
+                Number::Int(nanos) if value <= -1e17 => i128::from(nanos),
+                _ => nanos as i128,
+            };
+            OffsetDateTime::from_unix_timestamp_nanos(nanos).ok()
+        }
+        _ => None,
+    }
+}
+
+/// Parses a time of day in the form `HH:MM[:SS[.fff]]`.
+fn parse_time_of_day(string: &str) -> Option<Time> {
+    let mut parts = string.splitn(3, ':');
+    let hour = parse_digits(parts.next()?)?;
+    let minute = parse_digits(parts.next()?)?;
+    let (second, nanos) = match parts.next() {
+        None => (0, 0),
+        Some(rest) => {
+            let (second, fraction) = match rest.split_once('.') {
+                Some((second, fraction)) => (second, Some(fraction)),
+                None => (rest, None),
+            };
+            let nanos = match fraction {
+                Some(fraction) if !fraction.is_empty() && fraction.len() <= 9 => {
+                    let digits = format!("{fraction:0<9}");
+                    u32::try_from(parse_digits(&digits)?).ok()?
+                }
+                Some(_) => return None,
+                None => 0,
+            };
+            (parse_digits(second)?, nanos)
+        }
+    };
+    Time::from_hms_nano(
+        hour.try_into().ok()?,
+        minute.try_into().ok()?,
+        second.try_into().ok()?,
+        nanos,
+    )
+    .ok()
+}
+
+fn parse_digits(string: &str) -> Option<u64> {
+    if string.is_empty() || !string.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    string.parse().ok()
+}
+
+fn time_of_day_nanos(time: Time) -> u64 {
+    let (hour, minute, second, nanos) = time.as_hms_nano();
+    let seconds = u64::from(hour) * 3600 + u64::from(minute) * 60 + u64::from(second);
+    seconds * NANOS_PER_SECOND + u64::from(nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering::{Equal, Greater, Less};
+
+    use super::*;
+
+    fn bound(string: &str) -> Bound { string.parse().unwrap_or_else(|()| panic!("{string}")) }
+
+    fn string(string: &str) -> Value { Value::String(string.into()) }
+
+    fn uint(number: u64) -> Value { Value::Number(Number::UInt(number)) }
+
+    fn float(number: f64) -> Value { Value::Number(Number::Float(Float(number))) }
+
+    #[test]
+    fn parse_numbers() {
+        assert_eq!(bound("42"), Bound::Number(Number::UInt(42)));
+        assert_eq!(bound("-42"), Bound::Number(Number::Int(-42)));
+        assert_eq!(bound("1.5"), Bound::Number(Number::Float(Float(1.5))));
+        assert_eq!(bound("1e3"), Bound::Number(Number::Float(Float(1000.0))));
+        assert!("inf".parse::<Bound>().is_err());
+        assert!("NaN".parse::<Bound>().is_err());
+        assert!("".parse::<Bound>().is_err());
+    }
+
+    #[test]
+    fn parse_timestamps() {
+        assert_eq!(bound("1970-01-01T00:00:01Z"), Bound::Time(1_000_000_000));
+        assert_eq!(bound("1970-01-01T01:00:00.5+01:00"), Bound::Time(500_000_000));
+        assert!("2024-01-01".parse::<Bound>().is_err());
+        assert!("3000-01-01T00:00:00Z".parse::<Bound>().is_err());
+    }
+
+    #[test]
+    fn parse_times_of_day() {
+        assert_eq!(bound("10:05"), Bound::TimeOfDay((10 * 3600 + 5 * 60) * NANOS_PER_SECOND));
+        assert_eq!(bound("00:00:01.25"), Bound::TimeOfDay(1_250_000_000));
+        assert!("24:00".parse::<Bound>().is_err());
+        assert!("10:60".parse::<Bound>().is_err());
+        assert!("10:05:00.".parse::<Bound>().is_err());
+        assert!("10:05:00.1234567890".parse::<Bound>().is_err());
+        assert!("10:".parse::<Bound>().is_err());
+    }
+
+    #[test]
+    fn display_parses_back() {
+        for string in ["42", "-42", "1.5", "2024-01-02T03:04:05.678Z", "10:05:00", "23:59:59.5"] {
+            let parsed = bound(string);
+            assert_eq!(bound(&parsed.to_string()), parsed, "{string}");
+        }
+    }
+
+    #[test]
+    fn compare_numbers() {
+        assert_eq!(bound("10").compare(&uint(9)), Some(Less));
+        assert_eq!(bound("10").compare(&uint(10)), Some(Equal));
+        assert_eq!(bound("-1").compare(&uint(0)), Some(Greater));
+        assert_eq!(bound("0").compare(&Value::Number(Number::Int(-1))), Some(Less));
+        assert_eq!(bound("1.5").compare(&uint(2)), Some(Greater));
+        assert_eq!(bound("2").compare(&float(1.5)), Some(Less));
+        assert_eq!(bound("10").compare(&string("9.5")), Some(Less));
+        assert_eq!(bound("10").compare(&string("ten")), None);
+        assert_eq!(bound("10").compare(&Value::Bool(true)), None);
+        assert_eq!(bound("10").compare(&Value::Null), None);
+    }
+
+    #[test]
+    fn compare_numbers_with_timestamps() {
+        // 1700000000 is 2023-11-14T22:13:20Z
+        let bound = bound("1700000000");
+        assert_eq!(bound.compare(&string("2023-11-14T22:13:19Z")), Some(Less));
+        assert_eq!(bound.compare(&string("2023-11-14T22:13:20Z")), Some(Equal));
+        assert_eq!(bound.compare(&string("2023-11-14T23:13:21+01:00")), Some(Greater));
+        assert_eq!(
+            self::bound("1700000000000").compare(&string("2023-11-15T00:00:00Z")),
+            Some(Greater)
+        );
+    }
+
+    #[test]
+    fn compare_timestamps() {
+        let bound = bound("2023-11-14T22:13:20Z");
+        assert_eq!(bound.compare(&string("2023-11-14T22:13:19.999Z")), Some(Less));
+        assert_eq!(bound.compare(&string("2023-11-14T23:13:20+01:00")), Some(Equal));
+        assert_eq!(bound.compare(&string("2023-11-14")), None);
+        assert_eq!(bound.compare(&uint(1_700_000_001)), Some(Greater));
+        assert_eq!(bound.compare(&float(1_699_999_999.5)), Some(Less));
+    }
+
+    #[test]
+    fn unix_timestamp_units_are_guessed_from_magnitude() {
+        let bound = bound("2023-11-14T22:13:20Z");
+        for timestamp in
+            [1_700_000_000, 1_700_000_000_000, 1_700_000_000_000_000, 1_700_000_000_000_000_000]
+        {
+            assert_eq!(bound.compare(&uint(timestamp)), Some(Equal), "{timestamp}");
+            assert_eq!(bound.compare(&uint(timestamp + 1)), Some(Greater), "{timestamp}");
+        }
+        assert_eq!(bound.compare(&uint(1_000)), Some(Less)); // 1970-01-01T00:16:40Z
+    }
+
+    #[test]
+    fn compare_times_of_day() {
+        let bound = bound("10:05");
+        assert_eq!(bound.compare(&string("10:04:59.9")), Some(Less));
+        assert_eq!(bound.compare(&string("10:05")), Some(Equal));
+        // RFC3339 strings are compared in their own offset
+        assert_eq!(bound.compare(&string("2023-11-14T10:06:00+08:00")), Some(Greater));
+        assert_eq!(bound.compare(&string("2023-11-14T10:04:00Z")), Some(Less));
+        // Unix timestamps are compared in UTC, here 1970-01-01T10:05:00Z
+        assert_eq!(bound.compare(&uint(36_300)), Some(Equal));
+        assert_eq!(bound.compare(&string("later")), None);
+    }
+}
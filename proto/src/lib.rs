@@ -1,15 +1,38 @@
+use std::mem;
 use std::sync::Arc;
 
 use arcstr::ArcStr;
+pub use bound::Bound;
 pub use rmp_serde::{decode, encode};
+pub use value::{Float, Number, Value};
+
+mod bound;
+pub mod query;
+mod value;
 
 pub mod client {
     use serde::{Deserialize, Serialize};
 
+    use crate::{Anchor, Direction, IndexMethod, IndexRef};
+
     #[derive(Serialize, Deserialize)]
     pub enum Message {
         Handshake(Handshake),
         ListKeys(ListKeys),
+        /// Creates an index, backfilled from the messages currently in the buffer.
+        CreateIndex(IndexMethod),
+        /// Drops an existing index.
+        DropIndex(IndexMethod),
+        /// Requests a page of entries, answered with `server::Message::Entries`.
+        FetchEntries(FetchEntries),
+        /// Subscribes to new and evicted entries in an index.
+        ///
+        /// Subscribing to an `IndexRef::Method` creates the index if it does not exist,
+        /// which is dropped again with its last subscriber unless created with `CreateIndex`.
+        Subscribe(Subscribe),
+        Unsubscribe(Unsubscribe),
+        /// Requests statistics of the keys in the buffer, answered with `server::Message::KeyStats`.
+        KeyStats(KeyStats),
     }
 
     #[derive(Serialize, Deserialize)]
@@ -19,69 +42,491 @@ pub mod client {
 
     #[derive(Serialize, Deserialize)]
     pub struct ListKeys {}
+
+    #[derive(Serialize, Deserialize)]
+    pub struct FetchEntries {
+        /// Echoed in the response to correlate it with this request.
+        pub request_id: u64,
+        pub index:      IndexRef,
+        pub anchor:     Anchor,
+        pub direction:  Direction,
+        /// Maximum number of entries to return.
+        pub limit:      usize,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Subscribe {
+        /// A client-chosen ID echoed in the notifications of this subscription.
+        pub subscription_id: u64,
+        pub index:           IndexRef,
+        /// Whether `server::Appended` should contain the entries or only their IDs.
+        pub include_entries: bool,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Unsubscribe {
+        pub subscription_id: u64,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct KeyStats {
+        /// Echoed in the response to correlate it with this request.
+        pub request_id: u64,
+        /// Maximum number of most frequent values to return for each key.
+        pub top_values: usize,
+    }
 }
 
 pub mod server {
+    use arcstr::ArcStr;
     use serde::{Deserialize, Serialize};
 
-    use crate::IndexMethod;
+    use crate::{Entry, IndexMethod, MessageId, Value};
 
     #[derive(Serialize, Deserialize)]
     pub enum Message {
         HandshakeOk(HandshakeOk),
         UpdateKeyList(Vec<IndexMethod>),
         StatusFeed(StatusFeed),
+        Entries(Entries),
+        Appended(Appended),
+        Evicted(Evicted),
+        KeyStats(KeyStats),
     }
 
     #[derive(Serialize, Deserialize)]
     pub struct HandshakeOk {}
 
+    #[derive(Serialize, Deserialize)]
+    pub struct Entries {
+        /// The `request_id` of the corresponding `FetchEntries` request.
+        pub request_id: u64,
+        /// The entries in the page, in ascending order of ID.
+        pub entries:    Vec<(MessageId, Entry)>,
+        /// The anchor to fetch the next page in the same direction,
+        /// or `None` if the end of the index is reached.
+        pub next:       Option<MessageId>,
+    }
+
+    /// New entries in a subscribed index.
+    #[derive(Serialize, Deserialize)]
+    pub struct Appended {
+        pub subscription_id: u64,
+        /// The new entries in ascending order of ID.
+        ///
+        /// Entries are only included if requested in the subscription.
+        pub entries:         Vec<(MessageId, Option<Entry>)>,
+    }
+
+    /// Entries evicted from a subscribed index.
+    #[derive(Serialize, Deserialize)]
+    pub struct Evicted {
+        pub subscription_id: u64,
+        /// All entries with an ID less than or equal to this ID have been evicted.
+        pub until:           MessageId,
+    }
+
+    /// Statistics of the keys of the JSON messages in the buffer.
+    #[derive(Serialize, Deserialize)]
+    pub struct KeyStats {
+        /// The `request_id` of the corresponding `client::KeyStats` request.
+        pub request_id: u64,
+        /// Number of JSON messages in the buffer.
+        pub messages:   u64,
+        /// The keys in the buffer in ascending order,
+        /// with nested keys addressed by dotted paths such as `http.request.method`.
+        pub keys:       Vec<KeyStat>,
+    }
+
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct KeyStat {
+        pub key:            ArcStr,
+        /// Number of occurrences of the key in the buffer.
+        pub count:          u64,
+        /// Number of distinct values of the key, excluding objects.
+        pub distinct:       u64,
+        /// Whether `distinct` is exact.
+        ///
+        /// Otherwise the key has too many distinct values to count each of them,
+        /// `distinct` is an estimate,
+        /// and `top_values` only includes values that were counted.
+        pub distinct_exact: bool,
+        /// The most frequent values with their number of occurrences, in descending order.
+        pub top_values:     Vec<(Value, u64)>,
+    }
+
     #[derive(Serialize, Deserialize)]
     pub struct IndexList {
         indices: Vec<IndexMethod>,
     }
 
-    #[derive(Serialize, Deserialize)]
+    /// Usage of the message buffer, sent when it changes.
+    #[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
     pub struct StatusFeed {
-        file_name:      String,
-        lines_in_index: u64,
+        /// Number of messages in the buffer.
+        pub buffer_entries:      u64,
+        /// Approximate memory used by the messages in the buffer, in bytes.
+        pub buffer_memory:       u64,
+        /// The maximum memory of the buffer, if limited.
+        pub buffer_memory_limit: Option<u64>,
+        /// Number of messages evicted from the buffer that are still available on disk.
+        pub spill_entries:       u64,
+        /// Total size of the files storing messages on disk, in bytes.
+        pub spill_size:          u64,
+        /// The inputs of the buffer, in the order they were opened.
+        pub sources:             Vec<SourceStatus>,
+    }
+
+    #[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct SourceStatus {
+        /// The label of the messages read from the input.
+        pub label: ArcStr,
+        pub state: SourceState,
+    }
+
+    #[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum SourceState {
+        /// Messages are read from the input as they are written.
+        Reading,
+        /// The end of the input was reached and no more messages will be read.
+        Ended,
+        /// The last attempt to read the input failed with this error.
+        ///
+        /// Reading is retried, and the state returns to `Reading` after the next message.
+        Failed(String),
     }
 }
 
+/// Selects the entries in an index.
+///
+/// The condition is always canonical, so equivalent filters compare and hash equally.
 #[derive(Clone, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+#[serde(from = "Condition", into = "Condition")]
 pub struct IndexMethod {
-    pub conditions: Arc<[FieldCondition]>,
+    condition: Condition,
 }
 
 impl IndexMethod {
-    pub fn new(mut units: Vec<FieldCondition>) -> Self {
-        units.sort();
-        Self { conditions: Arc::from(units) }
+    pub fn new(condition: Condition) -> Self { Self { condition: condition.canonicalize() } }
+
+    pub fn condition(&self) -> &Condition { &self.condition }
+}
+
+impl From<Condition> for IndexMethod {
+    fn from(condition: Condition) -> Self { Self::new(condition) }
+}
+
+impl From<IndexMethod> for Condition {
+    fn from(method: IndexMethod) -> Self { method.condition }
+}
+
+/// A boolean combination of field conditions.
+#[derive(
+    Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Deserialize, serde::Serialize,
+)]
+pub enum Condition {
+    Field(FieldCondition),
+    /// Matches if all children match, or always if there are no children.
+    And(Arc<[Condition]>),
+    /// Matches if any child matches, or never if there are no children.
+    Or(Arc<[Condition]>),
+    Not(Arc<Condition>),
+}
+
+impl Condition {
+    /// A condition that matches every JSON entry.
+    pub fn always() -> Self { Self::And(Arc::from([])) }
+
+    pub fn is_always(&self) -> bool { matches!(self, Self::And(children) if children.is_empty()) }
+
+    /// Combines two conditions with AND, in canonical form.
+    pub fn and(self, other: Self) -> Self { Self::And(Arc::from([self, other])).canonicalize() }
+
+    /// Combines two conditions with OR, in canonical form.
+    pub fn or(self, other: Self) -> Self { Self::Or(Arc::from([self, other])).canonicalize() }
+
+    /// Negates the condition, in canonical form.
+    pub fn negate(self) -> Self { Self::Not(Arc::new(self)).canonicalize() }
+
+    /// Rewrites the condition into a canonical form.
+    ///
+    /// Nested `And`s and `Or`s are flattened, their children are sorted and deduplicated,
+    /// single-child `And`s and `Or`s are unwrapped and double negations are removed.
+    pub fn canonicalize(self) -> Self {
+        match self {
+            Self::Field(_) => self,
+            Self::And(children) => Self::canonicalize_children(&children, true),
+            Self::Or(children) => Self::canonicalize_children(&children, false),
+            Self::Not(child) => match Condition::clone(&child).canonicalize() {
+                Self::Not(inner) => Condition::clone(&inner),
+                child => Self::Not(Arc::new(child)),
+            },
+        }
+    }
+
+    fn canonicalize_children(children: &[Condition], is_and: bool) -> Self {
+        let mut flattened = Vec::with_capacity(children.len());
+        for child in children {
+            match child.clone().canonicalize() {
+                Self::And(grandchildren) if is_and => flattened.extend_from_slice(&grandchildren),
+                Self::Or(grandchildren) if !is_and => flattened.extend_from_slice(&grandchildren),
+                child => flattened.push(child),
+            }
+        }
+        flattened.sort();
+        flattened.dedup();
+
+        if flattened.len() == 1 {
+            return flattened.pop().expect("len == 1");
+        }
+        if is_and {
+            Self::And(Arc::from(flattened))
+        } else {
+            Self::Or(Arc::from(flattened))
+        }
     }
 }
 
-#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, serde::Deserialize, serde::Serialize)]
+impl From<FieldCondition> for Condition {
+    fn from(condition: FieldCondition) -> Self { Self::Field(condition) }
+}
+
+#[derive(
+    Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Deserialize, serde::Serialize,
+)]
 pub enum FieldCondition {
     HasKey(ArcStr),
-    KeyValue(ArcStr, ArcStr),
+    KeyValue(ArcStr, Value),
+    /// The field is less than the bound.
+    Lt(ArcStr, Bound),
+    /// The field is less than or equal to the bound.
+    Le(ArcStr, Bound),
+    /// The field is greater than the bound.
+    Gt(ArcStr, Bound),
+    /// The field is greater than or equal to the bound.
+    Ge(ArcStr, Bound),
+    /// The field is within the inclusive range between the two bounds.
+    Between(ArcStr, Bound, Bound),
+    /// The field matches the regular expression.
+    Regex(ArcStr, ArcStr),
+    /// The field contains the text, ignoring case.
+    Contains(ArcStr, ArcStr),
+    /// The field starts with the text, ignoring case.
+    StartsWith(ArcStr, ArcStr),
+    /// The field ends with the text, ignoring case.
+    EndsWith(ArcStr, ArcStr),
+    /// Any field, including nested fields, matches the regular expression.
+    AnyRegex(ArcStr),
+    /// The entry was read from the input with this label.
+    Source(ArcStr),
 }
 
 impl FieldCondition {
-    pub fn key(&self) -> &str {
+    /// The key path this condition applies to,
+    /// or `None` if the condition applies to all fields or to the source of the entry.
+    ///
+    /// Nested objects are addressed by dotted paths, e.g. `http.request.method`.
+    pub fn key(&self) -> Option<&str> {
         match self {
-            Self::HasKey(key) | Self::KeyValue(key, _) => key,
+            Self::HasKey(key)
+            | Self::KeyValue(key, _)
+            | Self::Lt(key, _)
+            | Self::Le(key, _)
+            | Self::Gt(key, _)
+            | Self::Ge(key, _)
+            | Self::Between(key, _, _)
+            | Self::Regex(key, _)
+            | Self::Contains(key, _)
+            | Self::StartsWith(key, _)
+            | Self::EndsWith(key, _) => Some(key),
+            Self::AnyRegex(_) | Self::Source(_) => None,
         }
     }
 }
 
-pub enum Entry {
+/// Selects a list of messages to read from.
+#[derive(Clone, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+pub enum IndexRef {
+    /// All messages in the buffer.
+    All,
+    /// Messages that are not valid JSON objects.
+    Raw,
+    /// Messages matched by an index.
+    Method(IndexMethod),
+    /// Messages matched by a query on the full-text index.
+    ///
+    /// Only available if the server was started with a full-text index.
+    Text(TextQuery),
+}
+
+/// A query on the full-text index.
+///
+/// The text is split into words at non-alphanumeric characters and matched case-insensitively.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+pub struct TextQuery {
+    pub text:   ArcStr,
+    /// If true, the words must appear consecutively in the same line or field.
+    /// Otherwise, each word may appear anywhere in the message.
+    pub phrase: bool,
+}
+
+/// The position in an index to start paging from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum Anchor {
+    /// The oldest message in the index.
+    Oldest,
+    /// The newest message in the index.
+    Newest,
+    /// The first message not before this ID when paging forward,
+    /// or the last message not after this ID when paging backward.
+    Id(MessageId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum Direction {
+    /// Towards newer messages.
+    Forward,
+    /// Towards older messages.
+    Backward,
+}
+
+/// A message read from an input.
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct Entry {
+    /// The label of the input the message was read from, such as its path or `stdin`.
+    pub source:  ArcStr,
+    pub content: Content,
+}
+
+impl Entry {
+    /// Estimates the heap memory owned by the entry, in bytes.
+    ///
+    /// Strings shared with other values, such as interned keys and the source label,
+    /// are not counted, so the estimate changes when the entry is cloned.
+    pub fn heap_size(&self) -> usize { self.content.heap_size() }
+}
+
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub enum Content {
     Json(JsonEntry),
     Raw(RawEntry),
 }
 
-pub struct JsonEntry(pub Vec<(ArcStr, arcstr::ArcStr)>);
+impl Content {
+    /// Estimates the heap memory owned by the content, in bytes.
+    ///
+    /// Strings shared with other values, such as interned keys, are not counted.
+    pub fn heap_size(&self) -> usize {
+        match self {
+            Self::Json(json) => {
+                let spare = json.0.capacity() - json.0.len();
+                value::fields_heap_size(&json.0) + spare * mem::size_of::<(ArcStr, Value)>()
+            }
+            Self::Raw(raw) => 2 * mem::size_of::<usize>() + raw.0.len(),
+        }
+    }
+}
+
+/// A structured log entry, with fields sorted by key.
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct JsonEntry(pub Vec<(ArcStr, Value)>);
+
+impl JsonEntry {
+    /// Looks up a field by a dotted key path such as `http.request.method`.
+    pub fn get_path(&self, path: &str) -> Option<&Value> { value::get_path(&self.0, path) }
+}
 
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
 pub struct RawEntry(pub Arc<[u8]>);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    serde::Deserialize,
+    serde::Serialize,
+)]
 pub struct MessageId(pub usize);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn has(key: &str) -> Condition { FieldCondition::HasKey(key.into()).into() }
+
+    fn and(children: impl Into<Vec<Condition>>) -> Condition {
+        Condition::And(Arc::from(children.into()))
+    }
+
+    fn or(children: impl Into<Vec<Condition>>) -> Condition {
+        Condition::Or(Arc::from(children.into()))
+    }
+
+    fn not(child: Condition) -> Condition { Condition::Not(Arc::new(child)) }
+
+    #[test]
+    fn reordered_children_are_equal() {
+        assert_eq!(
+            and([has("a"), or([has("b"), has("c")])]).canonicalize(),
+            and([or([has("c"), has("b")]), has("a")]).canonicalize(),
+        );
+        assert_eq!(
+            or([not(has("a")), and([has("b"), has("c")])]).canonicalize(),
+            or([and([has("c"), has("b")]), not(has("a"))]).canonicalize(),
+        );
+    }
+
+    #[test]
+    fn nested_children_are_flattened() {
+        assert_eq!(
+            and([has("a"), and([has("b"), and([has("c")])])]).canonicalize(),
+            and([has("c"), has("b"), has("a")]).canonicalize(),
+        );
+        assert_eq!(
+            or([or([has("a"), has("b")]), has("c")]).canonicalize(),
+            or([has("a"), or([has("c"), has("b")])]).canonicalize(),
+        );
+        // children of a different combinator are not flattened
+        assert_ne!(
+            and([has("a"), or([has("b"), has("c")])]).canonicalize(),
+            and([has("a"), has("b"), has("c")]).canonicalize(),
+        );
+    }
+
+    #[test]
+    fn duplicates_and_single_children_are_removed() {
+        assert_eq!(and([has("a"), has("a")]).canonicalize(), has("a"));
+        assert_eq!(or([and([has("a")])]).canonicalize(), has("a"));
+        assert_eq!(or([has("a"), has("b"), has("a")]).canonicalize(), or([has("a"), has("b")]));
+    }
+
+    #[test]
+    fn double_negations_are_removed() {
+        assert_eq!(not(not(has("a"))).canonicalize(), has("a"));
+        assert_eq!(not(not(not(has("a")))).canonicalize(), not(has("a")));
+        assert_eq!(
+            not(and([has("b"), not(not(has("a")))])).canonicalize(),
+            not(and([has("a"), has("b")])).canonicalize(),
+        );
+    }
+
+    #[test]
+    fn empty_combinators() {
+        assert!(and([]).canonicalize().is_always());
+        assert!(and([and([]), and([])]).canonicalize().is_always());
+        assert_eq!(or([]).canonicalize(), or([]));
+    }
+
+    #[test]
+    fn equal_conditions_have_equal_index_methods() {
+        let first = IndexMethod::new(or([has("a"), and([has("b"), has("c")])]));
+        let second = IndexMethod::new(or([and([has("c"), has("b")]), has("a")]));
+        assert!(first == second);
+    }
+}
@@ -0,0 +1,937 @@
+//! A textual query language for filter conditions.
+//!
+//! A query is a whitespace-separated list of terms, all of which must match:
+//!
+//! - `key=value` matches entries where the field at the dotted key path equals the value.
+//! - `has:key` matches entries that contain the field at the dotted key path.
+//!
+//! - `key<bound`, `key<=bound`, `key>bound` and `key>=bound` compare the field with a bound.
+//! - `key between low and high` matches fields within the inclusive range.
+//!
+//! - `key~pattern` matches fields matching the regular expression.
+//! - `key*=text`, `key^=text` and `key$=text` match fields containing, starting with
+//!   or ending with the text, ignoring case.
+//! - `~pattern` matches entries where any field matches the regular expression.
+//! - `source:label` matches entries read from the input with the label,
+//!   such as `source:api.log` or `source:stdin`.
+//!
+//! Bounds are numbers, RFC3339 timestamps or times of day such as `10:05`.
+//! Numbers are compared numerically, or as Unix timestamps with RFC3339 strings in the field,
+//! and timestamps chronologically with RFC3339 strings or Unix timestamps in the field.
+//!
+//! Terms can be combined with `OR`, negated with `NOT` and grouped with parentheses,
+//! e.g. `(level=error OR level=fatal) NOT component=healthcheck`.
+//! `AND` binds tighter than `OR` and may be written explicitly.
+//!
+//! Values are parsed as `null`, booleans and numbers where possible,
+//! and as strings otherwise.
+//! Keys and values can be quoted with `"` to include whitespace and special characters,
+//! e.g. `msg="connection reset"`.
+//! Arrays and objects are written in JSON syntax, e.g. `tags=["a","b"]`.
+
+use std::fmt::{self, Write as _};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use arcstr::ArcStr;
+
+use crate::{bound, Bound, Condition, FieldCondition, IndexMethod, IndexRef, Value};
+
+const HAS_PREFIX: &str = "has:";
+
+const SOURCE_PREFIX: &str = "source:";
+
+type MakeComparison = fn(ArcStr, Bound) -> FieldCondition;
+
+type MakeTextMatch = fn(ArcStr, ArcStr) -> FieldCondition;
+
+/// Text match operators, which must directly follow the key.
+const TEXT_MATCHES: &[(&str, MakeTextMatch)] = &[
+    ("~", FieldCondition::Regex),
+    ("*=", FieldCondition::Contains),
+    ("^=", FieldCondition::StartsWith),
+    ("$=", FieldCondition::EndsWith),
+];
+
+/// The prefix of a regular expression matched against all fields.
+const ANY_REGEX_PREFIX: char = '~';
+
+/// Comparison operators, with longer operators first so that they are matched greedily.
+const COMPARISONS: &[(&str, MakeComparison)] = &[
+    ("<=", FieldCondition::Le),
+    (">=", FieldCondition::Ge),
+    ("<", FieldCondition::Lt),
+    (">", FieldCondition::Gt),
+];
+
+/// A parsed query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    /// The canonical condition of the query.
+    pub condition: Condition,
+}
+
+impl Default for Query {
+    fn default() -> Self { Self { condition: Condition::always() } }
+}
+
+impl Query {
+    pub fn is_empty(&self) -> bool { self.condition.is_always() }
+
+    /// The index selecting entries matched by this query.
+    pub fn index_ref(&self) -> IndexRef {
+        if self.is_empty() {
+            IndexRef::All
+        } else {
+            IndexRef::Method(IndexMethod::new(self.condition.clone()))
+        }
+    }
+}
+
+impl FromStr for Query {
+    type Err = ParseError;
+
+    fn from_str(source: &str) -> Result<Self, ParseError> {
+        let mut parser = Parser { source, position: 0 };
+        parser.skip_whitespace();
+        if parser.peek().is_none() {
+            return Ok(Self::default());
+        }
+
+        let condition = parser.or()?;
+        match parser.peek() {
+            Some(')') => Err(parser.error(ParseErrorKind::UnmatchedParen)),
+            Some(ch) => Err(parser.error(ParseErrorKind::Unexpected(ch))),
+            None => Ok(Self { condition: condition.canonicalize() }),
+        }
+    }
+}
+
+impl fmt::Display for Query {
+    /// Formats the query such that it parses back to the same condition.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { self.condition.fmt(f) }
+}
+
+/// Operator precedence in query syntax, from loosest to tightest.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Precedence {
+    Or,
+    And,
+    Not,
+}
+
+impl fmt::Display for Condition {
+    /// Formats the condition in query syntax.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_condition(f, self, Precedence::Or)
+    }
+}
+
+fn write_condition(
+    f: &mut fmt::Formatter<'_>,
+    condition: &Condition,
+    parent: Precedence,
+) -> fmt::Result {
+    let (children, separator, precedence) = match condition {
+        Condition::Field(field) => return write!(f, "{field}"),
+        Condition::Not(child) => {
+            write!(f, "NOT ")?;
+            return write_condition(f, child, Precedence::Not);
+        }
+        Condition::And(children) => (children, " ", Precedence::And),
+        Condition::Or(children) => (children, " OR ", Precedence::Or),
+    };
+
+    let parenthesized = parent > precedence;
+    if parenthesized {
+        write!(f, "(")?;
+    }
+    for (i, child) in children.iter().enumerate() {
+        if i > 0 {
+            write!(f, "{separator}")?;
+        }
+        write_condition(f, child, precedence)?;
+    }
+    if parenthesized {
+        write!(f, ")")?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for FieldCondition {
+    /// Formats the condition in query syntax.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::HasKey(key) => {
+                write!(f, "{HAS_PREFIX}")?;
+                write_key(f, key)
+            }
+            Self::KeyValue(key, value) => {
+                write_key(f, key)?;
+                write!(f, "=")?;
+                write_value(f, value, false)
+            }
+            Self::Lt(key, bound) => write_comparison(f, key, "<", bound),
+            Self::Le(key, bound) => write_comparison(f, key, "<=", bound),
+            Self::Gt(key, bound) => write_comparison(f, key, ">", bound),
+            Self::Ge(key, bound) => write_comparison(f, key, ">=", bound),
+            Self::Between(key, low, high) => {
+                write_key(f, key)?;
+                write!(f, " between {low} and {high}")
+            }
+            Self::Regex(key, text) => write_text_match(f, key, "~", text),
+            Self::Contains(key, text) => write_text_match(f, key, "*=", text),
+            Self::StartsWith(key, text) => write_text_match(f, key, "^=", text),
+            Self::EndsWith(key, text) => write_text_match(f, key, "$=", text),
+            Self::AnyRegex(pattern) => {
+                write!(f, "{ANY_REGEX_PREFIX}")?;
+                write_text(f, pattern)
+            }
+            Self::Source(label) => {
+                write!(f, "{SOURCE_PREFIX}")?;
+                write_text(f, label)
+            }
+        }
+    }
+}
+
+fn write_text_match(
+    f: &mut fmt::Formatter<'_>,
+    key: &str,
+    operator: &str,
+    text: &str,
+) -> fmt::Result {
+    write_key(f, key)?;
+    write!(f, "{operator}")?;
+    write_text(f, text)
+}
+
+fn write_text(f: &mut fmt::Formatter<'_>, text: &str) -> fmt::Result {
+    if !text.is_empty() && text.chars().all(|ch| is_bare_value_char(ch, false)) {
+        write!(f, "{text}")
+    } else {
+        write_quoted(f, text)
+    }
+}
+
+fn write_comparison(
+    f: &mut fmt::Formatter<'_>,
+    key: &str,
+    operator: &str,
+    bound: &Bound,
+) -> fmt::Result {
+    write_key(f, key)?;
+    write!(f, "{operator}{bound}")
+}
+
+fn write_key(f: &mut fmt::Formatter<'_>, key: &str) -> fmt::Result {
+    let bare = !key.is_empty()
+        && !key.starts_with(HAS_PREFIX)
+        && !key.starts_with(SOURCE_PREFIX)
+        && !["NOT", "AND", "OR"].contains(&key)
+        && key.chars().all(is_bare_key_char)
+        // e.g. the key `a^` in `a^=x` would be parsed as the key `a` with the operator `^=`
+        && !TEXT_MATCHES.iter().any(|&(operator, _)| {
+            key.contains(operator)
+                || operator.strip_suffix('=').is_some_and(|prefix| key.ends_with(prefix))
+        });
+    if !bare {
+        write_quoted(f, key)
+    } else {
+        write!(f, "{key}")
+    }
+}
+
+fn write_value(f: &mut fmt::Formatter<'_>, value: &Value, nested: bool) -> fmt::Result {
+    match value {
+        Value::String(string) => {
+            let bare = !string.is_empty()
+                && !string.starts_with(['[', '{'])
+                && string.chars().all(|ch| is_bare_value_char(ch, nested))
+                && matches!(parse_bare(string), Value::String(_));
+            if bare {
+                write!(f, "{string}")
+            } else {
+                write_quoted(f, string)
+            }
+        }
+        Value::Array(items) => {
+            write!(f, "[")?;
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ",")?;
+                }
+                write_value(f, item, true)?;
+            }
+            write!(f, "]")
+        }
+        Value::Object(fields) => {
+            write!(f, "{{")?;
+            for (i, (key, value)) in fields.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ",")?;
+                }
+                write_quoted(f, key)?;
+                write!(f, ":")?;
+                write_value(f, value, true)?;
+            }
+            write!(f, "}}")
+        }
+        value => write!(f, "{value}"),
+    }
+}
+
+fn write_quoted(f: &mut fmt::Formatter<'_>, string: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for ch in string.chars() {
+        match ch {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\'' => f.write_char('\'')?,
+            ch => write!(f, "{}", ch.escape_debug())?,
+        }
+    }
+    f.write_char('"')
+}
+
+fn is_bare_key_char(ch: char) -> bool {
+    !ch.is_whitespace() && !ch.is_control() && !"=<>~\"()".contains(ch)
+}
+
+fn is_bare_value_char(ch: char, nested: bool) -> bool {
+    let special = if nested { ",:[]{}\"" } else { "\"()" };
+    !ch.is_whitespace() && !ch.is_control() && !special.contains(ch)
+}
+
+/// Interprets an unquoted value.
+fn parse_bare(word: &str) -> Value {
+    match word {
+        "null" => return Value::Null,
+        "true" => return Value::Bool(true),
+        "false" => return Value::Bool(false),
+        _ => {}
+    }
+
+    match bound::parse_number(word) {
+        Some(number) => Value::Number(number),
+        None => Value::String(ArcStr::from(word)),
+    }
+}
+
+struct Parser<'t> {
+    source:   &'t str,
+    /// The byte offset of the next character.
+    position: usize,
+}
+
+impl<'t> Parser<'t> {
+    fn peek(&self) -> Option<char> { self.source[self.position..].chars().next() }
+
+    fn bump(&mut self) -> Option<char> {
+        let ch = self.peek()?;
+        self.position += ch.len_utf8();
+        Some(ch)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.bump();
+        }
+    }
+
+    fn error(&self, kind: ParseErrorKind) -> ParseError {
+        ParseError { column: self.source[..self.position].chars().count(), kind }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ParseError> {
+        if self.peek() == Some(expected) {
+            self.bump();
+            Ok(())
+        } else {
+            Err(self.error(ParseErrorKind::Expected(expected)))
+        }
+    }
+
+    /// Takes the longest run of characters satisfying `predicate`.
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> &'t str {
+        let start = self.position;
+        while self.peek().is_some_and(&predicate) {
+            self.bump();
+        }
+        &self.source[start..self.position]
+    }
+
+    /// Whether `keyword` is the next word.
+    fn at_keyword(&self, keyword: &str) -> bool {
+        self.source[self.position..].strip_prefix(keyword).is_some_and(|after| {
+            after.chars().next().is_none_or(|ch| ch.is_whitespace() || ch == '(')
+        })
+    }
+
+    /// Consumes `keyword` if it is the next word.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let matched = self.at_keyword(keyword);
+        if matched {
+            self.position += keyword.len();
+        }
+        matched
+    }
+
+    fn or(&mut self) -> Result<Condition, ParseError> {
+        let mut children = vec![self.and()?];
+        while self.keyword("OR") {
+            children.push(self.and()?);
+        }
+        Ok(Condition::Or(Arc::from(children)))
+    }
+
+    fn and(&mut self) -> Result<Condition, ParseError> {
+        let mut children = vec![self.unary()?];
+        loop {
+            self.skip_whitespace();
+            if self.peek().is_none_or(|ch| ch == ')') || self.at_keyword("OR") {
+                break;
+            }
+            self.keyword("AND");
+            children.push(self.unary()?);
+        }
+        Ok(Condition::And(Arc::from(children)))
+    }
+
+    fn unary(&mut self) -> Result<Condition, ParseError> {
+        self.skip_whitespace();
+        if self.keyword("NOT") {
+            return Ok(Condition::Not(Arc::new(self.unary()?)));
+        }
+
+        match self.peek() {
+            Some('(') => {
+                self.bump();
+                self.skip_whitespace();
+                let condition = self.or()?;
+                self.skip_whitespace();
+                self.expect(')')?;
+                Ok(condition)
+            }
+            None | Some(')') => Err(self.error(ParseErrorKind::MissingCondition)),
+            Some(_) if self.at_keyword("OR") || self.at_keyword("AND") => {
+                Err(self.error(ParseErrorKind::MissingCondition))
+            }
+            Some(_) => Ok(Condition::Field(self.condition()?)),
+        }
+    }
+
+    fn condition(&mut self) -> Result<FieldCondition, ParseError> {
+        let condition = if self.source[self.position..].starts_with(HAS_PREFIX) {
+            self.position += HAS_PREFIX.len();
+            FieldCondition::HasKey(self.key()?)
+        } else if self.source[self.position..].starts_with(SOURCE_PREFIX) {
+            self.position += SOURCE_PREFIX.len();
+            FieldCondition::Source(self.text(SOURCE_PREFIX)?)
+        } else if self.peek() == Some(ANY_REGEX_PREFIX) {
+            self.bump();
+            FieldCondition::AnyRegex(self.pattern(ANY_REGEX_PREFIX.encode_utf8(&mut [0; 4]))?)
+        } else {
+            let key = self.key()?;
+            let key_end = self.position;
+
+            let rest = &self.source[self.position..];
+            let text_match = TEXT_MATCHES.iter().find(|&&(operator, _)| rest.starts_with(operator));
+            if let Some(&(operator, make)) = text_match {
+                self.position += operator.len();
+                let text = if operator == "~" {
+                    self.pattern(&format!("{key}{operator}"))?
+                } else {
+                    self.text(&format!("{key}{operator}"))?
+                };
+                return self.end_condition(make(key, text));
+            }
+
+            // comparisons may be surrounded by whitespace, e.g. `latency_ms > 500`
+            self.skip_whitespace();
+            let rest = &self.source[self.position..];
+            let comparison = COMPARISONS.iter().find(|&&(operator, _)| rest.starts_with(operator));
+            if let Some(&(operator, make)) = comparison {
+                self.position += operator.len();
+                make(key, self.bound()?)
+            } else if self.keyword("between") || self.keyword("BETWEEN") {
+                let low = self.bound()?;
+                self.skip_whitespace();
+                if !(self.keyword("and") || self.keyword("AND")) {
+                    return Err(self.error(ParseErrorKind::MissingBetweenAnd));
+                }
+                FieldCondition::Between(key, low, self.bound()?)
+            } else {
+                self.position = key_end;
+                if self.peek() != Some('=') {
+                    return Err(self.error(ParseErrorKind::MissingOperator(key.to_string())));
+                }
+                self.bump();
+
+                if self.peek().is_none_or(|ch| ch.is_whitespace() || ch == ')') {
+                    return Err(self.error(ParseErrorKind::MissingValue(key.to_string())));
+                }
+                FieldCondition::KeyValue(key, self.value(false)?)
+            }
+        };
+
+        self.end_condition(condition)
+    }
+
+    /// Checks that a condition is followed by a separator.
+    fn end_condition(&self, condition: FieldCondition) -> Result<FieldCondition, ParseError> {
+        match self.peek() {
+            Some(ch) if !ch.is_whitespace() && ch != ')' => {
+                Err(self.error(ParseErrorKind::Unexpected(ch)))
+            }
+            _ => Ok(condition),
+        }
+    }
+
+    /// Parses the bound of a comparison, skipping leading whitespace.
+    fn bound(&mut self) -> Result<Bound, ParseError> {
+        self.skip_whitespace();
+        let start = self.position;
+
+        let word = match self.peek() {
+            Some('"') => self.quoted()?,
+            _ => ArcStr::from(self.take_while(|ch| is_bare_value_char(ch, false))),
+        };
+        if word.is_empty() {
+            return Err(self.error(ParseErrorKind::MissingBound));
+        }
+
+        word.parse().map_err(|()| {
+            self.position = start;
+            self.error(ParseErrorKind::InvalidBound(word.to_string()))
+        })
+    }
+
+    /// Parses the text of a text match, where `operator` is the preceding part of the term.
+    fn text(&mut self, operator: &str) -> Result<ArcStr, ParseError> {
+        let text = match self.peek() {
+            Some('"') => return self.quoted(),
+            _ => self.take_while(|ch| is_bare_value_char(ch, false)),
+        };
+        if text.is_empty() {
+            return Err(self.error(ParseErrorKind::MissingText(operator.to_string())));
+        }
+        Ok(ArcStr::from(text))
+    }
+
+    /// Parses a regular expression, where `operator` is the preceding part of the term.
+    fn pattern(&mut self, operator: &str) -> Result<ArcStr, ParseError> {
+        let start = self.position;
+        let pattern = self.text(operator)?;
+        if let Err(err) = regex::Regex::new(&pattern) {
+            self.position = start;
+            // the last line of the message describes the error without the pattern
+            let message = err.to_string();
+            let message = message.lines().last().unwrap_or_default();
+            let message = message.trim().trim_start_matches("error: ").to_string();
+            return Err(self.error(ParseErrorKind::InvalidRegex(message)));
+        }
+        Ok(pattern)
+    }
+
+    fn key(&mut self) -> Result<ArcStr, ParseError> {
+        if self.peek() == Some('"') {
+            return self.quoted();
+        }
+
+        let start = self.position;
+        while let Some(ch) = self.peek() {
+            // stop before text match operators such as `*=`
+            let rest = &self.source[self.position..];
+            if !is_bare_key_char(ch) || TEXT_MATCHES.iter().any(|&(op, _)| rest.starts_with(op)) {
+                break;
+            }
+            self.bump();
+        }
+
+        let key = &self.source[start..self.position];
+        if key.is_empty() {
+            return Err(self.error(ParseErrorKind::MissingKey));
+        }
+        Ok(ArcStr::from(key))
+    }
+
+    fn value(&mut self, nested: bool) -> Result<Value, ParseError> {
+        match self.peek() {
+            Some('"') => Ok(Value::String(self.quoted()?)),
+            Some('[') => self.array(),
+            Some('{') => self.object(),
+            _ => {
+                let word = self.take_while(|ch| is_bare_value_char(ch, nested));
+                if word.is_empty() {
+                    return Err(match self.peek() {
+                        Some(ch) => self.error(ParseErrorKind::Unexpected(ch)),
+                        None => self.error(ParseErrorKind::UnexpectedEnd),
+                    });
+                }
+                Ok(parse_bare(word))
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, ParseError> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.bump();
+            return Ok(Value::Array(Arc::from(items)));
+        }
+
+        loop {
+            self.skip_whitespace();
+            items.push(self.value(true)?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => _ = self.bump(),
+                Some(']') => {
+                    self.bump();
+                    return Ok(Value::Array(Arc::from(items)));
+                }
+                _ => return Err(self.error(ParseErrorKind::Expected(']'))),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, ParseError> {
+        self.expect('{')?;
+        let mut fields = Vec::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.bump();
+            return Ok(Value::Object(Arc::from(fields)));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let key = if self.peek() == Some('"') {
+                self.quoted()?
+            } else {
+                let key = self.take_while(|ch| is_bare_value_char(ch, true));
+                if key.is_empty() {
+                    return Err(self.error(ParseErrorKind::MissingKey));
+                }
+                ArcStr::from(key)
+            };
+            self.skip_whitespace();
+            self.expect(':')?;
+            self.skip_whitespace();
+            fields.push((key, self.value(true)?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => _ = self.bump(),
+                Some('}') => {
+                    self.bump();
+                    // objects are sorted by key, the same as parsed entries
+                    fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+                    fields.dedup_by(|(a, _), (b, _)| a == b);
+                    return Ok(Value::Object(Arc::from(fields)));
+                }
+                _ => return Err(self.error(ParseErrorKind::Expected('}'))),
+            }
+        }
+    }
+
+    fn quoted(&mut self) -> Result<ArcStr, ParseError> {
+        let start = self.position;
+        self.expect('"')?;
+
+        let mut string = String::new();
+        loop {
+            let escape_position = self.position;
+            match self.bump() {
+                None => {
+                    self.position = start;
+                    return Err(self.error(ParseErrorKind::UnterminatedString));
+                }
+                Some('"') => break,
+                Some('\\') => {
+                    let ch = match self.bump() {
+                        Some('"') => '"',
+                        Some('\'') => '\'',
+                        Some('\\') => '\\',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('0') => '\0',
+                        Some('u') => self.unicode_escape(escape_position)?,
+                        Some(ch) => {
+                            self.position = escape_position;
+                            return Err(self.error(ParseErrorKind::InvalidEscape(ch)));
+                        }
+                        None => continue, // reported as unterminated string
+                    };
+                    string.push(ch);
+                }
+                Some(ch) => string.push(ch),
+            }
+        }
+
+        Ok(ArcStr::from(string))
+    }
+
+    /// Parses the `{XXXX}` part of a `\u{XXXX}` escape sequence.
+    fn unicode_escape(&mut self, escape_position: usize) -> Result<char, ParseError> {
+        let mut parse = || {
+            if self.bump()? != '{' {
+                return None;
+            }
+            let digits = self.take_while(|ch| ch.is_ascii_hexdigit());
+            if self.bump()? != '}' {
+                return None;
+            }
+            char::from_u32(u32::from_str_radix(digits, 16).ok()?)
+        };
+
+        match parse() {
+            Some(ch) => Ok(ch),
+            None => {
+                self.position = escape_position;
+                Err(self.error(ParseErrorKind::InvalidEscape('u')))
+            }
+        }
+    }
+}
+
+/// An error in a query, with the position where it was detected.
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("{kind} at column {}", column + 1)]
+pub struct ParseError {
+    /// The number of characters before the error position.
+    pub column: usize,
+    pub kind:   ParseErrorKind,
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum ParseErrorKind {
+    #[error("expected a condition")]
+    MissingCondition,
+    #[error("expected a key")]
+    MissingKey,
+    #[error(
+        "expected `=`, `<`, `>` or `between` after `{0}`, or use `has:{0}` to match entries with \
+         this key"
+    )]
+    MissingOperator(String),
+    #[error("expected a value after `{0}=`, or use `{0}=\"\"` to match an empty string")]
+    MissingValue(String),
+    #[error("expected text after `{0}`, or use `{0}\"\"` to match an empty string")]
+    MissingText(String),
+    #[error("invalid regular expression: {0}")]
+    InvalidRegex(String),
+    #[error("expected a number, RFC3339 timestamp or time of day")]
+    MissingBound,
+    #[error("`{0}` is not a number, RFC3339 timestamp or time of day")]
+    InvalidBound(String),
+    #[error("expected `and` after the lower bound of `between`")]
+    MissingBetweenAnd,
+    #[error("expected `{0}`")]
+    Expected(char),
+    #[error("unexpected `{0}`, quote the value with `\"` to include special characters")]
+    Unexpected(char),
+    #[error("unmatched `)`")]
+    UnmatchedParen,
+    #[error("unexpected end of query")]
+    UnexpectedEnd,
+    #[error("unterminated string")]
+    UnterminatedString,
+    #[error("invalid escape sequence `\\{0}`")]
+    InvalidEscape(char),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Number;
+
+    fn parse(query: &str) -> Condition {
+        query.parse::<Query>().unwrap_or_else(|err| panic!("{query}: {err}")).condition
+    }
+
+    fn parse_err(query: &str) -> ParseError { query.parse::<Query>().expect_err(query) }
+
+    fn eq(key: &str, value: &str) -> Condition {
+        FieldCondition::KeyValue(key.into(), Value::String(value.into())).into()
+    }
+
+    fn eq_uint(key: &str, value: u64) -> Condition {
+        FieldCondition::KeyValue(key.into(), Value::Number(Number::UInt(value))).into()
+    }
+
+    fn and(children: impl Into<Vec<Condition>>) -> Condition {
+        Condition::And(Arc::from(children.into())).canonicalize()
+    }
+
+    fn or(children: impl Into<Vec<Condition>>) -> Condition {
+        Condition::Or(Arc::from(children.into())).canonicalize()
+    }
+
+    fn assert_round_trip(condition: &Condition) {
+        let formatted = condition.to_string();
+        assert_eq!(&parse(&formatted), condition, "formatted as {formatted}");
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let expected = or([and([eq_uint("a", 1), eq_uint("b", 2)]), eq_uint("c", 3)]);
+        assert_eq!(parse("a=1 b=2 OR c=3"), expected);
+        assert_eq!(parse("a=1 AND b=2 OR c=3"), expected);
+        assert_eq!(parse("c=3 OR b=2 a=1"), expected);
+    }
+
+    #[test]
+    fn parentheses_group() {
+        let expected = and([eq_uint("a", 1), or([eq_uint("b", 2), eq_uint("c", 3)])]);
+        assert_eq!(parse("a=1 (b=2 OR c=3)"), expected);
+        assert_eq!(parse("( ( b=2 OR c=3 ) ) a=1"), expected);
+    }
+
+    #[test]
+    fn not_binds_tightest() {
+        assert_eq!(parse("NOT a=1 b=2"), and([eq_uint("a", 1).negate(), eq_uint("b", 2)]));
+        assert_eq!(parse("NOT (a=1 b=2)"), and([eq_uint("a", 1), eq_uint("b", 2)]).negate());
+        assert_eq!(parse("NOT NOT a=1"), eq_uint("a", 1));
+    }
+
+    #[test]
+    fn empty_query_matches_everything() {
+        assert!(parse("").is_always());
+        assert!(parse("  ").is_always());
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(parse("a=null"), FieldCondition::KeyValue("a".into(), Value::Null).into());
+        assert_eq!(parse("a=true"), FieldCondition::KeyValue("a".into(), Value::Bool(true)).into());
+        assert_eq!(
+            parse("a=-1"),
+            FieldCondition::KeyValue("a".into(), Value::Number(Number::Int(-1))).into()
+        );
+        assert_eq!(parse("a=\"1\""), eq("a", "1"));
+        assert_eq!(parse("a=inf"), eq("a", "inf"));
+    }
+
+    #[test]
+    fn quoted_keys_and_values() {
+        assert_eq!(parse("msg=\"connection reset\""), eq("msg", "connection reset"));
+        assert_eq!(parse("\"a b\"=c"), eq("a b", "c"));
+        assert_eq!(parse("\"has:x\"=c"), eq("has:x", "c"));
+        assert_eq!(parse("a=\"x) OR (y\""), eq("a", "x) OR (y"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(parse(r#"a="x\"y\\z""#), eq("a", "x\"y\\z"));
+        assert_eq!(parse(r#"a="\n\t\u{41}\u{1F600}""#), eq("a", "\n\tA\u{1F600}"));
+    }
+
+    #[test]
+    fn text_match_operators_end_keys() {
+        assert_eq!(parse("a*=x"), FieldCondition::Contains("a".into(), "x".into()).into());
+        assert_eq!(parse("a^=x"), FieldCondition::StartsWith("a".into(), "x".into()).into());
+        assert_eq!(parse("a$=x"), FieldCondition::EndsWith("a".into(), "x".into()).into());
+        assert_eq!(parse("a~x"), FieldCondition::Regex("a".into(), "x".into()).into());
+        assert_eq!(parse("a*b=x"), eq("a*b", "x"));
+    }
+
+    fn assert_error(query: &str, column: usize, check: impl FnOnce(&ParseErrorKind) -> bool) {
+        let err = parse_err(query);
+        assert_eq!(err.column, column, "{query}: {err}");
+        assert!(check(&err.kind), "{query}: {err}");
+    }
+
+    #[test]
+    fn error_columns() {
+        assert_error(
+            "a=1 b",
+            5,
+            |kind| matches!(kind, ParseErrorKind::MissingOperator(key) if key == "b"),
+        );
+        assert_error(
+            "a= b=1",
+            2,
+            |kind| matches!(kind, ParseErrorKind::MissingValue(key) if key == "a"),
+        );
+        assert_error("a=\"x", 2, |kind| matches!(kind, ParseErrorKind::UnterminatedString));
+        assert_error(r#"a="x\q""#, 4, |kind| matches!(kind, ParseErrorKind::InvalidEscape('q')));
+        assert_error(r#"a="\u{zz}""#, 3, |kind| matches!(kind, ParseErrorKind::InvalidEscape('u')));
+        assert_error("a=1)", 3, |kind| matches!(kind, ParseErrorKind::UnmatchedParen));
+        assert_error("(a=1", 4, |kind| matches!(kind, ParseErrorKind::Expected(')')));
+        assert_error("a=1 OR", 6, |kind| matches!(kind, ParseErrorKind::MissingCondition));
+        assert_error(
+            "t>abc",
+            2,
+            |kind| matches!(kind, ParseErrorKind::InvalidBound(word) if word == "abc"),
+        );
+        assert_error("t between 1 2", 12, |kind| matches!(kind, ParseErrorKind::MissingBetweenAnd));
+        assert_error(r#"a~"[""#, 2, |kind| matches!(kind, ParseErrorKind::InvalidRegex(_)));
+        assert_error("é=x y", 5, |kind| matches!(kind, ParseErrorKind::MissingOperator(_)));
+    }
+
+    #[test]
+    fn round_trip_field_conditions() {
+        let bound = |string: &str| string.parse::<Bound>().expect(string);
+        let conditions = [
+            FieldCondition::HasKey("a.b".into()),
+            FieldCondition::HasKey("a b".into()),
+            FieldCondition::KeyValue("a".into(), Value::String("true".into())),
+            FieldCondition::KeyValue("a".into(), Value::String("".into())),
+            FieldCondition::KeyValue("a".into(), Value::String("[x]".into())),
+            FieldCondition::KeyValue("a".into(), Value::String("x \"y\" \\ \n".into())),
+            FieldCondition::KeyValue("a".into(), Value::Null),
+            FieldCondition::KeyValue(
+                "a".into(),
+                Value::Array(Arc::from([Value::String("x,y".into()), Value::Bool(false)])),
+            ),
+            FieldCondition::KeyValue(
+                "a".into(),
+                Value::Object(Arc::from([("k:".into(), Value::Number(Number::UInt(1)))])),
+            ),
+            FieldCondition::KeyValue("a^".into(), Value::String("x".into())),
+            FieldCondition::KeyValue("a*".into(), Value::String("x".into())),
+            FieldCondition::KeyValue("a$".into(), Value::String("x".into())),
+            FieldCondition::KeyValue("a*=b".into(), Value::String("x".into())),
+            FieldCondition::KeyValue("has:a".into(), Value::String("x".into())),
+            FieldCondition::KeyValue("source:a".into(), Value::String("x".into())),
+            FieldCondition::KeyValue("OR".into(), Value::String("x".into())),
+            FieldCondition::Contains("a^".into(), "x y".into()),
+            FieldCondition::StartsWith("a$".into(), "x".into()),
+            FieldCondition::EndsWith("a*".into(), "x".into()),
+            FieldCondition::Regex("a".into(), "^x+(y)$".into()),
+            FieldCondition::Lt("a".into(), bound("1.5")),
+            FieldCondition::Le("a".into(), bound("-3")),
+            FieldCondition::Gt("a".into(), bound("2024-01-02T03:04:05.5Z")),
+            FieldCondition::Ge("a".into(), bound("10:05:00.25")),
+            FieldCondition::Between("a".into(), bound("1"), bound("2")),
+            FieldCondition::Between("NOT".into(), bound("1"), bound("2")),
+            FieldCondition::Between("a^".into(), bound("1"), bound("2")),
+            FieldCondition::AnyRegex("a b".into()),
+            FieldCondition::Source("api.log".into()),
+            FieldCondition::Source("my app.log".into()),
+        ];
+        for condition in conditions {
+            assert_round_trip(&condition.into());
+        }
+    }
+
+    #[test]
+    fn round_trip_nested_conditions() {
+        let conditions = [
+            or([and([eq_uint("a", 1), eq_uint("b", 2)]), eq_uint("c", 3)]),
+            and([eq_uint("a", 1), or([eq_uint("b", 2), eq_uint("c", 3)])]),
+            and([eq_uint("a", 1), eq_uint("b", 2)]).negate(),
+            or([eq_uint("a", 1), eq_uint("b", 2)]).negate(),
+            and([eq_uint("a", 1).negate(), or([eq("b", "x"), eq("c", "y").negate()])]),
+        ];
+        for condition in conditions {
+            assert_round_trip(&condition);
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::{cmp, fmt, mem};
+
+use arcstr::ArcStr;
+
+/// A JSON value in a structured log entry.
+///
+/// Object fields are sorted by key.
+///
+/// The derived ordering is only intended for canonicalization
+/// and does not compare numbers of different variants numerically.
+#[derive(
+    Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Deserialize, serde::Serialize,
+)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(Number),
+    String(ArcStr),
+    Array(Arc<[Value]>),
+    Object(Arc<[(ArcStr, Value)]>),
+}
+
+impl Value {
+    /// Estimates the heap memory owned by the value, in bytes.
+    ///
+    /// Strings shared with other values, such as interned keys, are not counted,
+    /// so the estimate changes when the value is cloned.
+    pub fn heap_size(&self) -> usize {
+        match self {
+            Self::Null | Self::Bool(_) | Self::Number(_) => 0,
+            Self::String(string) => arcstr_heap_size(string),
+            Self::Array(items) => {
+                ARC_HEADER_SIZE
+                    + mem::size_of_val(&**items)
+                    + items.iter().map(Value::heap_size).sum::<usize>()
+            }
+            Self::Object(fields) => ARC_HEADER_SIZE + fields_heap_size(fields),
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    /// Looks up a dotted key path relative to this value.
+    ///
+    /// Returns `None` if this value is not an object or an array.
+    pub fn get_path(&self, path: &str) -> Option<&Value> {
+        match self {
+            Self::Object(fields) => get_path(fields, path),
+            Self::Array(items) => {
+                let (first, rest) = match path.split_once('.') {
+                    Some((first, rest)) => (first, Some(rest)),
+                    None => (path, None),
+                };
+                let item = items.get(first.parse::<usize>().ok()?)?;
+                match rest {
+                    Some(rest) => item.get_path(rest),
+                    None => Some(item),
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    /// Formats the value as compact JSON.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => write!(f, "null"),
+            Self::Bool(bool) => write!(f, "{bool}"),
+            Self::Number(number) => write!(f, "{number}"),
+            Self::String(string) => write!(f, "{string:?}"),
+            Self::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, "]")
+            }
+            Self::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{key:?}:{value}")?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// A JSON number.
+///
+/// Non-negative integers are always represented as `UInt`.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Deserialize, serde::Serialize,
+)]
+pub enum Number {
+    UInt(u64),
+    Int(i64),
+    Float(Float),
+}
+
+impl Number {
+    pub fn from_i64(value: i64) -> Self {
+        match u64::try_from(value) {
+            Ok(value) => Self::UInt(value),
+            Err(_) => Self::Int(value),
+        }
+    }
+
+    pub fn as_f64(self) -> f64 {
+        match self {
+            Self::UInt(value) => value as f64,
+            Self::Int(value) => value as f64,
+            Self::Float(value) => value.0,
+        }
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UInt(value) => write!(f, "{value}"),
+            Self::Int(value) => write!(f, "{value}"),
+            Self::Float(value) => write!(f, "{}", value.0),
+        }
+    }
+}
+
+/// An `f64` with total ordering, so that it can be used in hashed and sorted conditions.
+#[derive(Debug, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub struct Float(pub f64);
+
+impl PartialEq for Float {
+    fn eq(&self, other: &Self) -> bool { self.cmp(other) == cmp::Ordering::Equal }
+}
+
+impl Eq for Float {}
+
+impl PartialOrd for Float {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> { Some(self.cmp(other)) }
+}
+
+impl Ord for Float {
+    fn cmp(&self, other: &Self) -> cmp::Ordering { self.0.total_cmp(&other.0) }
+}
+
+impl Hash for Float {
+    fn hash<H: Hasher>(&self, state: &mut H) { self.0.to_bits().hash(state) }
+}
+
+/// Looks up a dotted key path in a list of fields sorted by key.
+///
+/// A key that literally contains dots takes precedence over a nested lookup.
+pub(crate) fn get_path<'t>(fields: &'t [(ArcStr, Value)], path: &str) -> Option<&'t Value> {
+    if let Ok(position) = fields.binary_search_by(|(key, _)| key.as_str().cmp(path)) {
+        return Some(&fields[position].1);
+    }
+
+    for (dot, _) in path.match_indices('.') {
+        let (prefix, rest) = (&path[..dot], &path[dot + 1..]);
+        if let Ok(position) = fields.binary_search_by(|(key, _)| key.as_str().cmp(prefix)) {
+            if let Some(value) = fields[position].1.get_path(rest) {
+                return Some(value);
+            }
+        }
+    }
+
+    None
+}
+
+/// The reference counts stored before the data of an `Arc` allocation.
+const ARC_HEADER_SIZE: usize = 2 * mem::size_of::<usize>();
+
+/// Estimates the heap memory owned by a slice of fields, excluding the allocation header.
+pub(crate) fn fields_heap_size(fields: &[(ArcStr, Value)]) -> usize {
+    mem::size_of_val(fields)
+        + fields.iter().map(|(key, value)| arcstr_heap_size(key) + value.heap_size()).sum::<usize>()
+}
+
+fn arcstr_heap_size(string: &ArcStr) -> usize {
+    if ArcStr::strong_count(string).is_none_or(|count| count > 1) {
+        0 // static or shared
+    } else {
+        ARC_HEADER_SIZE + string.len()
+    }
+}
@@ -0,0 +1,514 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::{env, fs, io};
+
+use slv_proto::{JsonEntry, Value};
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+use unicode_width::{UnicodeWidthChar as _, UnicodeWidthStr as _};
+
+use crate::list::{display_value, level_color};
+
+const TIME_KEYS: &[&str] = &["time", "ts", "timestamp", "@timestamp"];
+const LEVEL_KEYS: &[&str] = &["level", "lvl", "severity"];
+const MESSAGE_KEYS: &[&str] = &["msg", "message"];
+
+/// The width of the source column, whose values are truncated at the start like file paths.
+const SOURCE_WIDTH: u16 = 20;
+
+const MIN_WIDTH: u16 = 1;
+const MAX_WIDTH: u16 = 200;
+
+/// The column layout of the list view, persisted per input format.
+pub struct Columns {
+    /// The path of the config file, or `None` if layouts cannot be persisted.
+    path:           Option<PathBuf>,
+    config:         Config,
+    /// The format of the input, detected from the first JSON entry.
+    format:         Option<Format>,
+    layout:         Layout,
+    /// The selected column if the column editor is open.
+    editing:        Option<usize>,
+    /// The leading column with the source label of each entry, if shown.
+    ///
+    /// Not saved with the layout, since it depends on the inputs of the session.
+    source:         Option<Column>,
+    /// Whether `source` was shown automatically or toggled by the user.
+    source_decided: bool,
+}
+
+impl Columns {
+    /// Loads the saved layouts from the user config directory.
+    pub fn load() -> Self {
+        let mut path = config_path();
+        let config = match path.as_deref().map(fs::read) {
+            Some(Ok(bytes)) => match serde_json::from_slice(&bytes) {
+                Ok(config) => config,
+                Err(err) => {
+                    // do not overwrite a file that the user may want to fix manually
+                    log::warn!("Cannot parse column config, layouts will not be saved: {err}");
+                    path = None;
+                    Config::default()
+                }
+            },
+            Some(Err(err)) if err.kind() == io::ErrorKind::NotFound => Config::default(),
+            Some(Err(err)) => {
+                log::warn!("Cannot read column config, layouts will not be saved: {err}");
+                path = None;
+                Config::default()
+            }
+            None => Config::default(),
+        };
+
+        Self {
+            path,
+            config,
+            format: None,
+            layout: Layout::default(),
+            editing: None,
+            source: None,
+            source_decided: false,
+        }
+    }
+
+    pub fn format(&self) -> Option<Format> { self.format }
+
+    /// Detects the input format from a JSON entry and switches to its layout.
+    ///
+    /// Does nothing if the format was already detected.
+    pub fn detect(&mut self, json: &JsonEntry) {
+        if self.format.is_some() {
+            return;
+        }
+
+        let format = Format::detect(json);
+        self.format = Some(format);
+        self.layout = match self.config.layouts.get(format.name()) {
+            Some(layout) => layout.clone(),
+            None => format.default_layout(json),
+        };
+    }
+
+    /// Shows the source column once the buffer has more than one input,
+    /// unless it was already shown or toggled.
+    pub fn detect_sources(&mut self, source_count: usize) {
+        if !self.source_decided && source_count > 1 {
+            self.source = Some(Column::source());
+            self.source_decided = true;
+        }
+    }
+
+    /// Shows or hides the source column.
+    pub fn toggle_source(&mut self) {
+        self.source = match self.source {
+            Some(_) => None,
+            None => Some(Column::source()),
+        };
+        self.source_decided = true;
+    }
+
+    /// Saves the current layout for the detected format.
+    pub fn save(&mut self) -> Result<(), ConfigError> {
+        let (path, format) = match (&self.path, self.format) {
+            (Some(path), Some(format)) => (path, format),
+            _ => return Ok(()),
+        };
+
+        self.config.layouts.insert(format.name().to_string(), self.layout.clone());
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(ConfigError::Write)?;
+        }
+        let bytes = serde_json::to_vec_pretty(&self.config).map_err(ConfigError::Serialize)?;
+        fs::write(path, bytes).map_err(ConfigError::Write)
+    }
+
+    /// Adds a column for `key`, or removes it if it is already a column.
+    ///
+    /// Returns whether the column was added.
+    pub fn toggle_key(&mut self, key: &str) -> bool {
+        match self.layout.columns.iter().position(|column| column.key == key) {
+            Some(position) => {
+                self.layout.columns.remove(position);
+                self.clamp_editing();
+                false
+            }
+            None => {
+                self.layout.columns.push(Column::new(key.to_string()));
+                true
+            }
+        }
+    }
+
+    pub fn editing(&self) -> Option<usize> { self.editing }
+
+    pub fn start_editing(&mut self) { self.editing = Some(0); }
+
+    pub fn stop_editing(&mut self) { self.editing = None; }
+
+    /// The column selected in the editor.
+    pub fn selected(&self) -> Option<&Column> { self.layout.columns.get(self.editing?) }
+
+    fn selected_mut(&mut self) -> Option<&mut Column> { self.layout.columns.get_mut(self.editing?) }
+
+    pub fn move_selection(&mut self, delta: isize) {
+        if let Some(selected) = &mut self.editing {
+            let last = self.layout.columns.len().saturating_sub(1) as isize;
+            *selected = (*selected as isize + delta).clamp(0, last) as usize;
+        }
+    }
+
+    /// Swaps the selected column with its neighbour.
+    pub fn move_column(&mut self, delta: isize) {
+        let selected = match self.editing {
+            Some(selected) if selected < self.layout.columns.len() => selected,
+            _ => return,
+        };
+        let last = self.layout.columns.len() - 1;
+        let target = (selected as isize + delta).clamp(0, last as isize) as usize;
+        self.layout.columns.swap(selected, target);
+        self.editing = Some(target);
+    }
+
+    pub fn resize(&mut self, delta: i16) {
+        if let Some(column) = self.selected_mut() {
+            column.width = column.width.saturating_add_signed(delta).clamp(MIN_WIDTH, MAX_WIDTH);
+        }
+    }
+
+    pub fn toggle_align(&mut self) {
+        if let Some(column) = self.selected_mut() {
+            column.align = match column.align {
+                Align::Left => Align::Right,
+                Align::Right => Align::Left,
+            };
+        }
+    }
+
+    pub fn cycle_truncate(&mut self) {
+        if let Some(column) = self.selected_mut() {
+            column.truncate = match column.truncate {
+                Truncate::End => Truncate::Start,
+                Truncate::Start => Truncate::Clip,
+                Truncate::Clip => Truncate::End,
+            };
+        }
+    }
+
+    pub fn remove_selected(&mut self) {
+        if let Some(selected) = self.editing {
+            if selected < self.layout.columns.len() {
+                self.layout.columns.remove(selected);
+                self.clamp_editing();
+            }
+        }
+    }
+
+    pub fn toggle_extra(&mut self) { self.layout.extra = !self.layout.extra; }
+
+    fn clamp_editing(&mut self) {
+        if let Some(selected) = &mut self.editing {
+            *selected = (*selected).min(self.layout.columns.len().saturating_sub(1));
+        }
+    }
+
+    /// The header line of the list, or `None` if there are no columns to label.
+    pub fn header(&self) -> Option<Spans<'static>> {
+        if self.layout.columns.is_empty() && self.source.is_none() {
+            return None;
+        }
+
+        let mut spans = Vec::new();
+        if let Some(source) = &self.source {
+            let style = Style::default().add_modifier(Modifier::BOLD);
+            spans.push(Span::styled(source.fit(&source.key), style));
+            spans.push(Span::raw(" "));
+        }
+        for (i, column) in self.layout.columns.iter().enumerate() {
+            let mut style = Style::default().add_modifier(Modifier::BOLD);
+            if self.editing == Some(i) {
+                style = style.add_modifier(Modifier::REVERSED);
+            }
+            spans.push(Span::styled(column.fit(&column.key), style));
+            spans.push(Span::raw(" "));
+        }
+        if self.layout.extra {
+            spans.push(Span::styled("fields", Style::default().add_modifier(Modifier::BOLD)));
+        }
+        Some(Spans::from(spans))
+    }
+
+    /// Formats the source column of an entry, if it is shown.
+    pub fn source_cell(&self, source: &str) -> Vec<Span<'static>> {
+        match &self.source {
+            Some(column) => vec![
+                Span::styled(column.fit(source), Style::default().fg(Color::Magenta)),
+                Span::raw(" "),
+            ],
+            None => Vec::new(),
+        }
+    }
+
+    /// Formats a JSON entry as a row of the table, excluding the source column.
+    pub fn row(&self, json: &JsonEntry) -> Spans<'static> {
+        let mut spans = Vec::new();
+
+        for column in &self.layout.columns {
+            let text = json.get_path(&column.key).map(display_value).unwrap_or_default();
+            let key = column.key.as_str();
+            let (text, style) = if LEVEL_KEYS.contains(&key) {
+                let style = Style::default().fg(level_color(&text)).add_modifier(Modifier::BOLD);
+                (text.to_uppercase(), style)
+            } else if TIME_KEYS.contains(&key) {
+                (text, Style::default().fg(Color::DarkGray))
+            } else {
+                (text, Style::default())
+            };
+            spans.push(Span::styled(column.fit(&text), style));
+            spans.push(Span::raw(" "));
+        }
+
+        if self.layout.extra {
+            for (key, value) in &json.0 {
+                if self.layout.columns.iter().any(|column| column.key == key.as_str()) {
+                    continue;
+                }
+                spans.push(Span::styled(format!("{key}="), Style::default().fg(Color::Cyan)));
+                spans.push(Span::raw(display_value(value)));
+                spans.push(Span::raw(" "));
+            }
+        }
+
+        Spans::from(spans)
+    }
+}
+
+/// Returns the path of the column config file under the user config directory.
+fn config_path() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("slv").join("columns.json"))
+}
+
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct Config {
+    /// Layouts keyed by `Format::name`.
+    #[serde(default)]
+    layouts: BTreeMap<String, Layout>,
+}
+
+/// The columns shown for JSON entries.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Layout {
+    pub columns: Vec<Column>,
+    /// Whether to show fields that are not columns in a trailing column.
+    pub extra:   bool,
+}
+
+impl Default for Layout {
+    fn default() -> Self { Self { columns: Vec::new(), extra: true } }
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Column {
+    /// The dotted key path of the field.
+    pub key:      String,
+    pub width:    u16,
+    #[serde(default)]
+    pub align:    Align,
+    #[serde(default)]
+    pub truncate: Truncate,
+}
+
+impl Column {
+    fn new(key: String) -> Self {
+        Self { key, width: 16, align: Align::Left, truncate: Truncate::End }
+    }
+
+    fn source() -> Self {
+        Self {
+            key:      String::from("source"),
+            width:    SOURCE_WIDTH,
+            align:    Align::Left,
+            truncate: Truncate::Start,
+        }
+    }
+
+    fn with_width(mut self, width: u16) -> Self {
+        self.width = width;
+        self
+    }
+
+    /// Pads or truncates `text` to exactly the width of the column.
+    fn fit(&self, text: &str) -> String {
+        let width = usize::from(self.width);
+
+        let mut fitted = if text.width() <= width {
+            text.to_string()
+        } else {
+            match self.truncate {
+                Truncate::End => {
+                    let mut fitted = take_width(text.chars(), width.saturating_sub(1));
+                    fitted.push('…');
+                    fitted
+                }
+                Truncate::Start => {
+                    let tail = take_width(text.chars().rev(), width.saturating_sub(1));
+                    let mut fitted = String::from("…");
+                    fitted.extend(tail.chars().rev());
+                    fitted
+                }
+                Truncate::Clip => take_width(text.chars(), width),
+            }
+        };
+
+        let padding = " ".repeat(width.saturating_sub(fitted.width()));
+        match self.align {
+            Align::Left => fitted.push_str(&padding),
+            Align::Right => fitted.insert_str(0, &padding),
+        }
+        fitted
+    }
+}
+
+/// Collects characters until the total display width would exceed `width`.
+fn take_width(chars: impl Iterator<Item = char>, width: usize) -> String {
+    let mut taken = String::new();
+    let mut taken_width = 0;
+    for ch in chars {
+        taken_width += ch.width().unwrap_or(0);
+        if taken_width > width {
+            break;
+        }
+        taken.push(ch);
+    }
+    taken
+}
+
+#[derive(Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Align {
+    #[default]
+    Left,
+    Right,
+}
+
+/// How to shorten values wider than the column.
+#[derive(Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Truncate {
+    /// Keep the start of the value and end with an ellipsis.
+    #[default]
+    End,
+    /// Keep the end of the value and start with an ellipsis, e.g. for file paths.
+    Start,
+    /// Cut off the value without an ellipsis.
+    Clip,
+}
+
+/// A well-known structured log format.
+#[derive(Clone, Copy)]
+pub enum Format {
+    /// node-bunyan
+    Bunyan,
+    /// pino
+    Pino,
+    /// go.uber.org/zap
+    Zap,
+    /// github.com/sirupsen/logrus
+    Logrus,
+    /// Any other JSON format.
+    Generic,
+}
+
+impl Format {
+    fn detect(json: &JsonEntry) -> Self {
+        let has = |key: &str| json.0.iter().any(|(k, _)| k.as_str() == key);
+        let numeric_level = matches!(json.get_path("level"), Some(Value::Number(_)));
+
+        if has("v") && has("name") && has("hostname") && has("pid") && numeric_level {
+            Self::Bunyan
+        } else if has("hostname") && has("pid") && has("msg") && numeric_level {
+            Self::Pino
+        } else if has("ts") && has("level") && has("msg") {
+            Self::Zap
+        } else if has("time") && has("level") && has("msg") {
+            Self::Logrus
+        } else {
+            Self::Generic
+        }
+    }
+
+    /// The key under which layouts for this format are saved.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Bunyan => "bunyan",
+            Self::Pino => "pino",
+            Self::Zap => "zap",
+            Self::Logrus => "logrus",
+            Self::Generic => "generic",
+        }
+    }
+
+    fn default_layout(self, json: &JsonEntry) -> Layout {
+        let columns = match self {
+            Self::Bunyan => vec![
+                Column::new("time".into()).with_width(24),
+                Column::new("level".into()).with_width(5),
+                Column::new("name".into()).with_width(12),
+                Column::new("msg".into()).with_width(60),
+            ],
+            Self::Pino => vec![
+                Column::new("time".into()).with_width(13),
+                Column::new("level".into()).with_width(5),
+                Column::new("msg".into()).with_width(60),
+            ],
+            Self::Zap => {
+                let mut columns = vec![
+                    Column::new("ts".into()).with_width(18),
+                    Column::new("level".into()).with_width(5),
+                ];
+                if json.get_path("caller").is_some() {
+                    let mut caller = Column::new("caller".into()).with_width(20);
+                    caller.truncate = Truncate::Start;
+                    columns.push(caller);
+                }
+                columns.push(Column::new("msg".into()).with_width(60));
+                columns
+            }
+            Self::Logrus => vec![
+                Column::new("time".into()).with_width(25),
+                Column::new("level".into()).with_width(5),
+                Column::new("msg".into()).with_width(60),
+            ],
+            Self::Generic => {
+                let find = |keys: &[&'static str]| {
+                    keys.iter().find(|&&key| json.get_path(key).is_some()).copied()
+                };
+                let mut columns = Vec::new();
+                if let Some(key) = find(TIME_KEYS) {
+                    columns.push(Column::new(key.into()).with_width(25));
+                }
+                if let Some(key) = find(LEVEL_KEYS) {
+                    columns.push(Column::new(key.into()).with_width(5));
+                }
+                if let Some(key) = find(MESSAGE_KEYS) {
+                    columns.push(Column::new(key.into()).with_width(60));
+                }
+                columns
+            }
+        };
+
+        Layout { columns, extra: true }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Cannot serialize column config: {0}")]
+    Serialize(serde_json::Error),
+    #[error("Cannot write column config: {0}")]
+    Write(io::Error),
+}
@@ -0,0 +1,218 @@
+use std::fmt::Write as _;
+
+use slv_proto::{Entry, FieldCondition, MessageId, Value};
+use tui::backend::Backend;
+use tui::layout::Rect;
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets;
+
+use crate::list;
+
+/// Shows all fields of the selected entry.
+pub struct DetailView {
+    id:       MessageId,
+    /// The label of the input the entry was read from.
+    source:   String,
+    content:  Content,
+    selected: usize,
+    state:    widgets::ListState,
+}
+
+enum Content {
+    Json(Vec<Row>),
+    Raw(String),
+}
+
+/// A line in the field tree of a JSON entry.
+struct Row {
+    depth: usize,
+    /// The dotted key path of the field.
+    path:  String,
+    label: String,
+    value: Value,
+}
+
+impl DetailView {
+    pub fn new(id: MessageId, entry: &Entry) -> Self {
+        let content = match &entry.content {
+            slv_proto::Content::Json(json) => {
+                let mut rows = Vec::new();
+                for (key, value) in &json.0 {
+                    push_rows(&mut rows, 0, key.to_string(), key.to_string(), value);
+                }
+                Content::Json(rows)
+            }
+            slv_proto::Content::Raw(raw) => Content::Raw(escape_bytes(&raw.0)),
+        };
+
+        Self {
+            id,
+            source: entry.source.to_string(),
+            content,
+            selected: 0,
+            state: widgets::ListState::default(),
+        }
+    }
+
+    pub fn id(&self) -> MessageId { self.id }
+
+    pub fn move_selection(&mut self, delta: isize) {
+        if let Content::Json(rows) = &self.content {
+            let last = rows.len().saturating_sub(1) as isize;
+            self.selected = (self.selected as isize + delta).clamp(0, last) as usize;
+        }
+    }
+
+    /// The text to copy for the selected field.
+    pub fn selected_text(&self) -> String {
+        match &self.content {
+            Content::Json(rows) => match rows.get(self.selected) {
+                Some(row) => match &row.value {
+                    Value::String(string) => string.to_string(),
+                    value => value.to_string(),
+                },
+                None => String::new(),
+            },
+            Content::Raw(text) => text.clone(),
+        }
+    }
+
+    /// The dotted key path of the selected field.
+    ///
+    /// Returns `None` for raw entries, which do not have fields.
+    pub fn selected_path(&self) -> Option<&str> {
+        match &self.content {
+            Content::Json(rows) => Some(rows.get(self.selected)?.path.as_str()),
+            Content::Raw(_) => None,
+        }
+    }
+
+    /// A filter condition matching the value of the selected field.
+    ///
+    /// Returns `None` for raw entries, which do not have fields.
+    pub fn selected_condition(&self, has_key_only: bool) -> Option<FieldCondition> {
+        let row = match &self.content {
+            Content::Json(rows) => rows.get(self.selected)?,
+            Content::Raw(_) => return None,
+        };
+
+        let key = row.path.as_str().into();
+        Some(if has_key_only {
+            FieldCondition::HasKey(key)
+        } else {
+            FieldCondition::KeyValue(key, row.value.clone())
+        })
+    }
+
+    /// A filter condition matching entries from the same source.
+    ///
+    /// Returns `None` for raw entries, which filters do not match.
+    pub fn source_condition(&self) -> Option<FieldCondition> {
+        match &self.content {
+            Content::Json(_) => Some(FieldCondition::Source(self.source.as_str().into())),
+            Content::Raw(_) => None,
+        }
+    }
+
+    pub fn render(&mut self, f: &mut tui::Frame<impl Backend>, area: Rect, focused: bool) {
+        let title = format!(" Entry #{} | source: {} ", self.id.0, self.source);
+        let border_style =
+            if focused { Style::default().fg(Color::Yellow) } else { Style::default() };
+        let block = widgets::Block::default()
+            .borders(widgets::Borders::ALL)
+            .border_style(border_style)
+            .title(title);
+
+        match &self.content {
+            Content::Json(rows) => {
+                let items: Vec<_> =
+                    rows.iter().map(|row| widgets::ListItem::new(row.spans())).collect();
+                let list = widgets::List::new(items).block(block).highlight_style(
+                    Style::default().add_modifier(if focused {
+                        Modifier::REVERSED
+                    } else {
+                        Modifier::UNDERLINED
+                    }),
+                );
+                self.state.select(Some(self.selected));
+                f.render_stateful_widget(list, area, &mut self.state);
+            }
+            Content::Raw(text) => {
+                let paragraph = widgets::Paragraph::new(text.as_str())
+                    .block(block)
+                    .wrap(widgets::Wrap { trim: false });
+                f.render_widget(paragraph, area);
+            }
+        }
+    }
+}
+
+fn push_rows(rows: &mut Vec<Row>, depth: usize, path: String, label: String, value: &Value) {
+    rows.push(Row { depth, path: path.clone(), label, value: value.clone() });
+
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields.iter() {
+                push_rows(rows, depth + 1, format!("{path}.{key}"), key.to_string(), value);
+            }
+        }
+        Value::Array(items) => {
+            for (i, value) in items.iter().enumerate() {
+                push_rows(rows, depth + 1, format!("{path}.{i}"), format!("[{i}]"), value);
+            }
+        }
+        _ => {}
+    }
+}
+
+impl Row {
+    fn spans(&self) -> Spans<'static> {
+        let mut spans = vec![
+            Span::raw("  ".repeat(self.depth)),
+            Span::styled(self.label.clone(), Style::default().fg(Color::Cyan)),
+            Span::raw(": "),
+        ];
+
+        match &self.value {
+            Value::Object(fields) => spans.push(Span::styled(
+                format!("{{{} fields}}", fields.len()),
+                Style::default().fg(Color::DarkGray),
+            )),
+            Value::Array(items) => spans.push(Span::styled(
+                format!("[{} items]", items.len()),
+                Style::default().fg(Color::DarkGray),
+            )),
+            value => spans.push(highlight(value)),
+        }
+
+        Spans::from(spans)
+    }
+}
+
+/// Formats a scalar value with syntax highlighting.
+fn highlight(value: &Value) -> Span<'static> {
+    let color = match value {
+        Value::String(_) => Color::Green,
+        Value::Number(_) => Color::Yellow,
+        Value::Bool(_) => Color::Magenta,
+        _ => Color::DarkGray,
+    };
+    let text = match value {
+        Value::String(string) => format!("{:?}", string.as_str()),
+        value => list::display_value(value),
+    };
+    Span::styled(text, Style::default().fg(color))
+}
+
+/// Displays bytes as text, escaping invalid UTF-8 sequences as `\xNN`.
+fn escape_bytes(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        text.push_str(chunk.valid());
+        for byte in chunk.invalid() {
+            write!(text, "\\x{byte:02x}").expect("String::write_fmt never fails");
+        }
+    }
+    text
+}
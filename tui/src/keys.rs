@@ -0,0 +1,155 @@
+use slv_proto::{server, FieldCondition, IndexMethod};
+use tui::backend::Backend;
+use tui::layout::Rect;
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets;
+
+use crate::list;
+
+/// Number of most frequent values requested for each key.
+pub const TOP_VALUES: usize = 10;
+/// Keys with at most this many distinct values are listed with their values.
+const MAX_FACET_VALUES: u64 = 32;
+
+/// Lists the keys in the buffer with their most frequent values,
+/// so that the user can pick a filter, which is indexed on the server.
+///
+/// Keys with few distinct values come first, since they make the most useful filters.
+pub struct KeysView {
+    /// The number of JSON messages and the rows, or `None` while loading.
+    content:  Option<(u64, Vec<Row>)>,
+    selected: usize,
+    state:    widgets::ListState,
+}
+
+struct Row {
+    /// `HasKey` for a key or `KeyValue` for one of its values.
+    condition: FieldCondition,
+    /// Number of occurrences of the key or the value.
+    count:     u64,
+    /// The number of distinct values and whether it is exact, for key rows.
+    distinct:  Option<(u64, bool)>,
+}
+
+impl KeysView {
+    pub fn new() -> Self {
+        Self { content: None, selected: 0, state: widgets::ListState::default() }
+    }
+
+    /// Replaces the rows with the latest statistics.
+    pub fn apply(&mut self, stats: server::KeyStats) {
+        let (mut facets, mut others): (Vec<_>, Vec<_>) = stats
+            .keys
+            .into_iter()
+            .partition(|key| key.distinct_exact && (1..=MAX_FACET_VALUES).contains(&key.distinct));
+        facets.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.key.cmp(&b.key)));
+        others.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.key.cmp(&b.key)));
+
+        let mut rows = Vec::new();
+        for key in facets {
+            rows.push(Row {
+                condition: FieldCondition::HasKey(key.key.clone()),
+                count:     key.count,
+                distinct:  Some((key.distinct, key.distinct_exact)),
+            });
+            for (value, count) in key.top_values {
+                rows.push(Row {
+                    condition: FieldCondition::KeyValue(key.key.clone(), value),
+                    count,
+                    distinct: None,
+                });
+            }
+        }
+        for key in others {
+            rows.push(Row {
+                condition: FieldCondition::HasKey(key.key),
+                count:     key.count,
+                distinct:  Some((key.distinct, key.distinct_exact)),
+            });
+        }
+
+        self.selected = self.selected.min(rows.len().saturating_sub(1));
+        self.content = Some((stats.messages, rows));
+    }
+
+    pub fn move_selection(&mut self, delta: isize) {
+        if let Some((_, rows)) = &self.content {
+            let last = rows.len().saturating_sub(1) as isize;
+            self.selected = (self.selected as isize + delta).clamp(0, last) as usize;
+        }
+    }
+
+    /// A filter condition matching the selected key or value.
+    pub fn selected_condition(&self) -> Option<&FieldCondition> {
+        let (_, rows) = self.content.as_ref()?;
+        Some(&rows.get(self.selected)?.condition)
+    }
+
+    /// Renders the view, marking the rows whose conditions are already indexed.
+    pub fn render(
+        &mut self,
+        f: &mut tui::Frame<impl Backend>,
+        area: Rect,
+        indices: &[IndexMethod],
+    ) {
+        let (messages, rows) = match &self.content {
+            Some((messages, rows)) => (*messages, rows),
+            None => {
+                let block =
+                    widgets::Block::default().borders(widgets::Borders::ALL).title(" Keys ");
+                f.render_widget(widgets::Paragraph::new("loading...").block(block), area);
+                return;
+            }
+        };
+
+        let title = format!(" Keys | {messages} JSON messages in the buffer ");
+        let block = widgets::Block::default().borders(widgets::Borders::ALL).title(title);
+        let items: Vec<_> = rows
+            .iter()
+            .map(|row| {
+                let method = IndexMethod::new(row.condition.clone().into());
+                widgets::ListItem::new(row.spans(messages, indices.contains(&method)))
+            })
+            .collect();
+        let list = widgets::List::new(items)
+            .block(block)
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        self.state.select((!rows.is_empty()).then_some(self.selected));
+        f.render_stateful_widget(list, area, &mut self.state);
+    }
+}
+
+impl Row {
+    fn spans(&self, messages: u64, indexed: bool) -> Spans<'static> {
+        let share = format!("{:5.1}%", self.count as f64 * 100.0 / messages.max(1) as f64);
+        let mut spans = match &self.condition {
+            FieldCondition::KeyValue(_, value) => vec![
+                Span::raw("    "),
+                Span::styled(
+                    format!("{:<36}", list::display_value(value)),
+                    Style::default().fg(Color::Green),
+                ),
+            ],
+            condition => vec![Span::styled(
+                format!("{:<40}", condition.key().unwrap_or_default()),
+                Style::default().fg(Color::Cyan),
+            )],
+        };
+        spans.push(Span::raw(format!(" {share} {:>9}", self.count)));
+
+        // keys with only object values have no distinct values
+        if let Some((distinct @ 1.., exact)) = self.distinct {
+            let approx = if exact { "" } else { "~" };
+            spans.push(Span::styled(
+                format!("  {approx}{distinct} values"),
+                Style::default().fg(Color::DarkGray),
+            ));
+        }
+        if indexed {
+            spans.push(Span::styled("  indexed", Style::default().fg(Color::Yellow)));
+        }
+
+        Spans::from(spans)
+    }
+}
@@ -1,26 +1,40 @@
 use std::future::Future;
 use std::io::{self, Write};
+use std::iter;
+use std::path::PathBuf;
 use std::sync::Arc;
 
-use crossterm::event::{Event, KeyCode, KeyModifiers};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
 use crossterm::terminal::{self, disable_raw_mode, enable_raw_mode};
 use crossterm::QueueableCommand as _;
 use futures::channel::mpsc;
 use futures::StreamExt;
 use slv_input::index;
+use slv_proto::query::Query;
+use slv_proto::{Content, IndexMethod};
 use tokio::sync::broadcast;
 use tui::backend::{Backend, CrosstermBackend};
 use tui::{layout, widgets, Terminal};
 
+mod columns;
+mod detail;
+mod keys;
+mod list;
+mod prompt;
+
+/// The default path to save snapshots to from the interactive UI.
+const SNAPSHOT_PATH: &str = "slv-snapshot.slv";
+
 type State = slv_client::State<mpsc::UnboundedSender<slv_proto::client::Message>>;
 
 pub async fn init(
+    options: Options,
     index: Arc<index::Store>,
     shutdown_tx: broadcast::Sender<()>,
     shutdown_rx: broadcast::Receiver<()>,
 ) -> Result<impl Future<Output = ()>, InitError> {
     let run = async move {
-        if let Err(err) = start_tui(index, shutdown_tx, shutdown_rx).await {
+        if let Err(err) = start_tui(options, index, shutdown_tx, shutdown_rx).await {
             eprintln!("Error: {err}");
         }
     };
@@ -80,6 +94,7 @@ impl Drop for ResetTerminalGuard {
 }
 
 async fn start_tui(
+    options: Options,
     index: Arc<index::Store>,
     shutdown_tx: broadcast::Sender<()>,
     mut shutdown_rx: broadcast::Receiver<()>,
@@ -95,6 +110,7 @@ async fn start_tui(
     let state = Arc::new(slv_client::State::new(client_tx));
 
     tokio::spawn({
+        let index = Arc::clone(&index);
         async move {
             slv_input::session::handle(session_rx, session_tx, &index).await;
         }
@@ -111,28 +127,452 @@ async fn start_tui(
 
     let mut term_events = crossterm::event::EventStream::new();
 
+    let (loaded_tx, mut loaded_rx) = mpsc::unbounded();
+    let (saved_tx, mut saved_rx) = mpsc::unbounded();
+    let (key_stats_tx, mut key_stats_rx) = mpsc::unbounded();
+    let filter = options.filter.unwrap_or_default();
+    let mut app = App {
+        list: list::ListView::new(filter.index_ref()),
+        columns: columns::Columns::load(),
+        detail: None,
+        detail_focused: false,
+        keys: None,
+        filter,
+        prompt: None,
+        message: None,
+    };
+    app.list.jump_newest();
+
+    // subscribing creates the index of the filter on the server
+    let (mut subscription, mut appended_rx) = state
+        .subscribe(app.filter.index_ref(), false)
+        .await
+        .map_err(|_| RunError::SessionClosed)?;
+
     loop {
-        terminal.draw(|f| ui(f, &state)).map_err(RunError::Draw)?;
+        for fetch in app.list.take_requests() {
+            spawn_fetch(&state, fetch, loaded_tx.clone());
+        }
+
+        terminal.draw(|f| ui(f, &state, &mut app)).map_err(RunError::Draw)?;
 
-        tokio::select! {
+        let action = tokio::select! {
             _ = shutdown_rx.recv() => break,
+            message = saved_rx.next() => {
+                app.message = message;
+                Action::None
+            }
+            stats = key_stats_rx.next() => {
+                let stats = stats.expect("key_stats_tx is owned by this function");
+                if let Some(keys) = &mut app.keys {
+                    keys.apply(stats);
+                }
+                Action::None
+            }
+            loaded = loaded_rx.next() => {
+                app.list.apply(loaded.expect("loaded_tx is owned by this function"));
+                app.detect_format();
+                Action::None
+            }
+            event = appended_rx.next() => {
+                let event = match event {
+                    Some(event) => event,
+                    None => break, // client worker stopped
+                };
+
+                // redraw once for all events already received
+                let events = iter::once(event)
+                    .chain(iter::from_fn(|| appended_rx.try_next().ok().flatten()));
+                for event in events {
+                    if let slv_client::SubscriptionEvent::Appended(entries) = event {
+                        app.list.on_appended(entries.into_iter().map(|(id, _)| id));
+                    }
+                }
+                Action::None
+            }
             event = term_events.next() => {
                 match event {
                     None => break,
-                    Some(Ok(event)) => handle_event(event, &shutdown_tx),
+                    Some(Ok(event)) => app.handle_event(event),
                     Some(Err(err)) => {
                         eprintln!("{err}");
-                        _ = shutdown_tx.send(());
+                        Action::Quit
                     },
                 }
             }
+        };
+
+        match action {
+            Action::None => {}
+            Action::Quit => _ = shutdown_tx.send(()),
+            Action::Copy(text) => {
+                copy_to_clipboard(terminal.backend_mut(), &text).map_err(RunError::Draw)?;
+                app.message = Some(String::from("Copied to clipboard"));
+            }
+            Action::Save(path) => {
+                app.message = Some(format!("Saving snapshot to {}...", path.display()));
+                let index = Arc::clone(&index);
+                let saved_tx = saved_tx.clone();
+                tokio::task::spawn_blocking(move || {
+                    let message = match index.save(&path) {
+                        Ok(len) => format!("Saved {len} messages to {}", path.display()),
+                        Err(err) => err.to_string(),
+                    };
+                    _ = saved_tx.unbounded_send(message); // the TUI may have exited
+                });
+            }
+            Action::FetchKeyStats => {
+                let state = Arc::clone(&state);
+                let key_stats_tx = key_stats_tx.clone();
+                tokio::spawn(async move {
+                    match state.key_stats(keys::TOP_VALUES).await {
+                        Ok(stats) => _ = key_stats_tx.unbounded_send(stats), // the TUI may have exited
+                        Err(err) => log::error!("Cannot fetch key statistics: {err}"),
+                    }
+                });
+            }
+            Action::CreateIndex(method) => {
+                _ = state.create_index(method).await; // the session is checked by subscribe()
+            }
+            Action::UpdateFilter => {
+                let index = app.filter.index_ref();
+                // subscribing creates the index on the server
+                let (new_subscription, new_appended_rx) = state
+                    .subscribe(index.clone(), false)
+                    .await
+                    .map_err(|_| RunError::SessionClosed)?;
+                _ = state.unsubscribe(subscription).await; // checked by subscribe() above
+                subscription = new_subscription;
+                appended_rx = new_appended_rx;
+
+                app.list = list::ListView::new(index);
+                app.list.jump_newest();
+            }
         }
     }
 
     Ok(())
 }
 
-fn ui(f: &mut tui::Frame<impl Backend>, state: &State) {
+fn spawn_fetch(
+    state: &Arc<State>,
+    fetch: list::Fetch,
+    loaded_tx: mpsc::UnboundedSender<list::Loaded>,
+) {
+    let state = Arc::clone(state);
+    tokio::spawn(async move {
+        let response = state
+            .fetch_entries(fetch.index.clone(), fetch.anchor, fetch.direction, fetch.limit)
+            .await;
+        match response {
+            Ok(response) => {
+                // the TUI may have exited
+                _ = loaded_tx.unbounded_send(fetch.loaded(response.entries, response.next));
+            }
+            Err(err) => log::error!("Cannot fetch entries: {err}"),
+        }
+    });
+}
+
+/// Copies text to the system clipboard through the OSC 52 escape sequence,
+/// which also works over SSH in supporting terminals.
+fn copy_to_clipboard(stdout: &mut impl Write, text: &str) -> io::Result<()> {
+    write!(stdout, "\x1b]52;c;{}\x07", base64::encode(text))?;
+    stdout.flush()
+}
+
+/// The state of the interactive UI.
+struct App {
+    list:           list::ListView,
+    columns:        columns::Columns,
+    /// The detail pane, if it is open.
+    detail:         Option<detail::DetailView>,
+    detail_focused: bool,
+    /// The key statistics view, if it is open.
+    keys:           Option<keys::KeysView>,
+    /// The query that entries in the list must match.
+    filter:         Query,
+    /// The open prompt and what its input is for.
+    prompt:         Option<(PromptKind, prompt::Prompt)>,
+    /// A message shown in the status line until the next key press.
+    message:        Option<String>,
+}
+
+enum PromptKind {
+    Filter,
+    /// The path to save a snapshot to.
+    Save,
+}
+
+/// An action requested by a key press that cannot be performed within `App`.
+enum Action {
+    None,
+    Quit,
+    Copy(String),
+    /// `App::filter` was changed.
+    UpdateFilter,
+    /// Save a snapshot of the store to the path.
+    Save(PathBuf),
+    /// Request the key statistics shown in `App::keys`.
+    FetchKeyStats,
+    CreateIndex(IndexMethod),
+}
+
+impl App {
+    /// Detects the input format from the loaded entries to select the column layout.
+    fn detect_format(&mut self) {
+        if self.columns.format().is_some() {
+            return;
+        }
+
+        let json = self.list.rows().find_map(|(_, entry)| match &entry.content {
+            Content::Json(json) => Some(json),
+            Content::Raw(_) => None,
+        });
+        if let Some(json) = json {
+            self.columns.detect(json);
+        }
+    }
+
+    fn save_columns(&mut self) {
+        if let Err(err) = self.columns.save() {
+            self.message = Some(err.to_string());
+        }
+    }
+
+    fn handle_event(&mut self, event: Event) -> Action {
+        let event = match event {
+            Event::Key(event) => event,
+            Event::Paste(text) => {
+                if let Some((_, prompt)) = &mut self.prompt {
+                    prompt.insert(&text);
+                }
+                // otherwise do nothing, paste is most likely an accident
+                return Action::None;
+            }
+            _ => return Action::None,
+        };
+
+        if event.modifiers.contains(KeyModifiers::CONTROL) && event.code == KeyCode::Char('c') {
+            log::debug!("ctrl-c received from crossterm");
+            return Action::Quit;
+        }
+
+        self.message = None;
+
+        if self.prompt.is_some() {
+            return self.handle_prompt_key(event);
+        }
+        if self.columns.editing().is_some() {
+            self.handle_columns_key(event.code);
+            return Action::None;
+        }
+        if self.keys.is_some() {
+            return self.handle_keys_key(event.code);
+        }
+
+        match event.code {
+            KeyCode::Char('q') => return Action::Quit,
+            KeyCode::Enter => {
+                if self.detail.take().is_none() {
+                    self.detail =
+                        self.list.selected().map(|(id, entry)| detail::DetailView::new(*id, entry));
+                    self.detail_focused = true;
+                } else {
+                    self.detail_focused = false;
+                }
+            }
+            KeyCode::Tab if self.detail.is_some() => self.detail_focused = !self.detail_focused,
+            KeyCode::Esc if self.detail_focused => self.detail_focused = false,
+            KeyCode::Char('x') if !self.filter.is_empty() => {
+                self.filter = Query::default();
+                return Action::UpdateFilter;
+            }
+            KeyCode::Char('/') => {
+                let prompt = prompt::Prompt::new("filter", self.filter.to_string());
+                self.prompt = Some((PromptKind::Filter, prompt));
+            }
+            KeyCode::Char('S') => {
+                let prompt = prompt::Prompt::new("save snapshot to", String::from(SNAPSHOT_PATH));
+                self.prompt = Some((PromptKind::Save, prompt));
+            }
+            KeyCode::Char('K') => {
+                self.keys = Some(keys::KeysView::new());
+                return Action::FetchKeyStats;
+            }
+            _ => {
+                return match &mut self.detail {
+                    Some(detail) if self.detail_focused => {
+                        let action = Self::handle_detail_key(
+                            detail,
+                            event.code,
+                            &mut self.filter,
+                            &mut self.columns,
+                            &mut self.message,
+                        );
+                        if event.code == KeyCode::Char('c') {
+                            self.save_columns();
+                        }
+                        action
+                    }
+                    _ => {
+                        self.handle_list_key(event.code);
+                        Action::None
+                    }
+                };
+            }
+        }
+
+        Action::None
+    }
+
+    fn handle_list_key(&mut self, code: KeyCode) {
+        let list = &mut self.list;
+        match code {
+            KeyCode::Char('j') | KeyCode::Down => list.move_selection(1),
+            KeyCode::Char('k') | KeyCode::Up => list.move_selection(-1),
+            KeyCode::PageDown => list.move_selection(list.page_size()),
+            KeyCode::PageUp => list.move_selection(-list.page_size()),
+            KeyCode::Char('g') | KeyCode::Home => list.jump_oldest(),
+            KeyCode::Char('G') | KeyCode::End => list.jump_newest(),
+            KeyCode::Char('f') => list.toggle_follow(),
+            KeyCode::Char('C') => self.columns.start_editing(),
+            _ => {}
+        }
+    }
+
+    fn handle_prompt_key(&mut self, event: KeyEvent) -> Action {
+        let (kind, prompt) = self.prompt.as_mut().expect("checked by caller");
+        match prompt.handle_key(event) {
+            prompt::PromptEvent::None => {}
+            prompt::PromptEvent::Cancel => self.prompt = None,
+            prompt::PromptEvent::Submit => match kind {
+                PromptKind::Filter => match prompt.input().parse::<Query>() {
+                    Ok(query) => {
+                        self.prompt = None;
+                        if query != self.filter {
+                            self.filter = query;
+                            return Action::UpdateFilter;
+                        }
+                    }
+                    Err(err) => prompt.set_error(err.kind.to_string(), err.column),
+                },
+                PromptKind::Save => {
+                    if prompt.input().is_empty() {
+                        prompt.set_error(String::from("missing file path"), 0);
+                    } else {
+                        let path = PathBuf::from(prompt.input());
+                        self.prompt = None;
+                        return Action::Save(path);
+                    }
+                }
+            },
+        }
+        Action::None
+    }
+
+    fn handle_columns_key(&mut self, code: KeyCode) {
+        let columns = &mut self.columns;
+        match code {
+            KeyCode::Esc | KeyCode::Enter | KeyCode::Char('C') => {
+                columns.stop_editing();
+                return;
+            }
+            KeyCode::Char('h') | KeyCode::Left => columns.move_selection(-1),
+            KeyCode::Char('l') | KeyCode::Right => columns.move_selection(1),
+            KeyCode::Char('H') => columns.move_column(-1),
+            KeyCode::Char('L') => columns.move_column(1),
+            KeyCode::Char('-') => columns.resize(-1),
+            KeyCode::Char('+') | KeyCode::Char('=') => columns.resize(1),
+            KeyCode::Char('a') => columns.toggle_align(),
+            KeyCode::Char('t') => columns.cycle_truncate(),
+            KeyCode::Char('d') | KeyCode::Delete => columns.remove_selected(),
+            KeyCode::Char('e') => columns.toggle_extra(),
+            KeyCode::Char('s') => columns.toggle_source(),
+            _ => return,
+        }
+        self.save_columns();
+    }
+
+    fn handle_keys_key(&mut self, code: KeyCode) -> Action {
+        let keys = self.keys.as_mut().expect("checked by caller");
+        match code {
+            KeyCode::Esc | KeyCode::Char('K') => self.keys = None,
+            KeyCode::Char('j') | KeyCode::Down => keys.move_selection(1),
+            KeyCode::Char('k') | KeyCode::Up => keys.move_selection(-1),
+            KeyCode::PageDown => keys.move_selection(10),
+            KeyCode::PageUp => keys.move_selection(-10),
+            KeyCode::Char('r') => return Action::FetchKeyStats,
+            KeyCode::Enter => {
+                if let Some(condition) = keys.selected_condition() {
+                    let condition = self.filter.condition.clone().and(condition.clone().into());
+                    self.keys = None;
+                    if condition != self.filter.condition {
+                        self.filter.condition = condition;
+                        return Action::UpdateFilter;
+                    }
+                }
+            }
+            KeyCode::Char('i') => {
+                if let Some(condition) = keys.selected_condition() {
+                    self.message = Some(format!("Creating index {condition}"));
+                    return Action::CreateIndex(IndexMethod::new(condition.clone().into()));
+                }
+            }
+            _ => {}
+        }
+        Action::None
+    }
+
+    fn handle_detail_key(
+        detail: &mut detail::DetailView,
+        code: KeyCode,
+        filter: &mut Query,
+        columns: &mut columns::Columns,
+        message: &mut Option<String>,
+    ) -> Action {
+        match code {
+            KeyCode::Char('j') | KeyCode::Down => detail.move_selection(1),
+            KeyCode::Char('k') | KeyCode::Up => detail.move_selection(-1),
+            KeyCode::Char('y') => return Action::Copy(detail.selected_text()),
+            KeyCode::Char('=') | KeyCode::Char('h') => {
+                match detail.selected_condition(code == KeyCode::Char('h')) {
+                    Some(condition) => {
+                        let condition = filter.condition.clone().and(condition.into());
+                        if condition != filter.condition {
+                            filter.condition = condition;
+                            return Action::UpdateFilter;
+                        }
+                    }
+                    None => *message = Some(String::from("Raw entries have no fields to filter")),
+                }
+            }
+            KeyCode::Char('c') => match detail.selected_path() {
+                Some(path) => {
+                    let added = columns.toggle_key(path);
+                    let verb = if added { "Added" } else { "Removed" };
+                    *message = Some(format!("{verb} column {path}"));
+                }
+                None => *message = Some(String::from("Raw entries have no fields to show")),
+            },
+            KeyCode::Char('s') => match detail.source_condition() {
+                Some(condition) => {
+                    let condition = filter.condition.clone().and(condition.into());
+                    if condition != filter.condition {
+                        filter.condition = condition;
+                        return Action::UpdateFilter;
+                    }
+                }
+                None => *message = Some(String::from("Filters only match JSON entries")),
+            },
+            _ => {}
+        }
+
+        Action::None
+    }
+}
+
+fn ui(f: &mut tui::Frame<impl Backend>, state: &State, app: &mut App) {
     let [main_chunk, status_chunk]: [_; 2] = layout::Layout::default()
         .direction(layout::Direction::Vertical)
         .margin(1)
@@ -141,23 +581,79 @@ fn ui(f: &mut tui::Frame<impl Backend>, state: &State) {
         .try_into()
         .expect("constraints.len()");
 
-    f.render_widget(widgets::Paragraph::new("slv"), main_chunk);
-    f.render_widget(widgets::Paragraph::new(state.status_line()), status_chunk)
-}
+    app.columns.detect_sources(state.source_count());
 
-fn handle_event(event: Event, shutdown_tx: &broadcast::Sender<()>) {
-    match event {
-        Event::Key(event) => {
-            if event.modifiers.contains(KeyModifiers::CONTROL) && event.code == KeyCode::Char('c') {
-                log::debug!("ctrl-c received from crossterm");
-                _ = shutdown_tx.send(());
+    match (&mut app.keys, &mut app.detail) {
+        (Some(keys), _) => keys.render(f, main_chunk, &state.key_list()),
+        (None, Some(detail)) => {
+            let [list_chunk, detail_chunk]: [_; 2] = layout::Layout::default()
+                .direction(layout::Direction::Vertical)
+                .constraints([
+                    layout::Constraint::Percentage(60),
+                    layout::Constraint::Percentage(40),
+                ])
+                .split(main_chunk)
+                .try_into()
+                .expect("constraints.len()");
+
+            // the detail pane follows the selection in the list
+            if let Some((id, entry)) = app.list.selected() {
+                if *id != detail.id() {
+                    *detail = detail::DetailView::new(*id, entry);
+                }
             }
+
+            app.list.render(f, list_chunk, &app.columns);
+            detail.render(f, detail_chunk, app.detail_focused);
         }
-        Event::Paste(_) => {
-            // do nothing, paste is most likely an accident since there is no input
-        }
-        _ => {}
+        (None, None) => app.list.render(f, main_chunk, &app.columns),
+    }
+
+    if let Some((_, prompt)) = &app.prompt {
+        prompt.render(f, status_chunk);
+        return;
+    }
+
+    let list = &app.list;
+    let mut status = state.status_line();
+    if !app.filter.is_empty() {
+        status.push_str(&format!(" | filter: {}", app.filter));
+    }
+    if let Some((id, _)) = list.selected() {
+        status.push_str(&format!(" | #{}", id.0));
+    }
+    if list.is_following() {
+        status.push_str(" | FOLLOW");
+    } else if list.unseen() > 0 {
+        status.push_str(&format!(" | +{} new", list.unseen()));
     }
+    if list.is_loading() {
+        status.push_str(" | loading...");
+    }
+    if let Some(column) = app.columns.selected() {
+        let format = app.columns.format().map_or("unknown", |format| format.name());
+        status.push_str(&format!(
+            " | {format} column {}: width {}, {:?}, truncate {:?} (h/l H/L -/+ a t d e s Esc)",
+            column.key, column.width, column.align, column.truncate,
+        ));
+    }
+    if app.keys.is_some() {
+        status.push_str(" | keys (j/k, Enter filter, i index, r refresh, Esc)");
+    }
+    if let Some(message) = &app.message {
+        status.push_str(&format!(" | {message}"));
+    }
+    f.render_widget(widgets::Paragraph::new(status), status_chunk)
+}
+
+#[derive(clap::Parser)]
+pub struct Options {
+    /// Only show entries matching this query, e.g. `level=error has:trace_id`.
+    ///
+    /// The filter can be changed in the interactive UI by pressing `/`.
+    /// Not allowed without the interactive UI.
+    #[clap(long, value_parser)]
+    pub filter: Option<Query>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -171,4 +667,6 @@ pub enum RunError {
     Configure(io::Error),
     #[error("Cannot draw terminal: {0}")]
     Draw(io::Error),
+    #[error("Client session closed unexpectedly")]
+    SessionClosed,
 }
@@ -0,0 +1,432 @@
+use std::cmp;
+use std::collections::VecDeque;
+
+use slv_proto::{Anchor, Content, Direction, Entry, IndexRef, MessageId, Value};
+use tui::backend::Backend;
+use tui::layout::{self, Rect};
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets;
+
+use crate::columns::Columns;
+
+/// Number of entries requested in each fetch.
+const FETCH_SIZE: usize = 256;
+/// Maximum number of entries kept in memory by the view.
+const MAX_ROWS: usize = FETCH_SIZE * 8;
+/// Fetch more entries when the selection is this close to the end of the loaded rows.
+const PREFETCH_MARGIN: usize = FETCH_SIZE / 2;
+
+/// A scrollable list of entries in an index.
+///
+/// Only a window of the index around the selection is loaded,
+/// so the view stays responsive regardless of the index size.
+pub struct ListView {
+    index:          IndexRef,
+    /// A contiguous window of the index in ascending order of ID.
+    rows:           VecDeque<(MessageId, Entry)>,
+    /// Whether the index may contain entries before `rows`.
+    has_before:     bool,
+    /// Whether the index may contain entries after `rows`.
+    has_after:      bool,
+    selected:       usize,
+    offset:         usize,
+    height:         usize,
+    /// Incremented when the rows are reset, to discard responses of obsolete fetches.
+    generation:     u64,
+    loading_reset:  bool,
+    loading_before: bool,
+    loading_after:  bool,
+    /// Selection movement that cannot be applied until more rows are loaded.
+    pending_move:   isize,
+    requests:       Vec<Fetch>,
+    /// Whether the selection is pinned to the newest entry.
+    following:      bool,
+    /// Number of entries appended to the index since follow mode was left.
+    unseen:         usize,
+    /// The newest ID known to be in the index.
+    latest:         Option<MessageId>,
+}
+
+impl ListView {
+    pub fn new(index: IndexRef) -> Self {
+        Self {
+            index,
+            rows: VecDeque::new(),
+            has_before: false,
+            has_after: false,
+            selected: 0,
+            offset: 0,
+            height: 1,
+            generation: 0,
+            loading_reset: false,
+            loading_before: false,
+            loading_after: false,
+            pending_move: 0,
+            requests: Vec::new(),
+            following: true,
+            unseen: 0,
+            latest: None,
+        }
+    }
+
+    /// Takes the fetches requested by the view since the last call.
+    pub fn take_requests(&mut self) -> Vec<Fetch> { std::mem::take(&mut self.requests) }
+
+    pub fn selected(&self) -> Option<&(MessageId, Entry)> { self.rows.get(self.selected) }
+
+    /// The loaded entries in ascending order of ID.
+    pub fn rows(&self) -> impl Iterator<Item = &(MessageId, Entry)> { self.rows.iter() }
+
+    pub fn is_loading(&self) -> bool {
+        self.loading_reset || self.loading_before || self.loading_after
+    }
+
+    pub fn is_following(&self) -> bool { self.following }
+
+    /// Number of entries appended to the index since follow mode was left.
+    pub fn unseen(&self) -> usize { self.unseen }
+
+    /// Toggles follow mode, jumping to the newest entry if it is enabled.
+    pub fn toggle_follow(&mut self) {
+        if self.following {
+            self.following = false;
+        } else {
+            self.following = true;
+            self.unseen = 0;
+            self.jump_newest();
+        }
+    }
+
+    /// Notifies the view that entries were appended to the index.
+    pub fn on_appended(&mut self, ids: impl IntoIterator<Item = MessageId>) {
+        let mut count = 0;
+        for id in ids {
+            self.latest = cmp::max(self.latest, Some(id));
+            count += 1;
+        }
+        if count == 0 {
+            return;
+        }
+
+        if !self.following {
+            self.unseen += count;
+            self.has_after = true;
+        } else if self.rows.is_empty() {
+            if !self.loading_reset {
+                self.jump_newest();
+            }
+        } else {
+            self.has_after = true;
+            self.prefetch();
+        }
+    }
+
+    /// Moves the selection to the oldest entry in the index.
+    pub fn jump_oldest(&mut self) {
+        self.following = false;
+        self.reset(Anchor::Oldest, Direction::Forward);
+    }
+
+    /// Moves the selection to the newest entry in the index.
+    pub fn jump_newest(&mut self) { self.reset(Anchor::Newest, Direction::Backward); }
+
+    fn reset(&mut self, anchor: Anchor, direction: Direction) {
+        self.generation += 1;
+        self.loading_reset = true;
+        self.loading_before = false;
+        self.loading_after = false;
+        self.pending_move = 0;
+
+        let kind = match direction {
+            Direction::Forward => LoadKind::ResetOldest,
+            Direction::Backward => LoadKind::ResetNewest,
+        };
+        self.request(kind, anchor, direction);
+    }
+
+    /// Moves the selection by `delta` rows, loading more rows if necessary.
+    pub fn move_selection(&mut self, delta: isize) {
+        if self.rows.is_empty() {
+            return;
+        }
+        if delta < 0 {
+            self.following = false;
+        }
+
+        let last = self.rows.len() - 1;
+        let target = self.selected as isize + delta;
+        if target < 0 {
+            self.selected = 0;
+            self.pending_move = if self.has_before { target } else { 0 };
+        } else if target as usize > last {
+            self.selected = last;
+            // always try to load more, since new entries may have been pushed
+            self.pending_move = target - last as isize;
+        } else {
+            self.selected = target as usize;
+            self.pending_move = 0;
+        }
+
+        self.prefetch();
+    }
+
+    pub fn page_size(&self) -> isize { cmp::max(self.height, 1) as isize }
+
+    fn prefetch(&mut self) {
+        if !self.loading_before
+            && self.has_before
+            && (self.selected < PREFETCH_MARGIN || self.pending_move < 0)
+        {
+            if let Some(&(front, _)) = self.rows.front() {
+                if let Some(before) = front.0.checked_sub(1) {
+                    self.loading_before = true;
+                    self.request(
+                        LoadKind::Before,
+                        Anchor::Id(MessageId(before)),
+                        Direction::Backward,
+                    );
+                }
+            }
+        }
+
+        if !self.loading_after
+            && (self.has_after || self.pending_move > 0)
+            && self.selected + PREFETCH_MARGIN >= self.rows.len()
+        {
+            if let Some(&(back, _)) = self.rows.back() {
+                self.loading_after = true;
+                self.request(
+                    LoadKind::After,
+                    Anchor::Id(MessageId(back.0 + 1)),
+                    Direction::Forward,
+                );
+            }
+        }
+    }
+
+    fn request(&mut self, kind: LoadKind, anchor: Anchor, direction: Direction) {
+        self.requests.push(Fetch {
+            generation: self.generation,
+            kind,
+            index: self.index.clone(),
+            anchor,
+            direction,
+            limit: FETCH_SIZE,
+        });
+    }
+
+    /// Applies the response of a fetch requested by this view.
+    pub fn apply(&mut self, loaded: Loaded) {
+        if loaded.generation != self.generation {
+            return;
+        }
+
+        match loaded.kind {
+            LoadKind::ResetOldest | LoadKind::ResetNewest => {
+                self.loading_reset = false;
+                self.rows = loaded.entries.into();
+                self.offset = 0;
+                if let LoadKind::ResetOldest = loaded.kind {
+                    self.has_before = false;
+                    self.has_after = loaded.next.is_some();
+                    self.selected = 0;
+                } else {
+                    self.has_before = loaded.next.is_some();
+                    self.has_after = self.is_behind(None);
+                    self.selected = self.rows.len().saturating_sub(1);
+                }
+            }
+            LoadKind::After => {
+                self.loading_after = false;
+                self.has_after = self.is_behind(loaded.next);
+
+                let back = self.rows.back().map(|&(id, _)| id);
+                let appended = loaded.entries.into_iter().filter(|&(id, _)| Some(id) > back);
+                let len = self.rows.len();
+                self.rows.extend(appended);
+                let appended = self.rows.len() - len;
+
+                if self.pending_move > 0 {
+                    let step = cmp::min(self.pending_move as usize, appended);
+                    self.selected += step;
+                    self.pending_move =
+                        if appended == 0 { 0 } else { self.pending_move - step as isize };
+                }
+                if self.following {
+                    self.selected = self.rows.len().saturating_sub(1);
+                }
+
+                self.trim_front();
+            }
+            LoadKind::Before => {
+                self.loading_before = false;
+                self.has_before = loaded.next.is_some();
+
+                let front = self.rows.front().map(|&(id, _)| id);
+                let mut prepended = 0;
+                for entry in loaded.entries.into_iter().rev() {
+                    if front.is_none_or(|front| entry.0 < front) {
+                        self.rows.push_front(entry);
+                        prepended += 1;
+                    }
+                }
+                self.selected += prepended;
+                self.offset += prepended;
+
+                if self.pending_move < 0 {
+                    let step = cmp::min(self.pending_move.unsigned_abs(), prepended);
+                    self.selected -= step;
+                    self.pending_move =
+                        if prepended == 0 { 0 } else { self.pending_move + step as isize };
+                }
+
+                self.trim_back();
+            }
+        }
+
+        self.prefetch();
+    }
+
+    /// Whether the index may contain entries after the loaded rows,
+    /// given the `next` anchor of the latest forward fetch.
+    fn is_behind(&self, next: Option<MessageId>) -> bool {
+        // entries may have been appended while the fetch was in flight
+        next.is_some() || self.latest > self.rows.back().map(|&(id, _)| id)
+    }
+
+    fn trim_front(&mut self) {
+        let excess = self.rows.len().saturating_sub(MAX_ROWS);
+        let excess = cmp::min(excess, self.selected.saturating_sub(PREFETCH_MARGIN));
+        if excess > 0 {
+            self.rows.drain(..excess);
+            self.selected -= excess;
+            self.offset = self.offset.saturating_sub(excess);
+            self.has_before = true;
+        }
+    }
+
+    fn trim_back(&mut self) {
+        let excess = self.rows.len().saturating_sub(MAX_ROWS);
+        let keep = self.selected + PREFETCH_MARGIN + 1;
+        let excess = cmp::min(excess, self.rows.len().saturating_sub(keep));
+        if excess > 0 {
+            self.rows.truncate(self.rows.len() - excess);
+            self.has_after = true;
+        }
+    }
+
+    pub fn render(&mut self, f: &mut tui::Frame<impl Backend>, area: Rect, columns: &Columns) {
+        let area = match columns.header() {
+            Some(header) => {
+                let [header_chunk, rows_chunk]: [_; 2] = layout::Layout::default()
+                    .direction(layout::Direction::Vertical)
+                    .constraints([layout::Constraint::Length(1), layout::Constraint::Min(0)])
+                    .split(area)
+                    .try_into()
+                    .expect("constraints.len()");
+                f.render_widget(widgets::Paragraph::new(header), header_chunk);
+                rows_chunk
+            }
+            None => area,
+        };
+
+        self.height = usize::from(area.height);
+
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if self.selected >= self.offset + self.height {
+            self.offset = self.selected + 1 - self.height;
+        }
+        // fill the screen if the rows are scrolled past the end
+        self.offset = cmp::min(self.offset, self.rows.len().saturating_sub(self.height));
+
+        let end = cmp::min(self.offset + self.height, self.rows.len());
+        let items: Vec<_> = self
+            .rows
+            .range(self.offset..end)
+            .map(|(_, entry)| widgets::ListItem::new(summarize(entry, columns)))
+            .collect();
+
+        let list = widgets::List::new(items)
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut list_state = widgets::ListState::default();
+        if !self.rows.is_empty() {
+            list_state.select(Some(self.selected - self.offset));
+        }
+        f.render_stateful_widget(list, area, &mut list_state);
+    }
+}
+
+/// A fetch requested by a `ListView`.
+pub struct Fetch {
+    pub generation: u64,
+    pub kind:       LoadKind,
+    pub index:      IndexRef,
+    pub anchor:     Anchor,
+    pub direction:  Direction,
+    pub limit:      usize,
+}
+
+impl Fetch {
+    pub fn loaded(self, entries: Vec<(MessageId, Entry)>, next: Option<MessageId>) -> Loaded {
+        Loaded { generation: self.generation, kind: self.kind, entries, next }
+    }
+}
+
+/// The response of a `Fetch`.
+pub struct Loaded {
+    generation: u64,
+    kind:       LoadKind,
+    entries:    Vec<(MessageId, Entry)>,
+    next:       Option<MessageId>,
+}
+
+#[derive(Clone, Copy)]
+pub enum LoadKind {
+    ResetOldest,
+    ResetNewest,
+    Before,
+    After,
+}
+
+/// Summarizes an entry in one line.
+fn summarize(entry: &Entry, columns: &Columns) -> Spans<'static> {
+    let mut spans = columns.source_cell(&entry.source);
+    match &entry.content {
+        Content::Raw(raw) => spans.push(Span::raw(escape_line(&String::from_utf8_lossy(&raw.0)))),
+        Content::Json(json) => spans.extend(columns.row(json).0),
+    }
+    Spans::from(spans)
+}
+
+/// Displays a value in one line, without quotes for strings.
+pub fn display_value(value: &Value) -> String {
+    match value {
+        Value::String(string) => escape_line(string),
+        value => value.to_string(),
+    }
+}
+
+/// Escapes control characters so that the string occupies exactly one line.
+pub fn escape_line(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len());
+    for ch in string.chars() {
+        if ch.is_control() {
+            escaped.extend(ch.escape_default());
+        } else {
+            escaped.push(ch);
+        }
+    }
+    escaped
+}
+
+pub fn level_color(level: &str) -> Color {
+    match level.to_lowercase().as_str() {
+        "fatal" | "panic" | "error" | "err" | "critical" => Color::Red,
+        "warn" | "warning" => Color::Yellow,
+        "info" => Color::Green,
+        "debug" | "trace" => Color::Blue,
+        _ => Color::Reset,
+    }
+}
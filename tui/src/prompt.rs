@@ -0,0 +1,123 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use tui::backend::Backend;
+use tui::layout::Rect;
+use tui::style::{Color, Style};
+use tui::text::{Span, Spans};
+use tui::widgets;
+use unicode_width::UnicodeWidthStr as _;
+
+/// A single-line text input shown in place of the status line.
+pub struct Prompt {
+    label:  &'static str,
+    input:  String,
+    /// The cursor position in characters.
+    cursor: usize,
+    /// An error message about the input and the character position it refers to.
+    error:  Option<(String, usize)>,
+}
+
+pub enum PromptEvent {
+    None,
+    Submit,
+    Cancel,
+}
+
+impl Prompt {
+    pub fn new(label: &'static str, input: String) -> Self {
+        let cursor = input.chars().count();
+        Self { label, input, cursor, error: None }
+    }
+
+    pub fn input(&self) -> &str { &self.input }
+
+    /// Shows an error until the input is edited.
+    pub fn set_error(&mut self, message: String, column: usize) {
+        self.error = Some((message, column));
+    }
+
+    fn byte_offset(&self, cursor: usize) -> usize {
+        self.input.char_indices().nth(cursor).map_or(self.input.len(), |(offset, _)| offset)
+    }
+
+    pub fn insert(&mut self, text: &str) {
+        let offset = self.byte_offset(self.cursor);
+        // newlines would break the single-line layout
+        let text: String = text.chars().filter(|ch| !ch.is_control()).collect();
+        self.input.insert_str(offset, &text);
+        self.cursor += text.chars().count();
+        self.error = None;
+    }
+
+    /// Deletes the characters between two cursor positions.
+    fn delete(&mut self, start: usize, end: usize) {
+        let range = self.byte_offset(start)..self.byte_offset(end);
+        self.input.replace_range(range, "");
+        self.cursor = start;
+        self.error = None;
+    }
+
+    pub fn handle_key(&mut self, event: KeyEvent) -> PromptEvent {
+        let len = self.input.chars().count();
+
+        if event.modifiers.contains(KeyModifiers::CONTROL) {
+            match event.code {
+                KeyCode::Char('a') => self.cursor = 0,
+                KeyCode::Char('e') => self.cursor = len,
+                KeyCode::Char('u') => self.delete(0, self.cursor),
+                KeyCode::Char('w') => {
+                    let chars: Vec<_> = self.input.chars().take(self.cursor).collect();
+                    let trimmed = chars.iter().rposition(|ch| !ch.is_whitespace());
+                    let start = trimmed
+                        .and_then(|end| chars[..end].iter().rposition(|ch| ch.is_whitespace()))
+                        .map_or(0, |space| space + 1);
+                    self.delete(start, self.cursor);
+                }
+                _ => {}
+            }
+            return PromptEvent::None;
+        }
+
+        match event.code {
+            KeyCode::Enter => return PromptEvent::Submit,
+            KeyCode::Esc => return PromptEvent::Cancel,
+            KeyCode::Char(ch) => self.insert(ch.encode_utf8(&mut [0; 4])),
+            KeyCode::Backspace if self.cursor > 0 => self.delete(self.cursor - 1, self.cursor),
+            KeyCode::Delete if self.cursor < len => self.delete(self.cursor, self.cursor + 1),
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(len),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = len,
+            _ => {}
+        }
+        PromptEvent::None
+    }
+
+    pub fn render(&self, f: &mut tui::Frame<impl Backend>, area: Rect) {
+        let label = format!("{}: ", self.label);
+        let mut spans = vec![Span::styled(label.clone(), Style::default().fg(Color::Cyan))];
+
+        match &self.error {
+            Some((message, column)) => {
+                // highlight the character at the error position
+                let start = self.byte_offset(*column);
+                let end = self.byte_offset(column + 1);
+                let error_style = Style::default().fg(Color::White).bg(Color::Red);
+                spans.push(Span::raw(self.input[..start].to_string()));
+                if start == end {
+                    spans.push(Span::styled(" ", error_style));
+                } else {
+                    spans.push(Span::styled(self.input[start..end].to_string(), error_style));
+                    spans.push(Span::raw(self.input[end..].to_string()));
+                }
+                spans.push(Span::styled(format!("  {message}"), Style::default().fg(Color::Red)));
+            }
+            None => spans.push(Span::raw(self.input.clone())),
+        }
+
+        f.render_widget(widgets::Paragraph::new(Spans::from(spans)), area);
+
+        let before_cursor = &self.input[..self.byte_offset(self.cursor)];
+        let x = area.x as usize + label.width() + before_cursor.width();
+        f.set_cursor((x as u16).min(area.right().saturating_sub(1)), area.y);
+    }
+}